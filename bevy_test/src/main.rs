@@ -1,17 +1,20 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use bevy::asset::{AssetIo, AssetIoError, AssetPlugin, BoxedFuture, FileAssetIo};
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
+use bevy::render::RenderStage;
 use bevy::tasks::IoTaskPool;
-use bevy_kira_audio::AudioChannel;
+use bevy_kira_audio::{AudioChannel, InstanceHandle, PlaybackState};
+use engine::ResourceProvider;
 
 fn is_game_directory(path: impl AsRef<Path>) -> bool {
     let path = path.as_ref();
     let is_game_dir = path.exists()
         && path.is_dir()
-        && path.join("Scripts").exists();
+        && (path.join(engine::MANIFEST_FILE).exists() || path.join("Scripts").exists());
     if !is_game_dir {
         println!("Path '{}' is not a valid game directory", path.display());
     }
@@ -19,65 +22,166 @@ fn is_game_directory(path: impl AsRef<Path>) -> bool {
 }
 
 fn get_game_directory() -> Option<PathBuf> {
-    std::env::args_os().nth(1).map(PathBuf::from)
+    std::env::args_os()
+        .skip(1)
+        .find(|arg| !arg.to_string_lossy().starts_with("--"))
+        .map(PathBuf::from)
         .filter(|d| is_game_directory(d))
         .or_else(|| std::env::current_dir().ok())
         .filter(|d| is_game_directory(d))
 }
 
+fn get_layout_direction() -> engine::LayoutDirection {
+    std::env::args()
+        .find_map(|arg| arg.strip_prefix("--direction=").map(str::to_string))
+        .and_then(|d| d.parse().map_err(|e| println!("{}", e)).ok())
+        .unwrap_or_default()
+}
+
+const SAVES_DIRECTORY: &str = "saves";
+const MODS_DIRECTORY: &str = "mods";
+// Scenes the timeline (T) goes back to.
+const TIMELINE_SCENES: usize = 50;
+
 fn main() {
     let directory = get_game_directory()
         .unwrap_or_else(|| r"C:\mkd\Downloads\Kanon".into());
     println!("Loading game files from '{}'", directory.display());
 
+    let direction = get_layout_direction();
+    let line_len = match direction {
+        engine::LayoutDirection::Vertical => 4,
+        _ => 60,
+    };
+    let script_key = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--script-key=").map(str::to_string))
+        .map(|key| engine::XorKey::from_hex(&key).ok_or(format!("invalid script key: {}", key)))
+        .transpose()
+        .and_then(|key| match key {
+            Some(key) => Ok(Some(key)),
+            None => engine::GameManifest::load(&directory).script_key(),
+        })
+        .unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        });
+    let archive_key = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--archive-key=").map(str::to_string))
+        .map(|key| leg_archive::ArchiveKey::parse(&key).ok_or(format!("invalid archive key: {}", key)))
+        .transpose()
+        .and_then(|key| match key {
+            Some(key) => Ok(Some(key)),
+            None => engine::GameManifest::load(&directory).archive_key(),
+        })
+        .unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        });
+    let script_options = engine::ScriptOptions {
+        vnds_compat: std::env::args().any(|arg| arg == "--vnds"),
+        decryptor: script_key.map(|key| std::sync::Arc::new(key) as _),
+        ..Default::default()
+    };
+
+    let mut engine = engine::EngineState::with_options(&directory, script_options);
+    if let Err(e) = engine.open_persistent_store("global.sav") {
+        println!("Persistent data not loaded: {}", e);
+    }
+    engine.set_skip_seen_only(!std::env::args().any(|arg| arg == "--skip-unread"));
+    engine.set_streamer_safe(std::env::args().any(|arg| arg == "--streamer-safe"));
+    engine.set_quicksave_file(Some("quick.sav".into()));
+    engine.set_checkpoint_limit(TIMELINE_SCENES);
+    engine.set_autosave(Some(engine::Autosave {
+        at_chapters: true,
+        ..engine::Autosave::new(engine::SaveManager::new(SAVES_DIRECTORY))
+    }));
+    // The text box fits three lines of horizontal text.
+    if direction != engine::LayoutDirection::Vertical {
+        engine.set_pagination(Some(engine::Pagination { line_len, lines: 3, direction }));
+    }
+    let language = std::env::args().find_map(|arg| arg.strip_prefix("--language=").map(str::to_string));
+    if let Err(e) = engine.set_language(language.as_deref()) {
+        println!("Language not switched: {}", e);
+    }
+    if let Some(file) = std::env::args().find_map(|arg| arg.strip_prefix("--translation=").map(str::to_string)) {
+        if let Err(e) = engine.load_translation(file) {
+            println!("Translation not loaded: {}", e);
+        }
+    }
+
+    let title = engine.manifest().title.clone().unwrap_or_else(|| "Madenon".to_string());
+    let (width, height) = engine.manifest().resolution.unwrap_or((725, 544));
     App::new()
         .insert_resource(WindowDescriptor {
-            title: "Madenon".to_string(),
-            width: 725.,
-            height: 544.,
+            title,
+            width: width as f32,
+            height: height as f32,
             vsync: true,
             resizable: false,
             ..Default::default()
         })
         .insert_resource(GameState {
-            engine: engine::EngineState::new(&directory),
+            engine,
+            saves: engine::SaveManager::new(SAVES_DIRECTORY).with_global_data("global.sav"),
             view: ViewState::JustStarted,
             sound_channel: AudioChannel::new("sound".to_string()),
             music_channel: AudioChannel::new("music".to_string()),
+            voice_channel: AudioChannel::new("voice".to_string()),
+            language,
             steps_after_save_load: VecDeque::new(),
             background_image: Handle::default(),
-            date_image: Handle::default(),
-            main_image: Handle::default(),
+            images: HashMap::new(),
+            pending_title: None,
+            prefetched: vec![],
+            voice: None,
+            music: None,
+        })
+        .insert_resource(TextLayoutSettings {
+            direction,
+            line_len,
         })
         .insert_resource(ClearColor(Color::WHITE))
         .add_plugins_with(DefaultPlugins, |group| {
-            group.add_after::<AssetPlugin, _>(LegAssetPlugin(
-                directory.join("SEArchive.legArchive")))
+            group.add_after::<AssetPlugin, _>(LegAssetPlugin(directory.clone(), archive_key.clone()))
         })
         .add_plugin(bevy_kira_audio::AudioPlugin)
+        .stage(RenderStage::Render, |stage: &mut SystemStage| stage.set_run_criteria(frame_pacing.system()))
         .add_startup_system(setup.system())
         .add_startup_system_to_stage(StartupStage::PostStartup, scripting_system.system())
         .add_system(keyboard_input_system.system())
         .add_system(typing_system.system())
         .add_system(image_presenting_system.system())
         .add_system(choice_system.system())
+        .add_system(chapter_card_system.system())
+        .add_system(auto_system.system())
         .run();
 }
 
 struct BackgroundImage;
 
-struct ForegroundImage;
-
-struct DateImage;
+// Sprite showing the image of the image slot with this name.
+struct ImageLayer(String);
 
 struct TypingTimer(Timer);
 
 struct GameText;
 
+// Shows lines with inline gaiji images in place of `GameText`, as rows of text and
+// images, with the rows spawned for the current line.
+struct GaijiText(Vec<Entity>);
+
+struct ChapterCard(Timer);
+
+struct TextLayoutSettings {
+    direction: engine::LayoutDirection,
+    line_len: usize,
+}
+
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    layout: Res<TextLayoutSettings>,
 ) {
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
     commands.spawn_bundle(SpriteBundle {
@@ -87,29 +191,6 @@ fn setup(
         },
         ..Default::default()
     }).insert(BackgroundImage);
-    commands.spawn_bundle(SpriteBundle {
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 1.0),
-            ..Default::default()
-        },
-        ..Default::default()
-    }).insert(ForegroundImage);
-    commands.spawn_bundle(ImageBundle {
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 2.0),
-            ..Default::default()
-        },
-        style: Style {
-            position_type: PositionType::Absolute,
-            position: Rect {
-                left: Val::Px(0.0),
-                top: Val::Px(0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        },
-        ..Default::default()
-    }).insert(DateImage);
     commands.spawn_bundle(ImageBundle {
         transform: Transform {
             translation: Vec3::new(0.0, 0.0, 3.0),
@@ -129,22 +210,66 @@ fn setup(
     });
     commands.spawn().insert(TypingTimer(Timer::from_seconds(0.05, true)));
     commands.spawn_bundle(UiCameraBundle::default());
+    let text_position = if layout.direction.is_right_aligned() {
+        Rect {
+            top: Val::Px(400.0),
+            right: Val::Px(28.5 + 10.0),
+            ..Default::default()
+        }
+    } else {
+        Rect {
+            top: Val::Px(400.0),
+            left: Val::Px(28.5 + 10.0),
+            ..Default::default()
+        }
+    };
     commands.spawn_bundle(TextBundle {
         style: Style {
             align_self: AlignSelf::FlexEnd,
             position_type: PositionType::Absolute,
-            position: Rect {
-                top: Val::Px(400.0),
-                left: Val::Px(28.5 + 10.0),
-                ..Default::default()
-            },
+            position: text_position.clone(),
             max_size: Size::new(Val::Px(725.0 - 38.5 * 2.0 - 20.0), Val::Px(80.0)),
             margin: Rect::all(Val::Px(10.0)),
             flex_direction: FlexDirection::Column,
             ..Default::default()
         },
+        text: Text {
+            alignment: TextAlignment {
+                horizontal: if layout.direction.is_right_aligned() {
+                    HorizontalAlign::Right
+                } else {
+                    HorizontalAlign::Left
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        },
         ..Default::default()
     }).insert(GameText);
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: text_position,
+            margin: Rect::all(Val::Px(10.0)),
+            // Rows are stacked upwards otherwise.
+            flex_direction: FlexDirection::ColumnReverse,
+            ..Default::default()
+        },
+        material: materials.add(Color::NONE.into()),
+        ..Default::default()
+    }).insert(GaijiText(vec![]));
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(200.0),
+                left: Val::Px(28.5 + 10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    }).insert(ChapterCard(Timer::from_seconds(3.0, false)));
 }
 
 #[derive(Debug)]
@@ -152,12 +277,17 @@ enum ViewState {
     JustStarted,
     Choice(ChoiceData),
     Text(TextData),
+    Timeline(TimelineData),
 }
 
 #[derive(Debug)]
 struct ChoiceData {
     selected: usize,
     choices: Vec<String>,
+    // Whether each option was picked in an earlier playthrough.
+    seen: Vec<bool>,
+    // Cleared when the text box has to show the options again.
+    is_rendered: bool,
 }
 
 impl ChoiceData {
@@ -167,29 +297,75 @@ impl ChoiceData {
         } else {
             self.selected -= 1;
         }
+        self.is_rendered = false;
     }
 
     fn down(&mut self) {
         self.selected = (self.selected + 1) % self.choices.len();
+        self.is_rendered = false;
+    }
+}
+
+// Scenes played so far, oldest first, shown over whatever was on screen before.
+#[derive(Debug)]
+struct TimelineData {
+    selected: usize,
+    scenes: Vec<String>,
+    previous: Box<ViewState>,
+    is_rendered: bool,
+}
+
+impl TimelineData {
+    fn up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+        self.is_rendered = false;
+    }
+
+    fn down(&mut self) {
+        self.selected = (self.selected + 1).min(self.scenes.len() - 1);
+        self.is_rendered = false;
     }
 }
 
 #[derive(Debug)]
 struct TextData {
     who: Option<String>,
-    what: Option<String>,
+    what: Option<engine::TextLayout>,
     cursor: usize,
+    // Images of the gaiji in the line, in order.
+    gaiji: Vec<Handle<ColorMaterial>>,
+    // How long the line has been fully shown.
+    shown_for: Duration,
+}
+
+struct SlotImage {
+    material: Handle<ColorMaterial>,
+    layer: i32,
+    x: usize,
+    y: usize,
 }
 
 struct GameState {
     engine: engine::EngineState,
+    // F5 and F6 use the first slot.
+    saves: engine::SaveManager,
     sound_channel: AudioChannel,
     music_channel: AudioChannel,
+    voice_channel: AudioChannel,
+    // Translation picked on the command line, F7 switches between it and the original.
+    language: Option<String>,
     view: ViewState,
     steps_after_save_load: VecDeque<engine::StepResult>,
-    main_image: Handle<ColorMaterial>,
-    date_image: Handle<ColorMaterial>,
+    // Images shown in each image slot, by slot name.
+    images: HashMap<String, SlotImage>,
     background_image: Handle<ColorMaterial>,
+    pending_title: Option<String>,
+    // Assets the script is about to use, kept loading until the next step.
+    prefetched: Vec<HandleUntyped>,
+    // Voice clip of the line being shown, auto mode may wait for it to finish.
+    voice: Option<InstanceHandle>,
+    // Music playing, as named in the script.
+    music: Option<String>,
 }
 
 fn keyboard_input_system(
@@ -198,20 +374,67 @@ fn keyboard_input_system(
     mut state: ResMut<GameState>,
     materials: ResMut<Assets<ColorMaterial>>,
     audio: Res<bevy_kira_audio::Audio>,
+    layout: Res<TextLayoutSettings>,
 ) {
+    if keyboard_input.just_pressed(KeyCode::F2) {
+        match state.engine.new_game() {
+            Ok(()) => {
+                audio.stop_channel(&state.music_channel);
+                audio.stop_channel(&state.sound_channel);
+                state.music = None;
+                state.images.clear();
+                state.background_image = Handle::default();
+                state.steps_after_save_load.clear();
+                scripting_system(asset_server, state, materials, audio, layout);
+                println!("New game!");
+            }
+            Err(e) => println!("Not restarted: {}", e),
+        };
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        // The game may be closed without the engine being dropped, so read lines and
+        // such are written out with the save.
+        if let Err(e) = state.engine.flush_persistent_store() {
+            println!("Persistent data not written: {}", e);
+        }
+        match state.engine.quicksave() {
+            Ok(()) => println!("Quicksaved!"),
+            Err(e) => println!("Not quicksaved: {}", e),
+        };
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        match state.engine.quickload() {
+            Ok(serialized) => {
+                state.steps_after_save_load = serialized.into();
+                scripting_system(asset_server, state, materials, audio, layout);
+                println!("Quickloaded!");
+            }
+            Err(e) => println!("Not quickloaded: {}", e),
+        };
+        return;
+    }
+
     if keyboard_input.just_pressed(KeyCode::F5) {
-        match state.engine.save("data.sav") {
-            Ok(()) => println!("Saved!"),
+        if let Err(e) = state.engine.flush_persistent_store() {
+            println!("Persistent data not written: {}", e);
+        }
+        match state.saves.save(&state.engine, 1) {
+            Ok(_) => println!("Saved!"),
             Err(e) => println!("Not saved: {}", e),
         };
         return;
     }
 
     if keyboard_input.just_pressed(KeyCode::F6) {
-        match state.engine.load("data.sav") {
+        let file = state.saves.slot_path(1);
+        match state.engine.load(file) {
             Ok(serialized) => {
                 state.steps_after_save_load = serialized.into();
-                scripting_system(asset_server, state, materials, audio);
+                scripting_system(asset_server, state, materials, audio, layout);
                 println!("Loaded!");
             }
             Err(e) => println!("Not loaded: {}", e),
@@ -219,6 +442,98 @@ fn keyboard_input_system(
         return;
     }
 
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        let language = match state.engine.language() {
+            Some(_) => None,
+            None => state.language.clone(),
+        };
+        match state.engine.set_language(language.as_deref()) {
+            Ok(()) => {
+                scripting_system(asset_server, state, materials, audio, layout);
+                println!("Switched language!");
+            }
+            Err(e) => println!("Language not switched: {}", e),
+        };
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::A) {
+        // Paced like the player read so far, once there's enough to go by.
+        let times = state.engine.reading_times();
+        let auto_mode = match state.engine.auto_mode() {
+            Some(_) => None,
+            None => Some(engine::AutoMode {
+                wait_for_voice: !std::env::args().any(|arg| arg == "--auto-ignore-voice"),
+                ..Default::default()
+            }.calibrated(&times)),
+        };
+        match (auto_mode, times.fit()) {
+            (Some(auto_mode), Some(_)) => println!(
+                "Auto mode on, {:?} plus {:?} per character from {} lines read",
+                auto_mode.delay, auto_mode.per_character, times.samples(),
+            ),
+            (Some(_), None) => println!("Auto mode on"),
+            (None, _) => println!("Auto mode off"),
+        }
+        state.engine.set_auto_mode(auto_mode);
+        return;
+    }
+
+    // Streamer-safe mode mutes copyrighted music and hides spoilers from the gallery.
+    if keyboard_input.just_pressed(KeyCode::F8) {
+        let streamer_safe = !state.engine.is_streamer_safe();
+        state.engine.set_streamer_safe(streamer_safe);
+        set_music_volume(&state, &audio);
+        println!("Streamer-safe mode {}", if streamer_safe { "on" } else { "off" });
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        match state.engine.reload_current_script() {
+            Ok(()) => {
+                scripting_system(asset_server, state, materials, audio, layout);
+                println!("Reloaded!");
+            }
+            Err(e) => println!("Not reloaded: {}", e),
+        };
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::T) {
+        let view = std::mem::replace(&mut state.view, ViewState::JustStarted);
+        state.view = match view {
+            // The timeline was shown over it, so it's shown again.
+            ViewState::Timeline(timeline) => match *timeline.previous {
+                ViewState::Choice(choice) => ViewState::Choice(ChoiceData { is_rendered: false, ..choice }),
+                ViewState::Text(text) => {
+                    let shown = text.what.as_ref().map_or(0, |what| what.len());
+                    ViewState::Text(TextData { cursor: text.cursor.min(shown), ..text })
+                }
+                view => view,
+            },
+            view if state.engine.checkpoints().is_empty() => {
+                println!("No scenes to go back to yet");
+                view
+            }
+            view => {
+                let scenes: Vec<_> = state.engine.checkpoints()
+                    .iter()
+                    .map(|checkpoint| match &checkpoint.chapter {
+                        Some(chapter) => format!("{} ({})", chapter, checkpoint.label),
+                        None => checkpoint.label.clone(),
+                    })
+                    .collect();
+                ViewState::Timeline(TimelineData {
+                    selected: scenes.len() - 1,
+                    scenes,
+                    previous: Box::new(view),
+                    is_rendered: false,
+                })
+            }
+        };
+        return;
+    }
+
     let GameState { view, .. } = &mut *state;
     match view {
         ViewState::Choice(choice) => {
@@ -228,84 +543,165 @@ fn keyboard_input_system(
                 choice.up();
             }
         }
+        ViewState::Timeline(timeline) => {
+            if keyboard_input.just_pressed(KeyCode::Down) {
+                timeline.down();
+            } else if keyboard_input.just_pressed(KeyCode::Up) {
+                timeline.up();
+            } else if keyboard_input.just_pressed(KeyCode::Return) {
+                let selected = timeline.selected;
+                match state.engine.restore_checkpoint(selected) {
+                    Ok(scene) => {
+                        state.view = ViewState::JustStarted;
+                        state.steps_after_save_load = scene.into();
+                        scripting_system(asset_server, state, materials, audio, layout);
+                        println!("Went back!");
+                    }
+                    Err(e) => println!("Not gone back: {}", e),
+                }
+            }
+            return;
+        }
         ViewState::Text(_) => {}
         ViewState::JustStarted => {}
     }
 
+    // Holding control skips through text, only read text unless `--skip-unread` is given.
+    let is_skipping = keyboard_input.pressed(KeyCode::LControl)
+        && matches!(view, ViewState::Text(_))
+        && state.engine.can_skip();
     if keyboard_input.just_pressed(KeyCode::Space) ||
-        keyboard_input.just_pressed(KeyCode::Return) {
-        scripting_system(asset_server, state, materials, audio)
+        keyboard_input.just_pressed(KeyCode::Return) ||
+        is_skipping {
+        // Lines moved on from by hand once read tell auto mode how fast the player reads.
+        let read = match &state.view {
+            ViewState::Text(TextData { what: Some(what), cursor, shown_for, .. }) if *cursor >= what.len() => {
+                Some((what.len(), *shown_for))
+            }
+            _ => None,
+        };
+        if let (Some((len, shown_for)), false, None) = (read, is_skipping, state.engine.auto_mode()) {
+            state.engine.record_reading(len, shown_for);
+        }
+        scripting_system(asset_server, state, materials, audio, layout)
     }
 }
 
+const PREFETCHED_ASSETS: usize = 4;
+
 fn scripting_system(
     asset_server: Res<AssetServer>,
     mut state: ResMut<GameState>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     audio: Res<bevy_kira_audio::Audio>,
+    layout: Res<TextLayoutSettings>,
 ) {
-    loop {
-        let step = match state.steps_after_save_load.pop_front() {
-            Some(step) => step,
-            None => engine::step(&mut state.engine)
-        };
-
+    // Voice clips belong to a single line, advancing cuts them off.
+    audio.stop_channel(&state.voice_channel);
+    state.voice = None;
+    let mut steps: Vec<_> = state.steps_after_save_load.drain(..).collect();
+    steps.extend(state.engine.run_until_interactive());
+    for step in steps {
         match step {
-            engine::StepResult::Text(who, what) => {
+            engine::StepResult::Text(who, what, true, spans) => {
+                let gaiji = spans
+                    .into_iter()
+                    .filter_map(|span| match span {
+                        engine::TextSpan::Image(path) => Some(materials.add(asset_server.load(path).into())),
+                        _ => None,
+                    })
+                    .collect();
                 state.view = ViewState::Text(TextData {
                     who,
-                    what: Some(what),
+                    what: Some(engine::TextLayout::new(&what, layout.line_len, layout.direction)),
                     cursor: 0,
+                    gaiji,
+                    shown_for: Duration::ZERO,
                 });
-                break;
             }
-            engine::StepResult::Jump(file) => {
-                state.engine.load_script(&file);
-                state.main_image = materials.add(asset_server.load("empty.png").into());
-                state.date_image = materials.add(asset_server.load("empty.png").into());
-                continue;
+            // The engine has already loaded the script.
+            engine::StepResult::Jump(_) => {
+                state.images.clear();
+            }
+            engine::StepResult::Title(title) => {
+                state.pending_title = Some(title);
+            }
+            engine::StepResult::Chapter(chapter) => {
+                let title = state.engine.current_title().map(|title| format!("{} - {}", title, chapter));
+                state.pending_title = Some(title.unwrap_or(chapter));
+            }
+            engine::StepResult::SaveWarning(warning) => {
+                println!("Warning: {}", warning);
             }
             engine::StepResult::Background(path) => {
                 state.background_image = materials.add(asset_server.load(path).into());
-                continue;
             }
-            engine::StepResult::Image(path, engine::ImageSlot::Main, _, _) => {
-                state.main_image = materials.add(asset_server.load(path).into());
-                continue;
+            engine::StepResult::Image(path, slot, x, y) => {
+                let material = materials.add(asset_server.load(path).into());
+                state.images.insert(slot.name, SlotImage { material, layer: slot.layer, x, y });
             }
-            engine::StepResult::Image(path, engine::ImageSlot::Date, _, _) => {
-                state.date_image = materials.add(asset_server.load(path).into());
-                continue;
+            engine::StepResult::ClearImage(slot) => {
+                state.images.remove(&slot.name);
             }
-            engine::StepResult::Choice(choices) => {
+            engine::StepResult::Choice(choices, seen) => {
                 state.view = ViewState::Choice(ChoiceData {
-                    choices: choices.clone(),
+                    choices,
                     selected: 0,
+                    seen,
+                    is_rendered: false,
                 });
-                break;
             }
-            engine::StepResult::Sound(path) => {
+            engine::StepResult::Sound(path, times) => {
                 if path == "~" {
                     audio.stop_channel(&state.sound_channel);
+                } else if times.map_or(false, |times| times < 0) {
+                    audio.play_looped_in_channel(
+                        asset_server.load(state.engine.asset_path(&path)),
+                        &state.sound_channel,
+                    );
                 } else {
+                    // Repeat counts above one aren't supported, those sounds play once.
                     audio.play_in_channel(
-                        asset_server.load(PathBuf::from(path)),
+                        asset_server.load(state.engine.asset_path(&path)),
                         &state.sound_channel,
                     );
                 }
             }
-            engine::StepResult::Music(path) => {
+            engine::StepResult::Music { file: path, fade: _ } => {
+                // Fading in isn't supported either, see `MusicStop`.
                 audio.stop_channel(&state.music_channel);
-                if path != "~" {
-                    audio.play_looped_in_channel(
-                        asset_server.load(PathBuf::from(path)),
-                        &state.music_channel,
-                    );
-                }
+                audio.play_looped_in_channel(
+                    asset_server.load(state.engine.asset_path(&path)),
+                    &state.music_channel,
+                );
+                state.music = Some(path);
+                set_music_volume(&state, &audio);
+            }
+            engine::StepResult::Voice(path) => {
+                state.voice = Some(audio.play_in_channel(
+                    asset_server.load(state.engine.asset_path(&path)),
+                    &state.voice_channel,
+                ));
+            }
+            engine::StepResult::MusicStop { fade: _ } => {
+                // Channels can't be faded out with this version of the audio plugin.
+                audio.stop_channel(&state.music_channel);
+                state.music = None;
             }
             _ => (),
         }
     }
+    state.prefetched = state.engine.upcoming_assets(PREFETCHED_ASSETS)
+        .into_iter()
+        .map(|path| asset_server.load_untyped(path))
+        .collect();
+}
+
+// Copyrighted music keeps playing muted in streamer-safe mode, so turning it off brings
+// the music back where it is.
+fn set_music_volume(state: &GameState, audio: &bevy_kira_audio::Audio) {
+    let is_muted = state.music.as_ref().is_some_and(|music| state.engine.is_muted(music));
+    audio.set_volume_in_channel(if is_muted { 0.0 } else { 1.0 }, &state.music_channel);
 }
 
 fn render_choices(
@@ -321,14 +717,16 @@ fn render_choices(
             style: TextStyle {
                 font: asset_server.load("fonts/FiraSans-Bold.ttf"),
                 font_size: 20.0,
-                color: match choice_state.selected == idx {
-                    false => Color::WHITE,
-                    true => Color::RED,
+                color: match (choice_state.selected == idx, choice_state.seen[idx]) {
+                    (true, _) => Color::RED,
+                    (false, true) => Color::GRAY,
+                    (false, false) => Color::WHITE,
                 },
             },
         });
     }
     state.set_choice(choice_state.selected);
+    choice_state.is_rendered = true;
 }
 
 fn choice_system(
@@ -336,97 +734,318 @@ fn choice_system(
     mut state: ResMut<GameState>,
     mut text_query: Query<&mut Text, With<GameText>>,
 ) {
+    // Only rendered when something changed, so nothing is redone while the player thinks.
     let GameState { engine, view, .. } = &mut *state;
     match view {
-        ViewState::Choice(choice) => {
+        ViewState::Choice(choice) if !choice.is_rendered => {
             render_choices(&mut *text_query.single_mut().unwrap(), engine, &asset_server, choice);
         }
+        ViewState::Timeline(timeline) if !timeline.is_rendered => {
+            render_timeline(&mut *text_query.single_mut().unwrap(), &asset_server, timeline);
+        }
+        ViewState::Choice(_) | ViewState::Timeline(_) => {}
         ViewState::JustStarted => {}
         ViewState::Text(_) => {}
     }
 }
 
+// Only the scenes around the selected one fit in the text box.
+fn render_timeline(text: &mut Text, asset_server: &AssetServer, timeline: &mut TimelineData) {
+    text.sections.clear();
+    let first = timeline.selected.saturating_sub(1).min(timeline.scenes.len().saturating_sub(3));
+    for (idx, scene) in timeline.scenes.iter().enumerate().skip(first).take(3) {
+        text.sections.push(TextSection {
+            value: scene.to_string() + "\n",
+            style: TextStyle {
+                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                font_size: 20.0,
+                color: if timeline.selected == idx { Color::RED } else { Color::WHITE },
+            },
+        });
+    }
+    timeline.is_rendered = true;
+}
+
 fn typing_system(
+    mut commands: Commands,
     time: Res<Time>,
     mut state: ResMut<GameState>,
     asset_server: ResMut<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
     mut text_query: Query<&mut Text, With<GameText>>,
+    mut gaiji_query: Query<(Entity, &mut GaijiText)>,
     mut query: Query<&mut TypingTimer>,
 ) {
+    state.engine.tick(time.delta());
     let mut timer = query.single_mut().unwrap();
     timer.0.tick(time.delta());
     if !timer.0.just_finished() {
         return;
     }
 
-    if let ViewState::Text(TextData { cursor, who, what }) = &mut state.view {
+    // Once the whole line is shown, there's nothing to redo until the next one.
+    let is_shown = matches!(
+        &state.view,
+        ViewState::Text(TextData { what, cursor, .. }) if *cursor > what.as_ref().map_or(0, |what| what.len())
+    );
+    if is_shown {
+        return;
+    }
+    let (container, mut rows) = gaiji_query.single_mut().unwrap();
+    for row in rows.0.drain(..) {
+        commands.entity(row).despawn_recursive();
+    }
+    if let ViewState::Text(TextData { cursor, who, what, gaiji, .. }) = &mut state.view {
         *cursor += 1;
 
+        let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+        let style = |color| TextStyle { font: font.clone(), font_size: 20.0, color };
+        let mut who = who.as_ref().map(|who| {
+            let separator = match what.as_ref().map(|w| w.direction()) {
+                Some(engine::LayoutDirection::LeftToRight) | None => ": ",
+                Some(_) => "\n",
+            };
+            format!("{}{}", who, separator)
+        });
+        let rendered = what.as_ref().map(|what| what.render(*cursor)).unwrap_or_default();
+
         let mut text = text_query.single_mut().unwrap();
         text.sections.clear();
-        if let Some(who) = who {
-            text.sections.push(TextSection {
-                value: format!("{}: ", who),
-                style: TextStyle {
-                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                    font_size: 20.0,
-                    color: Color::RED,
-                },
-            });
+        if gaiji.is_empty() {
+            if let Some(who) = who {
+                text.sections.push(TextSection { value: who, style: style(Color::RED) });
+            }
+            text.sections.push(TextSection { value: rendered, style: style(Color::WHITE) });
+            return;
         }
 
-        if let Some(what) = what {
-            text.sections.push(TextSection {
-                value: what.chars().take(*cursor).collect(),
-                style: TextStyle {
-                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                    font_size: 20.0,
-                    color: Color::WHITE,
-                },
-            });
+        // Each placeholder in the rendered text stands for the next gaiji image.
+        let transparent = materials.add(Color::NONE.into());
+        let mut images = gaiji.iter();
+        commands.entity(container).with_children(|parent| {
+            for line in rendered.split('\n') {
+                let mut row = parent.spawn_bundle(NodeBundle {
+                    style: Style { flex_direction: FlexDirection::Row, ..Default::default() },
+                    material: transparent.clone(),
+                    ..Default::default()
+                });
+                row.with_children(|row| {
+                    if let Some(who) = who.take() {
+                        row.spawn_bundle(TextBundle {
+                            text: Text::with_section(who, style(Color::RED), Default::default()),
+                            ..Default::default()
+                        });
+                    }
+                    for (n, part) in line.split(engine::INLINE_IMAGE).enumerate() {
+                        if n > 0 {
+                            if let Some(image) = images.next() {
+                                row.spawn_bundle(ImageBundle {
+                                    style: Style { size: Size::new(Val::Px(20.0), Val::Px(20.0)), ..Default::default() },
+                                    material: image.clone(),
+                                    ..Default::default()
+                                });
+                            }
+                        }
+                        if !part.is_empty() {
+                            row.spawn_bundle(TextBundle {
+                                text: Text::with_section(part, style(Color::WHITE), Default::default()),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                });
+                rows.0.push(row.id());
+            }
+        });
+    }
+}
+
+// Times how long fully typed lines are up, and moves on from them by itself while auto
+// mode is on.
+fn auto_system(
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut state: ResMut<GameState>,
+    materials: ResMut<Assets<ColorMaterial>>,
+    audio: Res<bevy_kira_audio::Audio>,
+    layout: Res<TextLayoutSettings>,
+) {
+    let (len, shown_for) = match &mut state.view {
+        ViewState::Text(TextData { what: Some(what), cursor, shown_for, .. }) if *cursor >= what.len() => {
+            *shown_for += time.delta();
+            (what.len(), *shown_for)
+        }
+        _ => return,
+    };
+    let auto_mode = match state.engine.auto_mode() {
+        Some(auto_mode) => auto_mode,
+        None => return,
+    };
+    let voice = match &state.voice {
+        None => engine::VoiceStatus::Silent,
+        Some(voice) => match audio.state(voice.clone()) {
+            PlaybackState::Stopped => engine::VoiceStatus::Finished,
+            _ => engine::VoiceStatus::Playing,
+        },
+    };
+    if auto_mode.should_advance(len, shown_for, voice) {
+        scripting_system(asset_server, state, materials, audio, layout);
+    }
+}
+
+// While nothing on screen moves, a frame is only drawn this often, which still shows
+// images as they finish loading.
+const IDLE_FRAME_TIME: Duration = Duration::from_millis(100);
+// Presenting with vsync held updates to the display rate, skipped frames are held to it
+// here instead.
+const DISPLAY_FRAME_TIME: Duration = Duration::from_micros(16_667);
+
+// Run criteria of the render stage. Every frame is drawn while a line types out, a
+// chapter card is up or a key is held, otherwise the GPU is left alone between idle
+// frames. Game systems run on every update either way.
+fn frame_pacing(
+    state: Res<GameState>,
+    keyboard_input: Res<Input<KeyCode>>,
+    cards: Query<&ChapterCard>,
+    mut last_render: Local<Option<Instant>>,
+    mut last_update: Local<Option<Instant>>,
+) -> ShouldRun {
+    // Until `typing_system` is done with the line.
+    let is_typing = match &state.view {
+        ViewState::Text(TextData { what, cursor, .. }) => *cursor <= what.as_ref().map_or(0, |what| what.len()),
+        ViewState::JustStarted => true,
+        _ => false,
+    };
+    let is_active = is_typing
+        || cards.iter().any(|card| !card.0.finished())
+        || keyboard_input.get_pressed().next().is_some();
+
+    let now = Instant::now();
+    if is_active || last_render.is_none_or(|at| now - at >= IDLE_FRAME_TIME) {
+        *last_render = Some(now);
+        *last_update = Some(now);
+        return ShouldRun::Yes;
+    }
+    if let Some(elapsed) = last_update.map(|at| at.elapsed()) {
+        if elapsed < DISPLAY_FRAME_TIME {
+            std::thread::sleep(DISPLAY_FRAME_TIME - elapsed);
+        }
+    }
+    *last_update = Some(Instant::now());
+    ShouldRun::No
+}
+
+fn chapter_card_system(
+    time: Res<Time>,
+    mut state: ResMut<GameState>,
+    asset_server: Res<AssetServer>,
+    mut windows: ResMut<Windows>,
+    mut query: Query<(&mut Text, &mut ChapterCard)>,
+) {
+    let (mut text, mut card) = query.single_mut().unwrap();
+    if let Some(title) = state.pending_title.take() {
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_title(format!("Madenon - {}", title));
         }
+        text.sections = vec![TextSection {
+            value: title,
+            style: TextStyle {
+                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                font_size: 32.0,
+                color: Color::WHITE,
+            },
+        }];
+        card.0.reset();
+        return;
+    }
+
+    card.0.tick(time.delta());
+    if card.0.just_finished() {
+        text.sections.clear();
     }
 }
 
 fn image_presenting_system(
+    mut commands: Commands,
     state: Res<GameState>,
     materials: Res<Assets<ColorMaterial>>,
     textures: Res<Assets<Texture>>,
     mut color_query: QuerySet<(
         QueryState<&mut Handle<ColorMaterial>, With<BackgroundImage>>,
-        QueryState<&mut Handle<ColorMaterial>, With<ForegroundImage>>,
-        QueryState<&mut Handle<ColorMaterial>, With<DateImage>>,
+        QueryState<(Entity, &ImageLayer, &mut Handle<ColorMaterial>, &mut Transform)>,
     )>,
 ) {
-    let is_texture_loaded = |handle: &Handle<ColorMaterial>| -> bool {
+    let texture_size = |handle: &Handle<ColorMaterial>| -> Option<Vec2> {
         materials
             .get(handle)
             .and_then(|mat| mat.texture.as_ref())
             .and_then(|tex| textures.get(tex))
-            .is_some()
+            .map(|tex| Vec2::new(tex.size.width as f32, tex.size.height as f32))
     };
+    // Slot positions are the top left corner of the image, in window pixels.
+    let image_transform = |image: &SlotImage, size: Vec2| Transform::from_xyz(
+        image.x as f32 + (size.x - 725.0) / 2.0,
+        (544.0 - size.y) / 2.0 - image.y as f32,
+        image.layer as f32,
+    );
 
-    if is_texture_loaded(&state.background_image) {
+    if texture_size(&state.background_image).is_some() {
         *color_query.q0().single_mut().unwrap() = state.background_image.clone();
     }
-    if is_texture_loaded(&state.main_image) {
-        *color_query.q1().single_mut().unwrap() = state.main_image.clone();
+
+    let mut shown = HashSet::new();
+    for (entity, layer, mut material, mut transform) in color_query.q1().iter_mut() {
+        match state.images.get(&layer.0) {
+            Some(image) => {
+                if let Some(size) = texture_size(&image.material) {
+                    *material = image.material.clone();
+                    *transform = image_transform(image, size);
+                }
+                shown.insert(layer.0.clone());
+            }
+            None => commands.entity(entity).despawn(),
+        }
     }
-    if is_texture_loaded(&state.date_image) {
-        *color_query.q2().single_mut().unwrap() = state.date_image.clone();
+    for (name, image) in state.images.iter().filter(|(name, _)| !shown.contains(*name)) {
+        if let Some(size) = texture_size(&image.material) {
+            commands.spawn_bundle(SpriteBundle {
+                material: image.material.clone(),
+                transform: image_transform(image, size),
+                ..Default::default()
+            }).insert(ImageLayer(name.clone()));
+        }
     }
 }
 
 struct LegArchiveLoader {
     fallback: Box<dyn AssetIo>,
-    leg: Mutex<leg_archive::Archive>,
+    leg: engine::ArchiveResources,
 }
 
 impl LegArchiveLoader {
-    fn new(fallback: Box<dyn AssetIo>, archive_path: impl AsRef<Path>) -> Self {
+    // Files in the `mods` directory of the game override the ones in its archives.
+    fn new(fallback: Box<dyn AssetIo>, directory: &Path, key: Option<leg_archive::ArchiveKey>) -> Self {
+        let mut archives: Vec<PathBuf> = std::fs::read_dir(directory)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        archives.retain(|path| matches!(path.extension().and_then(|e| e.to_str()), Some("legArchive") | Some("zip")));
+        archives.sort();
+
+        let mut set = leg_archive::ArchiveSet::new()
+            .with_normalized_directory(directory.join(MODS_DIRECTORY), leg_archive::Normalization::ALL);
+        for path in archives {
+            let archive = match &key {
+                Some(key) => leg_archive::load_any_encrypted(&path, leg_archive::Normalization::ALL, key.clone()),
+                None => leg_archive::load_any_with(&path, leg_archive::Normalization::ALL),
+            };
+            match archive {
+                Ok(archive) => set = set.with_archive(archive),
+                Err(e) => println!("Not loading archive {}: {}", path.display(), e),
+            }
+        }
         Self {
             fallback,
-            leg: Mutex::new(leg_archive::load(archive_path, false).unwrap()),
+            leg: engine::ArchiveResources::new(set, PathBuf::new()).with_normalization(leg_archive::Normalization::ALL),
         }
     }
 }
@@ -434,18 +1053,26 @@ impl LegArchiveLoader {
 
 impl AssetIo for LegArchiveLoader {
     fn load_path<'a>(&'a self, path: &'a Path) -> BoxedFuture<'a, Result<Vec<u8>, AssetIoError>> {
-        if let Some(x) = self.leg.lock().unwrap().read(path.to_str().unwrap()) {
-            return Box::pin(std::future::ready(Ok(x.into_vec())));
-        }
-        self.fallback.load_path(path)
+        Box::pin(async move {
+            match self.leg.read_async(path).await {
+                Some(data) => Ok(data.into_vec()),
+                None => self.fallback.load_path(path).await,
+            }
+        })
     }
 
     fn read_directory(&self, path: &Path) -> Result<Box<dyn Iterator<Item=PathBuf>>, AssetIoError> {
-        self.fallback.read_directory(path)
+        match self.leg.list(path) {
+            Ok(names) if !names.is_empty() => {
+                let path = path.to_path_buf();
+                Ok(Box::new(names.into_iter().map(move |name| path.join(name))))
+            }
+            _ => self.fallback.read_directory(path),
+        }
     }
 
     fn is_directory(&self, path: &Path) -> bool {
-        self.fallback.is_directory(path)
+        matches!(self.leg.list(path), Ok(names) if !names.is_empty()) || self.fallback.is_directory(path)
     }
 
     fn watch_path_for_changes(&self, path: &Path) -> Result<(), AssetIoError> {
@@ -457,7 +1084,8 @@ impl AssetIo for LegArchiveLoader {
     }
 }
 
-struct LegAssetPlugin(PathBuf);
+// The game directory, and the key of its archives.
+struct LegAssetPlugin(PathBuf, Option<leg_archive::ArchiveKey>);
 
 impl Plugin for LegAssetPlugin {
     fn build(&self, app: &mut App) {
@@ -466,6 +1094,7 @@ impl Plugin for LegAssetPlugin {
             AssetServer::new(LegArchiveLoader::new(
                 Box::new(FileAssetIo::new(&"assets")),
                 &self.0,
+                self.1.clone(),
             ), task_pool)
         );
     }
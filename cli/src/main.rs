@@ -1,8 +1,14 @@
-use engine::{StepResult, step, EngineState};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-fn user_choice(choices: &[String]) -> usize {
-    for (idx, choice) in choices.iter().enumerate() {
-        println!(" {}. {}", idx + 1, choice);
+use engine::{StepResult, step, EngineState, ScriptDecryptor, ScriptOptions, XorKey};
+use serde::{Deserialize, Serialize};
+
+fn user_choice(choices: &[String], seen: &[bool]) -> usize {
+    for (idx, (choice, seen)) in choices.iter().zip(seen).enumerate() {
+        let marker = if *seen { " (seen)" } else { "" };
+        println!(" {}. {}{}", idx + 1, choice, marker);
     }
 
     let mut input = String::new();
@@ -19,23 +25,265 @@ fn user_choice(choices: &[String]) -> usize {
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut state = EngineState::new(r"C:\Users\Host\Downloads\Kanon");
+#[derive(Serialize)]
+struct Event<'a> {
+    script: &'a str,
+    pc: usize,
+    timestamp: u128,
+    result: &'a StepResult,
+}
+
+#[derive(Deserialize)]
+struct ChoiceInput {
+    choice: usize,
+}
+
+fn json_choice(choices: &[String]) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut input = String::new();
     loop {
-        match step(&mut state) {
-            StepResult::Continue => {}
+        input.clear();
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            return Err("stdin closed while waiting for a choice".into());
+        }
+        match serde_json::from_str::<ChoiceInput>(&input) {
+            Ok(ChoiceInput { choice }) if choice < choices.len() => return Ok(choice),
+            _ => eprintln!("// Invalid choice: {}", input.trim()),
+        }
+    }
+}
+
+fn run(
+    directory: &str,
+    json: bool,
+    options: ScriptOptions,
+    persistent: Option<&str>,
+    language: Option<&str>,
+    translation: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if options.decryptor.is_none() {
+        engine::GameManifest::load(Path::new(directory)).script_key()?;
+    }
+    let mut state = EngineState::with_options(directory, options);
+    if let Some(file) = persistent {
+        state.open_persistent_store(file)?;
+    }
+    if language.is_some() {
+        state.set_language(language)?;
+    }
+    if let Some(file) = translation {
+        state.load_translation(file)?;
+    }
+    loop {
+        let script = state.current_script().to_string();
+        let pc = state.pc();
+        let result = step(&mut state);
+        if json && !matches!(result, StepResult::Continue | StepResult::Yield) {
+            let event = Event {
+                script: &script,
+                pc,
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+                result: &result,
+            };
+            println!("{}", serde_json::to_string(&event)?);
+        }
+
+        match result {
+            StepResult::Continue | StepResult::Yield => {}
             StepResult::Exit => {
-                println!("// Exitted!");
+                if !json {
+                    println!("// Exitted!");
+                }
                 break;
             }
             StepResult::Jump(file) => {
-                println!("// Loading script {}", &file);
-                state.load_script(&file);
+                if !json {
+                    println!("// Loading script {}", file);
+                }
+                state.load_script(&file)?;
             }
-            StepResult::Choice(choices) => {
-                state.set_choice(user_choice(&choices));
+            StepResult::Choice(choices, seen) => {
+                let choice = if json {
+                    json_choice(&choices)?
+                } else {
+                    user_choice(&choices, &seen)
+                };
+                state.set_choice(choice);
             }
+            StepResult::Text(who, what, _, _) if !json => {
+                match who {
+                    Some(who) => println!("{}: {}", who, what),
+                    None => println!("{}", what),
+                }
+            }
+            StepResult::Ending(id) if !json => {
+                println!("// Reached ending {}", id);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Global data goes along when `--persistent=` names the file.
+fn saves_with_global(directory: &str, persistent: Option<&str>) -> engine::SaveManager {
+    let saves = engine::SaveManager::new(directory);
+    match persistent {
+        Some(file) => saves.with_global_data(file),
+        None => saves,
+    }
+}
+
+fn dedup(directory: &str, min_similarity: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for cluster in engine::find_duplicate_text(directory, min_similarity)? {
+        let kind = if cluster.exact { "identical" } else { "similar" };
+        println!("{} lines ({}):", cluster.occurrences.len(), kind);
+        for occurrence in &cluster.occurrences {
+            println!("  {} @ {}: {}", occurrence.script, occurrence.pc, occurrence.text);
         }
     }
     Ok(())
 }
+
+fn stats(directory: &str, json: bool, options: &ScriptOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let all = engine::analyze(directory, options)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&all)?);
+        return Ok(());
+    }
+    for stats in &all {
+        println!("{}: {} lines, {} words, {} characters, {} choices, {} branches",
+            stats.script, stats.lines, stats.words, stats.characters, stats.choices, stats.branches);
+        println!("  (narration): {}", stats.narration_lines);
+        for (speaker, lines) in &stats.lines_by_speaker {
+            println!("  {}: {}", speaker, lines);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run_command() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_command() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|arg| arg == "--json");
+    let verbose = args.iter().any(|arg| arg == "--verbose");
+    let persistent = args.iter().find_map(|arg| arg.strip_prefix("--persistent="));
+    let language = args.iter().find_map(|arg| arg.strip_prefix("--language="));
+    let translation = args.iter().find_map(|arg| arg.strip_prefix("--translation="));
+    let script_key = args.iter()
+        .find_map(|arg| arg.strip_prefix("--script-key="))
+        .map(|key| XorKey::from_hex(key).ok_or("invalid script key"))
+        .transpose()?;
+    let options = ScriptOptions {
+        vnds_compat: args.iter().any(|arg| arg == "--vnds"),
+        decryptor: script_key.map(|key| Arc::new(key) as Arc<dyn ScriptDecryptor>),
+        ..Default::default()
+    };
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(if verbose { tracing::Level::TRACE } else { tracing::Level::WARN })
+        .init();
+
+    let mut positional = args.iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .map(String::as_str);
+
+    match positional.next() {
+        Some("run") | None => {
+            run(positional.next().unwrap_or(r"C:\Users\Host\Downloads\Kanon"), json, options, persistent, language, translation)
+        }
+        Some("dedup") => {
+            let directory = positional.next().ok_or("missing game directory")?;
+            let min_similarity = positional.next().map_or(Ok(0.9), str::parse)?;
+            dedup(directory, min_similarity)
+        }
+        Some("save") => {
+            match (positional.next(), positional.next(), positional.next()) {
+                // Global data goes along when `--persistent=` names the file.
+                (Some("export"), Some(save), Some(bundle)) => Ok(engine::export_save(save, persistent.map(Path::new), bundle)?),
+                (Some("import"), Some(bundle), Some(save)) => Ok(engine::import_save(bundle, save, persistent.map(Path::new))?),
+                (Some("export-all"), Some(saves), Some(bundle)) => Ok(saves_with_global(saves, persistent).export_all(bundle)?),
+                (Some("import-all"), Some(bundle), Some(saves)) => {
+                    let slots = saves_with_global(saves, persistent).import(bundle)?;
+                    println!("// Imported {} slots", slots.len());
+                    Ok(())
+                }
+                _ => Err("usage: cli save export <save> <bundle> | cli save import <bundle> <save> | \
+                    cli save export-all <saves dir> <bundle> | cli save import-all <bundle> <saves dir>".into()),
+            }
+        }
+        Some("remap") => {
+            match (positional.next(), positional.next()) {
+                (Some(old), Some(new)) => {
+                    let table = engine::RemapTable::generate(old, new, &options)?;
+                    if table.is_empty() {
+                        println!("// No saved positions moved");
+                    }
+                    let output = positional.next().map_or_else(
+                        || std::path::Path::new(new).join(engine::REMAP_FILE),
+                        std::path::PathBuf::from,
+                    );
+                    Ok(table.save(output)?)
+                }
+                _ => Err("usage: cli remap <old game dir> <new game dir> [output]".into()),
+            }
+        }
+        Some("line-ids") => {
+            let directory = positional.next().ok_or("missing game directory")?;
+            let file = std::path::Path::new(directory).join(engine::LINE_IDS_FILE);
+            let mut ids = if file.exists() {
+                engine::LineIds::load(&file)?
+            } else {
+                engine::LineIds::default()
+            };
+            let assigned = ids.update(directory, &options)?;
+            println!("// Assigned {} new line ids", assigned);
+            Ok(ids.save(file)?)
+        }
+        Some("stats") => {
+            let directory = positional.next().ok_or("missing game directory")?;
+            stats(directory, json, &options)
+        }
+        Some("graph") => {
+            let directory = positional.next().ok_or("missing game directory")?;
+            let graph = match positional.next() {
+                Some(script) => engine::FlowGraph::for_script(directory, script, &options)?,
+                None => engine::FlowGraph::for_game(directory, &options)?,
+            };
+            print!("{}", graph.to_dot());
+            Ok(())
+        }
+        Some("lint") => {
+            let mut found = 0;
+            for file in positional {
+                let source = std::fs::read(file)?;
+                for diagnostic in engine::lint(file, &source, &options) {
+                    println!("{}", diagnostic);
+                    found += 1;
+                }
+            }
+            match found {
+                0 => Ok(()),
+                _ => Err(format!("{} problems found", found).into()),
+            }
+        }
+        Some("xor") => {
+            match (positional.next(), positional.next(), positional.next()) {
+                (Some(key), Some(input), Some(output)) => {
+                    let key = XorKey::from_hex(key).ok_or("invalid script key")?;
+                    let mut data = std::fs::read(input)?;
+                    key.apply(&mut data);
+                    Ok(std::fs::write(output, data)?)
+                }
+                _ => Err("usage: cli xor <hex key> <input> <output>".into()),
+            }
+        }
+        Some(command) => Err(format!("unknown command: {}", command).into()),
+    }
+}
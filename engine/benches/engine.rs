@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use criterion::{BatchSize, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use engine::{EngineState, StepResult, step};
+
+const SCENES: usize = 2000;
+
+// One long route, with scenes using the instructions common in real games.
+fn large_script() -> String {
+    let mut script = String::new();
+    for scene in 0..SCENES {
+        script.push_str(&format!("\
+            label scene{scene}\n\
+            bgload bg{bg}.png\n\
+            setimg ch{ch}.png 10 20\n\
+            music bgm{music}.ogg\n\
+            setvar visits = $visits + 1\n\
+            if $visits >= {scene}\n\
+            text \"Ayu\" Line {scene} of the scene, about as long as a typical sentence is.\n\
+            fi\n\
+            text Narration of scene {scene}, with a few more words to lay out.\n\
+            choice Left|Right|Back?$visits > 10\n\
+            if $selected == 1\n\
+            setvar route[$visits] = $visits * 2 - 1\n\
+            fi\n\
+            sound se{sound}.ogg\n\
+            delay 10\n\
+            cleartext\n",
+            scene = scene, bg = scene % 10, ch = scene % 5, music = scene % 3, sound = scene % 4,
+        ));
+    }
+    script
+}
+
+fn game_dir(name: &str, script: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("madenon-bench-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(directory.join("Scripts")).unwrap();
+    std::fs::write(directory.join("Scripts").join("main.scr"), script).unwrap();
+    directory
+}
+
+// Steps through the whole script, picking the first option of every choice.
+fn run(state: &mut EngineState) -> u64 {
+    let mut steps = 0;
+    loop {
+        steps += 1;
+        match step(state) {
+            StepResult::Exit => return steps,
+            StepResult::Choice(..) => state.set_choice(0),
+            result => {
+                black_box(result);
+            }
+        }
+    }
+}
+
+fn parsing(c: &mut Criterion) {
+    let script = large_script();
+    let directory = game_dir("parse", &script);
+    // Reloading parses the script from disk every time, bypassing the cache.
+    let mut state = EngineState::new(&directory);
+    let mut group = c.benchmark_group("load_script");
+    group.throughput(Throughput::Bytes(script.len() as u64));
+    group.bench_function("large", |b| b.iter(|| state.reload_current_script().unwrap()));
+    group.finish();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+fn stepping(c: &mut Criterion) {
+    let directory = game_dir("step", &large_script());
+    let steps = run(&mut EngineState::new(&directory));
+    let mut group = c.benchmark_group("step");
+    group.throughput(Throughput::Elements(steps));
+    group.bench_function("large", |b| {
+        b.iter_batched(|| EngineState::new(&directory), |mut state| run(&mut state), BatchSize::LargeInput)
+    });
+    group.finish();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+criterion_group!(benches, parsing, stepping);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Inputs are compiled scripts serialized as JSON.
+fuzz_target!(|data: &[u8]| {
+    engine::fuzzing::run_script(data);
+});
@@ -0,0 +1,82 @@
+use crate::{Instr, Label, Script};
+
+// A position in a script which can be found again after the script has been edited.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Anchor {
+    Start,
+    Label(Label),
+    Text {
+        text: String,
+        // Number of identical lines before this one.
+        occurrence: usize,
+        // Number of text instructions before this one.
+        ordinal: usize,
+    },
+}
+
+pub(crate) fn find(script: &Script, pc: usize) -> Anchor {
+    let last = pc.min(script.code.len().saturating_sub(1));
+    for pc in (0..=last).rev() {
+        if let Some(Instr::text(_, text, _)) = script.code.get(pc) {
+            let preceding = script.code[..pc].iter().filter_map(|instr| match instr {
+                Instr::text(_, text, _) => Some(text),
+                _ => None,
+            });
+            let (occurrence, ordinal) = preceding.fold((0, 0), |(occurrence, ordinal), t| {
+                (occurrence + (t == text) as usize, ordinal + 1)
+            });
+            return Anchor::Text { text: text.clone(), occurrence, ordinal };
+        }
+
+        let label = script.labels
+            .iter()
+            .filter(|(_, &offset)| offset == pc)
+            .map(|(label, _)| label)
+            .min_by_key(|label| format!("{:?}", label));
+        if let Some(label) = label {
+            return Anchor::Label(label.clone());
+        }
+    }
+    Anchor::Start
+}
+
+pub(crate) fn resolve(script: &Script, anchor: &Anchor) -> usize {
+    match anchor {
+        Anchor::Start => 0,
+        Anchor::Label(label) => script.labels.get(label).copied().unwrap_or(0),
+        Anchor::Text { text, occurrence, ordinal } => {
+            let texts: Vec<(usize, &String)> = script.code
+                .iter()
+                .enumerate()
+                .filter_map(|(pc, instr)| match instr {
+                    Instr::text(_, text, _) => Some((pc, text)),
+                    _ => None,
+                })
+                .collect();
+
+            texts.iter()
+                .filter(|(_, t)| *t == text)
+                .nth(*occurrence)
+                .or_else(|| texts.get(*ordinal))
+                .or_else(|| texts.last())
+                .map_or(0, |(pc, _)| *pc)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, resolve};
+    use crate::parse;
+
+    #[test]
+    fn remaps_after_edit() {
+        let old = parse("text a\ntext b\nlabel x\nsound s\ntext b\n", None, &Default::default()).unwrap().into_script().unwrap();
+        let new = parse("text a\ntext new\ntext b\nlabel x\ndelay 1\nsound s\ntext b\n", None, &Default::default()).unwrap().into_script().unwrap();
+
+        assert_eq!(resolve(&new, &find(&old, 1)), 2);
+        assert_eq!(resolve(&new, &find(&old, 2)), 3);
+        assert_eq!(resolve(&new, &find(&old, 3)), 5);
+        assert_eq!(resolve(&new, &find(&old, 4)), 5);
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// How auto mode paces moving on from lines by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoMode {
+    // How long a fully shown line stays up, plus `per_character` for each of its characters.
+    pub delay: Duration,
+    pub per_character: Duration,
+    // Whether lines with a voice clip stay up until it has finished playing, instead of
+    // for as long as their text takes to read.
+    pub wait_for_voice: bool,
+}
+
+impl Default for AutoMode {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(1000),
+            per_character: Duration::from_millis(50),
+            wait_for_voice: true,
+        }
+    }
+}
+
+// Whether the line being shown has a voice clip and if it's still playing, as only
+// the frontend playing it knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceStatus {
+    Silent,
+    Playing,
+    Finished,
+}
+
+impl AutoMode {
+    // Whether to move on from a line of `len` characters which has been fully shown
+    // for `shown_for`.
+    pub fn should_advance(&self, len: usize, shown_for: Duration, voice: VoiceStatus) -> bool {
+        match voice {
+            VoiceStatus::Playing if self.wait_for_voice => false,
+            VoiceStatus::Finished if self.wait_for_voice => shown_for >= self.delay,
+            _ => shown_for >= self.delay + self.per_character * len as u32,
+        }
+    }
+
+    // Paced the way the player moves on from lines, once enough of that was recorded.
+    pub fn calibrated(self, times: &ReadingTimes) -> Self {
+        match times.fit() {
+            Some((delay, per_character)) => Self { delay, per_character, ..self },
+            None => self,
+        }
+    }
+}
+
+// Lines needed before auto mode is fitted to the player.
+const MIN_READING_SAMPLES: u64 = 20;
+// Lines up for longer than this were left alone rather than read.
+const MAX_READING_TIME: Duration = Duration::from_secs(60);
+
+// How long the player took to move on from fully shown lines, against their length.
+// Only sums are kept, which is all a least squares line through them needs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReadingTimes {
+    samples: u64,
+    chars: f64,
+    millis: f64,
+    chars_squared: f64,
+    chars_millis: f64,
+}
+
+impl ReadingTimes {
+    pub fn record(&mut self, len: usize, shown_for: Duration) {
+        if shown_for > MAX_READING_TIME {
+            return;
+        }
+        let (x, y) = (len as f64, shown_for.as_secs_f64() * 1000.0);
+        self.samples += 1;
+        self.chars += x;
+        self.millis += y;
+        self.chars_squared += x * x;
+        self.chars_millis += x * y;
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    // The time a line stays up and the time per character of its length that fit the
+    // recorded lines best, with enough of them.
+    pub fn fit(&self) -> Option<(Duration, Duration)> {
+        if self.samples < MIN_READING_SAMPLES {
+            return None;
+        }
+        let n = self.samples as f64;
+        let spread = n * self.chars_squared - self.chars * self.chars;
+        // Lines of the same length can't tell the time per character apart.
+        let per_character = if spread > f64::EPSILON {
+            ((n * self.chars_millis - self.chars * self.millis) / spread).max(0.0)
+        } else {
+            0.0
+        };
+        let delay = ((self.millis - per_character * self.chars) / n).max(0.0);
+        Some((Duration::from_secs_f64(delay / 1000.0), Duration::from_secs_f64(per_character / 1000.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{AutoMode, ReadingTimes, VoiceStatus};
+
+    #[test]
+    fn waits_for_voice() {
+        let auto = AutoMode::default();
+        let timed = AutoMode { wait_for_voice: false, ..auto };
+        let ms = Duration::from_millis;
+
+        assert!(!auto.should_advance(20, ms(1500), VoiceStatus::Silent));
+        assert!(auto.should_advance(20, ms(2000), VoiceStatus::Silent));
+        assert!(!auto.should_advance(20, ms(5000), VoiceStatus::Playing));
+        assert!(auto.should_advance(20, ms(1000), VoiceStatus::Finished));
+        assert!(timed.should_advance(20, ms(2000), VoiceStatus::Playing));
+        assert!(!timed.should_advance(20, ms(1000), VoiceStatus::Finished));
+    }
+
+    #[test]
+    fn fits_reading_times() {
+        let ms = Duration::from_millis;
+        let mut times = ReadingTimes::default();
+        for len in 0..19 {
+            times.record(len * 10, ms(500 + 20 * len as u64 * 10));
+        }
+        times.record(10, Duration::from_secs(600));
+        let too_few = times.fit();
+        times.record(190, ms(500 + 20 * 190));
+        let (delay, per_character) = times.fit().unwrap();
+        let calibrated = AutoMode::default().calibrated(&times);
+
+        assert_eq!(too_few, None);
+        assert_eq!(times.samples(), 20);
+        assert_eq!((delay.as_secs_f64() * 1000.0).round(), 500.0);
+        assert_eq!((per_character.as_secs_f64() * 1000.0).round(), 20.0);
+        assert_eq!((calibrated.delay, calibrated.per_character), (delay, per_character));
+        assert!(calibrated.wait_for_voice);
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+// Lines kept for scrolling back unless the frontend asks for another amount.
+pub const DEFAULT_BACKLOG_LEN: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacklogEntry {
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+// The most recent lines the player read, oldest first.
+#[derive(Clone)]
+pub(crate) struct Backlog {
+    entries: VecDeque<BacklogEntry>,
+    capacity: usize,
+}
+
+impl Default for Backlog {
+    fn default() -> Self {
+        Self { entries: VecDeque::new(), capacity: DEFAULT_BACKLOG_LEN }
+    }
+}
+
+impl Backlog {
+    pub(crate) fn push(&mut self, speaker: Option<String>, text: String) {
+        self.entries.push_back(BacklogEntry { speaker, text });
+        self.trim();
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.trim();
+    }
+
+    // Replaces the lines, for loading saves.
+    pub(crate) fn replace(&mut self, entries: Vec<BacklogEntry>) {
+        self.entries = entries.into();
+        self.trim();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn entries(&self) -> &VecDeque<BacklogEntry> {
+        &self.entries
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+use crate::persistent;
+use crate::saves::{decode, parse_save, write_atomically};
+use crate::{SaveError, fnv1a};
+
+const BUNDLE_MAGIC: &str = "madenon-save-bundle";
+const BUNDLE_VERSION: u32 = 1;
+// Entry of the data kept across playthroughs, like read text and picked choices.
+pub(crate) const GLOBAL_ENTRY: &str = "global";
+
+#[derive(Serialize, Deserialize)]
+struct BundleContents {
+    entries: BTreeMap<String, serde_json::Value>,
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+// Writes a single portable file containing the given save slot, and the global data
+// file with read text and such if there is one, as the slot alone doesn't say what
+// was read in other playthroughs.
+pub fn export_save(save: impl AsRef<Path>, global: Option<&Path>, bundle: impl AsRef<Path>) -> Result<(), Error> {
+    let mut entries = BTreeMap::new();
+    entries.insert("slot".to_string(), read_entry(save.as_ref())?);
+    if let Some(global) = global.filter(|global| global.is_file()) {
+        entries.insert(GLOBAL_ENTRY.to_string(), read_entry(global)?);
+    }
+    write_bundle(bundle.as_ref(), entries)
+}
+
+// Verifies a bundle created by `export_save` and writes its save slot to `save`, and
+// its global data to `global` if both are there.
+pub fn import_save(bundle: impl AsRef<Path>, save: impl AsRef<Path>, global: Option<&Path>) -> Result<(), Error> {
+    let entries = read_bundle(bundle.as_ref())?;
+    let slot = entries.get("slot").ok_or_else(|| invalid("bundle has no save slot"))?;
+    // Checked like loading would, so a bad bundle fails here and not at the next load.
+    parse_save(&serde_json::to_vec(slot)?).map_err(|e| invalid(format!("invalid save slot: {}", e)))?;
+    let global = global.zip(entries.get(GLOBAL_ENTRY));
+    if let Some((global, _)) = global {
+        check_not_open(global)?;
+    }
+    write_atomically(save.as_ref(), &serde_json::to_vec_pretty(slot)?)?;
+    if let Some((global, data)) = global {
+        write_atomically(global, &serde_json::to_vec_pretty(data)?)?;
+    }
+    Ok(())
+}
+
+// Global data open in a game would be written over by it, so it can't be imported.
+fn check_not_open(global: &Path) -> Result<(), Error> {
+    if persistent::is_open(global) {
+        return Err(Error::new(ErrorKind::ResourceBusy, SaveError::GlobalDataInUse));
+    }
+    Ok(())
+}
+
+// Reads a save or other data file, in either save format, as a bundle entry.
+pub(crate) fn read_entry(file: &Path) -> Result<serde_json::Value, Error> {
+    decode(&std::fs::read(file)?).map_err(|e| invalid(e.to_string()))
+}
+
+pub(crate) fn write_bundle(bundle: &Path, entries: BTreeMap<String, serde_json::Value>) -> Result<(), Error> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    serde_json::to_writer(&mut encoder, &BundleContents { entries })?;
+    let payload = encoder.finish()?;
+    let header = format!("{} {} {:016x}\n", BUNDLE_MAGIC, BUNDLE_VERSION, fnv1a(&payload));
+    let mut data = header.into_bytes();
+    data.extend_from_slice(&payload);
+    std::fs::write(bundle, data)
+}
+
+pub(crate) fn read_bundle(bundle: &Path) -> Result<BTreeMap<String, serde_json::Value>, Error> {
+    let data = std::fs::read(bundle)?;
+    let split = data.iter().position(|&b| b == b'\n').ok_or_else(|| invalid("missing bundle header"))?;
+    let (header, payload) = (&data[..split], &data[split + 1..]);
+    let header = std::str::from_utf8(header).map_err(|_| invalid("not a save bundle"))?;
+
+    let (version, checksum) = match header.split(' ').collect::<Vec<_>>()[..] {
+        [BUNDLE_MAGIC, version, checksum] => (version, checksum),
+        _ => return Err(invalid("not a save bundle")),
+    };
+    let version: u32 = version.parse().map_err(|_| invalid("invalid bundle version"))?;
+    if version > BUNDLE_VERSION {
+        return Err(invalid(format!("unsupported bundle version {}", version)));
+    }
+    let checksum = u64::from_str_radix(checksum, 16).map_err(|_| invalid("invalid bundle checksum"))?;
+    if checksum != fnv1a(payload) {
+        return Err(invalid("bundle checksum mismatch, the file is corrupted"));
+    }
+
+    let mut json = vec![];
+    GzDecoder::new(payload).read_to_end(&mut json)?;
+    let contents: BundleContents = serde_json::from_slice(&json)?;
+    Ok(contents.entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{export_save, import_save, write_bundle};
+    use crate::testing::TestGame;
+
+    #[test]
+    fn round_trips_slot_and_global_data() {
+        let game_dir = TestGame::new("bundle");
+        let save = game_dir.write("save.sav", r#"{"version": 1, "memory": {}, "current_script": "main.scr", "pc": 3}"#);
+        let global = game_dir.write("global.sav", r#"{"seen_text": [1, 2], "play_time": 10}"#);
+        let bundle = game_dir.join("shared.bundle");
+        export_save(&save, Some(&global), &bundle).unwrap();
+
+        let (imported_save, imported_global) = (game_dir.join("imported.sav"), game_dir.join("imported-global.sav"));
+        import_save(&bundle, &imported_save, Some(&imported_global)).unwrap();
+        let read = |file| serde_json::from_slice::<serde_json::Value>(&std::fs::read(file).unwrap()).unwrap();
+
+        assert_eq!(read(&imported_save), read(&save));
+        assert_eq!(read(&imported_global), read(&global));
+
+        let mut corrupted = std::fs::read(&bundle).unwrap();
+        *corrupted.last_mut().unwrap() ^= 1;
+        std::fs::write(&bundle, corrupted).unwrap();
+        assert!(import_save(&bundle, &imported_save, None).is_err());
+    }
+
+    #[test]
+    fn compresses_bundles_and_checks_slots() {
+        let game_dir = TestGame::new("bundle-checks");
+        let save = game_dir.write("save.sav", format!(r#"{{"version": 1, "memory": {{}}, "current_script": "{}", "pc": 3}}"#, "a".repeat(1000)));
+        let bundle = game_dir.join("shared.bundle");
+        export_save(&save, None, &bundle).unwrap();
+        let compressed = std::fs::metadata(&bundle).unwrap().len();
+
+        let mut entries = BTreeMap::new();
+        entries.insert("slot".to_string(), serde_json::json!({"version": 1, "memory": {}, "current_script": "main.scr", "pc": "three"}));
+        let bad = game_dir.join("bad.bundle");
+        write_bundle(&bad, entries).unwrap();
+        let imported = game_dir.join("imported.sav");
+        let error = import_save(&bad, &imported, None).unwrap_err();
+
+        assert!(compressed < 500);
+        assert!(error.to_string().starts_with("invalid save slot"), "{}", error);
+        assert!(!imported.exists());
+    }
+}
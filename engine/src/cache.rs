@@ -0,0 +1,104 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::Script;
+use crate::symbol::Symbol;
+
+pub(crate) struct ScriptCache {
+    // Shared, so a script can be executed while the cache changes.
+    scripts: HashMap<Symbol, Arc<Script>>,
+    // Most recently used script names first.
+    recently_used: VecDeque<Symbol>,
+    capacity: Option<usize>,
+}
+
+impl ScriptCache {
+    pub(crate) fn new(capacity: Option<usize>) -> Self {
+        Self {
+            scripts: HashMap::new(),
+            recently_used: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn get(&self, name: Symbol) -> Option<&Script> {
+        self.scripts.get(&name).map(Arc::as_ref)
+    }
+
+    pub(crate) fn get_shared(&self, name: Symbol) -> Option<Arc<Script>> {
+        self.scripts.get(&name).cloned()
+    }
+
+    pub(crate) fn contains(&self, name: Symbol) -> bool {
+        self.scripts.contains_key(&name)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Symbol, &Script)> {
+        self.scripts.iter().map(|(&name, script)| (name, script.as_ref()))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub(crate) fn touch(&mut self, name: Symbol) {
+        if let Some(idx) = self.recently_used.iter().position(|&n| n == name) {
+            let name = self.recently_used.remove(idx).unwrap();
+            self.recently_used.push_front(name);
+        }
+    }
+
+    pub(crate) fn insert(&mut self, name: Symbol, script: Script) {
+        if self.scripts.insert(name, Arc::new(script)).is_some() {
+            self.touch(name);
+        } else {
+            self.recently_used.push_front(name);
+        }
+        self.evict();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.scripts.clear();
+        self.recently_used.clear();
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        // The most recently used script is the one being executed, so it's always kept.
+        let capacity = match self.capacity {
+            Some(capacity) => capacity.max(1),
+            None => return,
+        };
+        while self.recently_used.len() > capacity {
+            if let Some(name) = self.recently_used.pop_back() {
+                self.scripts.remove(&name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptCache;
+    use crate::Script;
+    use crate::symbol::SymbolTable;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut symbols = SymbolTable::default();
+        let (a, b, c) = (symbols.intern("a.scr"), symbols.intern("b.scr"), symbols.intern("c.scr"));
+        let mut cache = ScriptCache::new(Some(2));
+        cache.insert(a, Script::default());
+        cache.insert(b, Script::default());
+        cache.touch(a);
+        cache.insert(c, Script::default());
+
+        assert!(cache.contains(a));
+        assert!(!cache.contains(b));
+        assert!(cache.contains(c));
+    }
+}
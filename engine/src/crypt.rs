@@ -0,0 +1,83 @@
+// Undoes the obfuscation some distributions apply to their script files.
+pub trait ScriptDecryptor: Send + Sync {
+    fn decrypt(&self, data: Vec<u8>) -> Result<Vec<u8>, std::io::Error>;
+}
+
+// Repeating-key XOR, which is symmetric, so the same key also obfuscates.
+#[derive(Debug, Clone)]
+pub struct XorKey(Vec<u8>);
+
+impl XorKey {
+    pub fn new(key: impl Into<Vec<u8>>) -> Option<Self> {
+        let key = key.into();
+        if key.is_empty() {
+            return None;
+        }
+        Some(Self(key))
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let pairs = hex.as_bytes().chunks_exact(2);
+        if !pairs.remainder().is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let key = pairs
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Self::new(key)
+    }
+
+    pub fn apply(&self, data: &mut [u8]) {
+        for (b, k) in data.iter_mut().zip(self.0.iter().cycle()) {
+            *b ^= k;
+        }
+    }
+}
+
+impl ScriptDecryptor for XorKey {
+    fn decrypt(&self, mut data: Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
+        self.apply(&mut data);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScriptDecryptor, XorKey};
+    use crate::testing::TestGame;
+    use crate::{EngineState, MANIFEST_FILE, StepResult};
+
+    #[test]
+    fn parses_hex_keys() {
+        assert!(XorKey::from_hex("0a1B").is_some());
+        assert!(XorKey::from_hex("0a1").is_none());
+        assert!(XorKey::from_hex("0g").is_none());
+        assert!(XorKey::from_hex("+f").is_none());
+        assert!(XorKey::from_hex("").is_none());
+    }
+
+    #[test]
+    fn decrypts_what_it_encrypts() {
+        let key = XorKey::from_hex("5a17").unwrap();
+        let mut data = b"text hello\n".to_vec();
+        key.apply(&mut data);
+
+        assert_ne!(data, b"text hello\n");
+        assert_eq!(key.decrypt(data).unwrap(), b"text hello\n");
+    }
+
+    #[test]
+    fn runs_scripts_encrypted_with_the_manifest_key() {
+        let game_dir = TestGame::new("encrypted");
+        game_dir.write(MANIFEST_FILE, "script_key = \"5a17\"\n");
+        let mut script = b"text secret\n".to_vec();
+        XorKey::from_hex("5a17").unwrap().apply(&mut script);
+        game_dir.write("Scripts/main.scr", script);
+        let mut state = EngineState::new(&game_dir);
+
+        assert!(matches!(&state.run_until_interactive()[..], [StepResult::Text(_, text, true, _)] if text == "secret"));
+
+        game_dir.write(MANIFEST_FILE, "script_key = \"5a1\"\n");
+        assert!(EngineState::new(&game_dir).manifest().script_key().is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::manifest::scripts_dir;
+use crate::{Instr, ScriptOptions, list_scripts, load_script};
+
+#[derive(Debug, Clone)]
+pub struct TextOccurrence {
+    pub script: String,
+    pub pc: usize,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct TextCluster {
+    // Whether all occurrences are the same line after normalization.
+    pub exact: bool,
+    pub occurrences: Vec<TextOccurrence>,
+}
+
+// Groups text lines of all scripts in the game which are identical or at least
+// `min_similarity` (0.0 - 1.0) similar to each other.
+pub fn find_duplicate_text(
+    directory: impl AsRef<Path>,
+    min_similarity: f64,
+) -> Result<Vec<TextCluster>, Box<dyn std::error::Error + Send + Sync>> {
+    let scripts_dir = scripts_dir(directory.as_ref());
+
+    let mut buckets: HashMap<String, Vec<TextOccurrence>> = HashMap::new();
+    for name in list_scripts(&scripts_dir, &ScriptOptions::default())? {
+        let script = load_script(scripts_dir.join(&name), &ScriptOptions::default())?;
+        for (pc, instr) in script.code.iter().enumerate() {
+            if let Instr::text(_, text, _) = instr {
+                let key = normalize(text);
+                if key.is_empty() {
+                    continue;
+                }
+                buckets.entry(key).or_default().push(TextOccurrence {
+                    script: name.clone(),
+                    pc,
+                    text: text.clone(),
+                });
+            }
+        }
+    }
+
+    let mut keys: Vec<(String, Vec<char>)> = buckets
+        .keys()
+        .map(|k| (k.clone(), k.chars().collect()))
+        .collect();
+    keys.sort_by(|(a, ac), (b, bc)| ac.len().cmp(&bc.len()).then_with(|| a.cmp(b)));
+
+    let mut parents: Vec<usize> = (0..keys.len()).collect();
+    if min_similarity < 1.0 {
+        let lines: Vec<_> = keys.iter().map(|(_, chars)| chars.as_slice()).collect();
+        for (i, j) in similar_pairs(&lines, min_similarity) {
+            let (x, y) = (find(&mut parents, i), find(&mut parents, j));
+            parents[y] = x;
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..keys.len() {
+        groups.entry(find(&mut parents, i)).or_default().push(i);
+    }
+
+    let mut clusters: Vec<TextCluster> = groups
+        .into_values()
+        .map(|members| {
+            let mut occurrences: Vec<TextOccurrence> = members
+                .iter()
+                .flat_map(|&i| buckets[&keys[i].0].iter().cloned())
+                .collect();
+            occurrences.sort_by(|a, b| (&a.script, a.pc).cmp(&(&b.script, b.pc)));
+            TextCluster {
+                exact: members.len() == 1,
+                occurrences,
+            }
+        })
+        .filter(|cluster| cluster.occurrences.len() > 1)
+        .collect();
+    clusters.sort_by(|a, b| {
+        b.occurrences.len().cmp(&a.occurrences.len())
+            .then_with(|| a.occurrences[0].script.cmp(&b.occurrences[0].script))
+            .then_with(|| a.occurrences[0].pc.cmp(&b.occurrences[0].pc))
+    });
+    Ok(clusters)
+}
+
+// Pairs of lines, sorted by length, which are at least `min_similarity` similar. An
+// edit changes at most two of the character pairs in a line, so lines sharing too few
+// pairs are skipped without comparing them, and the rest are found through an index
+// of the lines each pair is in.
+fn similar_pairs(lines: &[&[char]], min_similarity: f64) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    let mut index: HashMap<(char, char), Vec<usize>> = HashMap::new();
+    let bigrams: Vec<HashSet<(char, char)>> = lines
+        .iter()
+        .map(|line| line.windows(2).map(|pair| (pair[0], pair[1])).collect())
+        .collect();
+    for (j, b) in lines.iter().enumerate() {
+        let max_distance = ((1.0 - min_similarity) * b.len() as f64 + 1e-9).floor() as usize;
+        // Lines are sorted by length, so shorter ones than this can't be similar enough.
+        let is_long_enough = |i: usize| lines[i].len() as f64 >= b.len() as f64 * min_similarity;
+        let candidates: Vec<usize> = if bigrams[j].len() <= 2 * max_distance {
+            // Short lines may be similar without sharing any pairs.
+            (0..j).rev().take_while(|&i| is_long_enough(i)).collect()
+        } else {
+            let mut shared: HashMap<usize, usize> = HashMap::new();
+            for bigram in &bigrams[j] {
+                for &i in index.get(bigram).into_iter().flatten() {
+                    *shared.entry(i).or_default() += 1;
+                }
+            }
+            let mut candidates: Vec<_> = shared
+                .into_iter()
+                .filter(|&(i, count)| {
+                    let needed = bigrams[i].len().max(bigrams[j].len()).saturating_sub(2 * max_distance);
+                    count >= needed && is_long_enough(i)
+                })
+                .map(|(i, _)| i)
+                .collect();
+            candidates.sort_unstable();
+            candidates
+        };
+        for i in candidates {
+            if similarity(lines[i], b) >= min_similarity {
+                pairs.push((i, j));
+            }
+        }
+        for bigram in &bigrams[j] {
+            index.entry(*bigram).or_default().push(j);
+        }
+    }
+    pairs
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn similarity(a: &[char], b: &[char]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_duplicate_text, similar_pairs, similarity};
+    use crate::testing::TestGame;
+
+    #[test]
+    fn similarities() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(similarity(&chars("abcd"), &chars("abcd")), 1.0);
+        assert_eq!(similarity(&chars("abcd"), &chars("abed")), 0.75);
+        assert_eq!(similarity(&chars(""), &chars("ab")), 0.0);
+    }
+
+    #[test]
+    fn finds_fuzzy_clusters() {
+        let game_dir = TestGame::new("dedup");
+        game_dir.script("a.scr", "\
+            text Good morning, everyone!\n\
+            text Something else entirely.\n\
+            text See you tomorrow.\n");
+        game_dir.script("b.scr", "\
+            text Good morning everyone!\n\
+            text good  morning, everyone!\n\
+            text See you tomorrow.\n");
+        let clusters = find_duplicate_text(&game_dir, 0.9).unwrap();
+        let exact = find_duplicate_text(&game_dir, 1.0).unwrap();
+
+        let texts: Vec<Vec<_>> = clusters
+            .iter()
+            .map(|cluster| cluster.occurrences.iter().map(|o| (o.script.as_str(), o.pc)).collect())
+            .collect();
+        assert_eq!(texts, [vec![("a.scr", 0), ("b.scr", 0), ("b.scr", 1)], vec![("a.scr", 2), ("b.scr", 2)]]);
+        assert!(!clusters[0].exact);
+        assert!(clusters[1].exact);
+        assert_eq!(exact.len(), 2);
+        assert_eq!(exact[0].occurrences.len(), 2);
+    }
+
+    #[test]
+    fn skips_only_dissimilar_pairs() {
+        let lines: Vec<Vec<char>> = ["ab", "abc", "abd", "xbcd", "abcde", "abcdf", "vwxyz", "aabcdef"]
+            .iter()
+            .map(|line| line.chars().collect())
+            .collect();
+        let lines: Vec<_> = lines.iter().map(Vec::as_slice).collect();
+        for &min_similarity in &[0.5, 0.6, 0.75, 0.8] {
+            let mut expected = vec![];
+            for j in 0..lines.len() {
+                for i in 0..j {
+                    if similarity(lines[i], lines[j]) >= min_similarity {
+                        expected.push((i, j));
+                    }
+                }
+            }
+            let mut pairs = similar_pairs(&lines, min_similarity);
+            pairs.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(pairs, expected, "at {}", min_similarity);
+        }
+    }
+}
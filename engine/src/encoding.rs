@@ -0,0 +1,42 @@
+use std::borrow::Cow;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum ScriptEncoding {
+    // UTF-8 when the file is valid UTF-8, Shift-JIS (CP932) otherwise.
+    #[default]
+    Auto,
+    Utf8,
+    ShiftJis,
+}
+
+impl ScriptEncoding {
+    pub(crate) fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, std::str::Utf8Error> {
+        let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+        match self {
+            ScriptEncoding::Utf8 => std::str::from_utf8(bytes).map(Cow::Borrowed),
+            ScriptEncoding::ShiftJis => Ok(decode_shift_jis(bytes)),
+            ScriptEncoding::Auto => Ok(match std::str::from_utf8(bytes) {
+                Ok(text) => Cow::Borrowed(text),
+                Err(_) => decode_shift_jis(bytes),
+            }),
+        }
+    }
+}
+
+fn decode_shift_jis(bytes: &[u8]) -> Cow<'_, str> {
+    // encoding_rs maps the Shift_JIS label to the WHATWG decoder, which covers CP932 extensions.
+    encoding_rs::SHIFT_JIS.decode_without_bom_handling(bytes).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptEncoding;
+
+    #[test]
+    fn decodes_shift_jis() {
+        let bytes = b"text \x82\xa0\x82\xa2";
+        assert_eq!(ScriptEncoding::Auto.decode(bytes).unwrap(), "text あい");
+        assert_eq!(ScriptEncoding::Auto.decode("text あい".as_bytes()).unwrap(), "text あい");
+        assert!(ScriptEncoding::Utf8.decode(bytes).is_err());
+    }
+}
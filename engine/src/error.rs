@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+// Errors shown to players. Messages start with the kind of failure, so they read the
+// same in every frontend and can be grepped for in logs.
+#[derive(Debug)]
+pub enum GameError {
+    Parse(String, Box<dyn Error + Send + Sync>),
+    Runtime(String),
+    Save(PathBuf, Box<dyn Error + Send + Sync>),
+    Archive(PathBuf, Box<dyn Error + Send + Sync>),
+    Asset(PathBuf, Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameError::Parse(script, e) => write!(f, "parse error: script {} can't be loaded: {}", script, e),
+            GameError::Runtime(message) => write!(f, "runtime error: {}", message),
+            // Saves read from or written to streams have no file.
+            GameError::Save(file, e) if file.as_os_str().is_empty() => write!(f, "save error: {}", e),
+            GameError::Save(file, e) => write!(f, "save error: {}: {}", file.display(), e),
+            GameError::Archive(file, e) => write!(f, "archive error: {}: {}", file.display(), e),
+            GameError::Asset(file, e) => write!(f, "asset error: {}: {}", file.display(), e),
+        }
+    }
+}
+
+impl GameError {
+    pub fn save_error(&self) -> Option<&SaveError> {
+        match self {
+            GameError::Save(_, e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl Error for GameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GameError::Parse(_, e)
+            | GameError::Save(_, e)
+            | GameError::Archive(_, e)
+            | GameError::Asset(_, e) => Some(e.as_ref()),
+            GameError::Runtime(_) => None,
+        }
+    }
+}
+
+// Problems with saves which frontends may want to tell apart. Returned inside
+// `GameError::Save`, except for warnings from loading.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum SaveError {
+    // Scripts which changed, or are gone, since the game was saved. The save may resume
+    // at another line than the one it was made at.
+    ScriptMismatch(Vec<String>),
+    // Copying or renaming a slot which has no save.
+    EmptySlot(u32),
+    // Quickloading before anything was quicksaved.
+    NoQuicksave,
+    // Importing global data which a running game has open.
+    GlobalDataInUse,
+    Missing,
+    // Cut off, usually by a crash while it was written.
+    Truncated,
+    // Not a save, or damaged.
+    Invalid(String),
+    // Written by a newer version of the engine, with the save format version it has.
+    UnsupportedVersion(u32),
+    // The saved script changed and there's no label to resume from in it.
+    NoResumePoint { script: String, saved_len: Option<usize>, len: usize },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::ScriptMismatch(scripts) => {
+                write!(f, "game files changed since this save: {}", scripts.join(", "))
+            }
+            SaveError::EmptySlot(slot) => write!(f, "slot {} has no save", slot),
+            SaveError::NoQuicksave => write!(f, "nothing was quicksaved"),
+            SaveError::GlobalDataInUse => write!(f, "the game has the global data open, it can't be imported over"),
+            SaveError::Missing => write!(f, "the save doesn't exist"),
+            SaveError::Truncated => write!(f, "the save is incomplete, it may not have been written fully"),
+            SaveError::Invalid(e) => write!(f, "the save is damaged: {}", e),
+            SaveError::UnsupportedVersion(version) => {
+                write!(f, "the save is from a newer version of the game (save format {})", version)
+            }
+            SaveError::NoResumePoint { script, saved_len, len } => {
+                let saved_len = saved_len.map_or_else(|| "?".to_string(), |len| len.to_string());
+                write!(f, "script {} changed since the save ({} instructions, now {}) and has no label to resume from", script, saved_len, len)
+            }
+        }
+    }
+}
+
+impl Error for SaveError {}
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{EngineState, Operator, Value, VarOrConst, parse_var_ref, unescape};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+pub(crate) type Condition = (Expr, Operator, Expr);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Expr {
+    Const(Value),
+    Var(VarOrConst),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+impl Expr {
+    pub(crate) fn eval(&self, state: &EngineState) -> Value {
+        match self {
+            Expr::Const(value) => value.clone(),
+            // Unset variables evaluate as empty.
+            Expr::Var(var) => state.get_var(var).unwrap_or_default(),
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(state), rhs.eval(state));
+                match (op, lhs.as_int(), rhs.as_int()) {
+                    (BinaryOp::Add, Some(a), Some(b)) => Value::Int(a.wrapping_add(b)),
+                    // Adding anything to a string concatenates.
+                    (BinaryOp::Add, _, _) => Value::Str(format!("{}{}", lhs, rhs)),
+                    (BinaryOp::Sub, ..) => Value::Int(lhs.to_int().wrapping_sub(rhs.to_int())),
+                    (BinaryOp::Mul, ..) => Value::Int(lhs.to_int().wrapping_mul(rhs.to_int())),
+                    (BinaryOp::Div | BinaryOp::Rem, _, _) if rhs.to_int() == 0 => {
+                        warn!(?lhs, ?rhs, "division by zero, using 0");
+                        Value::Int(0)
+                    }
+                    (BinaryOp::Div, ..) => Value::Int(lhs.to_int().wrapping_div(rhs.to_int())),
+                    (BinaryOp::Rem, ..) => Value::Int(lhs.to_int().wrapping_rem(rhs.to_int())),
+                }
+            }
+        }
+    }
+
+    // Whether all constants are integers, which tells apart `$a + 1` from values that
+    // merely contain an operator character, like `bg-01.png`.
+    fn is_arithmetic(&self) -> bool {
+        match self {
+            Expr::Const(value) => matches!(value, Value::Int(_)),
+            Expr::Var(_) => true,
+            Expr::Binary(lhs, _, rhs) => lhs.is_arithmetic() && rhs.is_arithmetic(),
+        }
+    }
+}
+
+pub(crate) fn check((lhs, op, rhs): &Condition, state: &EngineState) -> bool {
+    compare(lhs, *op, rhs, state)
+}
+
+pub(crate) fn compare(lhs: &Expr, op: Operator, rhs: &Expr, state: &EngineState) -> bool {
+    let ordering = lhs.eval(state).compare(&rhs.eval(state));
+    match op {
+        Operator::Equal => ordering.is_eq(),
+        Operator::NotEqual => ordering.is_ne(),
+        Operator::Less => ordering.is_lt(),
+        Operator::LessEqual => ordering.is_le(),
+        Operator::Greater => ordering.is_gt(),
+        Operator::GreaterEqual => ordering.is_ge(),
+    }
+}
+
+// Parses the value of an assignment or comparison, which is either arithmetic or
+// a (possibly quoted) literal. Only values with a variable or parentheses are
+// arithmetic, so literals like `12-25` or `bg-01.png` stay as written.
+pub(crate) fn parse_value(s: &str) -> Expr {
+    if !s.starts_with(&['"', '\''][..]) && s.contains(&['$', '('][..]) {
+        if let Ok(expr) = parse_expr(s) {
+            if expr.is_arithmetic() {
+                return expr;
+            }
+        }
+    }
+    Expr::Const(Value::parse(&unescape(s)))
+}
+
+// Splits a condition like `$a + $b >= 10` on its comparison operator. A lone name on
+// the left is a variable even without `$`, as in `if seen == 1`.
+pub(crate) fn parse_condition(s: &str) -> Result<Condition, String> {
+    const OPERATORS: [(&str, Operator); 6] = [
+        ("==", Operator::Equal),
+        ("!=", Operator::NotEqual),
+        ("<=", Operator::LessEqual),
+        (">=", Operator::GreaterEqual),
+        ("<", Operator::Less),
+        (">", Operator::Greater),
+    ];
+    let (pos, token, op) = OPERATORS.iter()
+        .filter_map(|&(token, op)| Some((s.find(token)?, token, op)))
+        // Earliest operator, the longer one where `<` and `<=` start at the same place.
+        .min_by_key(|&(pos, token, _)| (pos, std::cmp::Reverse(token.len())))
+        .ok_or_else(|| format!("missing comparison in condition `{}`", s))?;
+
+    let (lhs, rhs) = (s[..pos].trim(), s[pos + token.len()..].trim());
+    if lhs.is_empty() {
+        return Err(format!("missing left side in condition `{}`", s));
+    }
+    let lhs = match parse_value(lhs) {
+        Expr::Const(_) | Expr::Var(_) => Expr::Var(VarOrConst { is_ref: true, ..parse_var_ref(lhs)? }),
+        expr => expr,
+    };
+    Ok((lhs, op, parse_value(rhs)))
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Operand(&'a str),
+    Op(BinaryOp),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let token = match c {
+            '+' => Token::Op(BinaryOp::Add),
+            '-' => Token::Op(BinaryOp::Sub),
+            '*' => Token::Op(BinaryOp::Mul),
+            '/' => Token::Op(BinaryOp::Div),
+            '%' => Token::Op(BinaryOp::Rem),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            _ => {
+                let end = rest
+                    .find(|c: char| c.is_ascii_whitespace() || "+-*/%()".contains(c))
+                    .unwrap_or(rest.len());
+                Token::Operand(&rest[..end])
+            }
+        };
+        let len = match token {
+            Token::Operand(operand) => operand.len(),
+            _ => 1,
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+// Parses arithmetic like `$a + $b * (2 - $c)`. `$name` refers to a variable, anything
+// else is a constant.
+pub(crate) fn parse_expr(s: &str) -> Result<Expr, String> {
+    let tokens = tokenize(s);
+    let mut pos = 0;
+    let expr = parse_sum(&tokens, &mut pos, 0)?;
+    match tokens.get(pos) {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?} in expression `{}`", token, s)),
+    }
+}
+
+fn parse_sum(tokens: &[Token], pos: &mut usize, depth: usize) -> Result<Expr, String> {
+    let mut lhs = parse_product(tokens, pos, depth)?;
+    while let Some(&Token::Op(op @ (BinaryOp::Add | BinaryOp::Sub))) = tokens.get(*pos) {
+        *pos += 1;
+        let rhs = parse_product(tokens, pos, depth)?;
+        lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+    }
+    Ok(lhs)
+}
+
+fn parse_product(tokens: &[Token], pos: &mut usize, depth: usize) -> Result<Expr, String> {
+    let mut lhs = parse_operand(tokens, pos, depth)?;
+    while let Some(&Token::Op(op @ (BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem))) = tokens.get(*pos) {
+        *pos += 1;
+        let rhs = parse_operand(tokens, pos, depth)?;
+        lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+    }
+    Ok(lhs)
+}
+
+// Bounds the recursion on parentheses and negation, so no script can overflow the stack.
+const MAX_NESTING: usize = 64;
+
+fn parse_operand(tokens: &[Token], pos: &mut usize, depth: usize) -> Result<Expr, String> {
+    if depth > MAX_NESTING {
+        return Err("expression nested too deeply".to_string());
+    }
+    let token = tokens.get(*pos).ok_or("unexpected end of expression")?;
+    *pos += 1;
+    match token {
+        Token::Operand(operand) if operand.starts_with('$') => Ok(Expr::Var(parse_var_ref(operand)?)),
+        // Numbers in arithmetic are numbers, leading zeros or not.
+        Token::Operand(operand) => Ok(Expr::Const(operand.parse().map_or_else(|_| Value::Str(operand.to_string()), Value::Int))),
+        Token::Op(BinaryOp::Sub) => {
+            let operand = parse_operand(tokens, pos, depth + 1)?;
+            Ok(Expr::Binary(Box::new(Expr::Const(Value::Int(0))), BinaryOp::Sub, Box::new(operand)))
+        }
+        Token::Open => {
+            let expr = parse_sum(tokens, pos, depth + 1)?;
+            match tokens.get(*pos) {
+                Some(Token::Close) => {
+                    *pos += 1;
+                    Ok(expr)
+                }
+                _ => Err("missing `)` in expression".to_string()),
+            }
+        }
+        token => Err(format!("unexpected {:?} in expression", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryOp, Expr, parse_expr, parse_value};
+    use crate::Value;
+
+    #[test]
+    fn precedence() {
+        let expr = parse_expr("$a + $b * (2 - 1)").unwrap();
+        match expr {
+            Expr::Binary(lhs, BinaryOp::Add, rhs) => {
+                assert!(matches!(*lhs, Expr::Var(_)));
+                assert!(matches!(*rhs, Expr::Binary(_, BinaryOp::Mul, _)));
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+        assert!(parse_expr("1 +").is_err());
+        assert!(parse_expr("(1 + 2").is_err());
+        assert!(matches!(parse_value("bg-01.png"), Expr::Const(_)));
+        assert!(matches!(parse_value("$a-1"), Expr::Binary(..)));
+    }
+
+    #[test]
+    fn evaluates_only_explicit_arithmetic() {
+        assert!(matches!(parse_value("12-25"), Expr::Const(Value::Str(s)) if s == "12-25"));
+        assert!(matches!(parse_value("2*3"), Expr::Const(Value::Str(s)) if s == "2*3"));
+        assert!(matches!(parse_value("(12-25)"), Expr::Binary(_, BinaryOp::Sub, _)));
+        assert!(matches!(parse_value("$a + 007"), Expr::Binary(_, _, rhs) if matches!(*rhs, Expr::Const(Value::Int(7)))));
+        assert!(matches!(parse_value("7"), Expr::Const(Value::Int(7))));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::{EngineState, Script, ScriptOptions, StepResult, parse, step};
+
+// Entry points of the targets in `fuzz/`, which can't reach the parser and the
+// script cache directly. Neither may panic, whatever the input.
+
+pub fn parse_script(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    for vnds_compat in [false, true] {
+        let options = ScriptOptions { vnds_compat, ..Default::default() };
+        if let Ok(emitter) = parse(&text, None, &options) {
+            let _ = emitter.into_script();
+        }
+    }
+}
+
+const MAX_STEPS: usize = 10_000;
+
+// Runs a compiled script, given as JSON, as the game's `main.scr`.
+pub fn run_script(data: &[u8]) {
+    let script: Script = match serde_json::from_slice(data) {
+        Ok(script) => script,
+        Err(_) => return,
+    };
+    // No game files exist there, so every other script fails to load.
+    let directory = std::env::temp_dir().join("madenon-fuzz-missing");
+    let mut state = EngineState::new(directory);
+    let name = state.symbols.intern("main.scr");
+    state.cache_script(name, script);
+    if state.load_script("main.scr").is_err() {
+        return;
+    }
+    for _ in 0..MAX_STEPS {
+        match step(&mut state) {
+            StepResult::Exit => break,
+            StepResult::Jump(file) => {
+                if state.load_script(&file).is_err() {
+                    break;
+                }
+            }
+            StepResult::Choice(choices, _) => state.set_choice(choices.len().saturating_sub(1)),
+            _ => (),
+        }
+    }
+}
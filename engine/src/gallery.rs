@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::ResourceProvider;
+
+// Images which unlock a gallery entry once shown, as paths relative to the game
+// directory like `CG/ev01.png`, in the order the gallery lists them.
+pub const GALLERY_FILE: &str = "gallery.json";
+
+#[derive(Default)]
+pub(crate) struct Gallery {
+    images: Vec<String>,
+}
+
+impl Gallery {
+    pub(crate) fn load(directory: &Path, resources: &dyn ResourceProvider) -> Self {
+        let file = directory.join(GALLERY_FILE);
+        if !resources.is_file(&file) {
+            return Self::default();
+        }
+        let images: Result<Vec<String>, std::io::Error> = resources.read(&file)
+            .and_then(|data| Ok(serde_json::from_slice(&data)?));
+        match images {
+            Ok(images) => Self { images },
+            Err(e) => {
+                warn!(file = %file.display(), %e, "invalid gallery, no images will be unlocked");
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, image: &str) -> bool {
+        self.images.iter().any(|i| i == image)
+    }
+
+    pub(crate) fn images(&self) -> &[String] {
+        &self.images
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::manifest::{GameManifest, scripts_dir};
+use crate::{Instr, Label, Script, ScriptOptions, load_script};
+
+// Run of instructions without any control flow into or out of its middle.
+struct Block {
+    script: String,
+    start: usize,
+    // Label the block starts at, if any.
+    label: Option<String>,
+    title: String,
+}
+
+enum Target {
+    Block(String),
+    // Script jumped to, and the label within it.
+    Script(String, Option<String>),
+}
+
+// Control flow between the blocks of one or more scripts, for writers to see the
+// structure of routes. Rendered with Graphviz, see `to_dot`.
+pub struct FlowGraph {
+    blocks: Vec<Block>,
+    // From node id, with an optional edge label.
+    edges: Vec<(String, Target, &'static str)>,
+    // Scripts which are jumped to but aren't part of the graph.
+    external: BTreeSet<String>,
+}
+
+impl FlowGraph {
+    pub fn for_script(
+        directory: impl AsRef<Path>,
+        name: &str,
+        options: &ScriptOptions,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let script = load_script(scripts_dir(directory.as_ref()).join(name), options)?;
+        let mut graph = FlowGraph { blocks: vec![], edges: vec![], external: BTreeSet::new() };
+        for target in graph.add_script(name, &script) {
+            graph.external.insert(target);
+        }
+        Ok(graph)
+    }
+
+    // Follows `jump`s from the entry script through the whole game.
+    pub fn for_game(
+        directory: impl AsRef<Path>,
+        options: &ScriptOptions,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let manifest = GameManifest::load(directory.as_ref());
+        let scripts_dir = manifest.scripts_dir(directory.as_ref());
+        let mut graph = FlowGraph { blocks: vec![], edges: vec![], external: BTreeSet::new() };
+        let mut visited = BTreeSet::new();
+        let mut pending = VecDeque::from(vec![manifest.entry_script]);
+        while let Some(name) = pending.pop_front() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if !options.resources().is_file(&scripts_dir.join(&name)) {
+                graph.external.insert(name);
+                continue;
+            }
+            let script = load_script(scripts_dir.join(&name), options)?;
+            pending.extend(graph.add_script(&name, &script));
+        }
+        Ok(graph)
+    }
+
+    // Adds the blocks of a script, and returns the scripts it jumps to.
+    fn add_script(&mut self, name: &str, script: &Script) -> Vec<String> {
+        let code = &script.code;
+        let mut starts: BTreeSet<usize> = script.labels.values().copied().collect();
+        starts.insert(0);
+        for (pc, instr) in code.iter().enumerate() {
+            match instr {
+                Instr::branch(_, _, _, target) | Instr::goto(Label::Offset(target)) => {
+                    starts.insert(pc + 1);
+                    starts.insert(*target);
+                }
+                Instr::jump(..) | Instr::endscript => {
+                    starts.insert(pc + 1);
+                }
+                _ => (),
+            }
+        }
+        starts.retain(|&start| start < code.len());
+
+        let id = |pc: usize| format!("{}:{}", name, pc);
+        let starts: Vec<usize> = starts.into_iter().collect();
+        let mut jumps = vec![];
+        for (idx, &start) in starts.iter().enumerate() {
+            let end = starts.get(idx + 1).copied().unwrap_or(code.len());
+            let label = script.labels
+                .iter()
+                .find(|(_, &pc)| pc == start)
+                .map(|(label, _)| label_name(label));
+            let text = code[start..end].iter().find_map(|instr| match instr {
+                Instr::text(_, text, _) if !text.is_empty() => Some(text.chars().take(30).collect()),
+                _ => None,
+            });
+            self.blocks.push(Block {
+                script: name.to_string(),
+                start,
+                title: label.clone().or(text).unwrap_or_else(|| format!("pc {}", start)),
+                label,
+            });
+
+            let edge = |to: usize, kind| (id(start), Target::Block(id(to)), kind);
+            match &code[end - 1] {
+                Instr::branch(_, _, _, target) => {
+                    if end < code.len() {
+                        self.edges.push(edge(end, "then"));
+                    }
+                    if *target < code.len() {
+                        self.edges.push(edge(*target, "else"));
+                    }
+                }
+                Instr::goto(Label::Offset(target)) => {
+                    if *target < code.len() {
+                        self.edges.push(edge(*target, ""));
+                    }
+                }
+                Instr::jump(target, label) => {
+                    let to = Target::Script(target.clone(), label.as_ref().map(label_name));
+                    self.edges.push((id(start), to, "jump"));
+                    jumps.push(target.clone());
+                }
+                Instr::endscript => (),
+                _ if end < code.len() => self.edges.push(edge(end, "")),
+                _ => (),
+            }
+        }
+        jumps
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph flow {\n    node [shape=box];\n");
+        let scripts: BTreeSet<&str> = self.blocks.iter().map(|block| block.script.as_str()).collect();
+        for script in scripts {
+            writeln!(dot, "    subgraph \"cluster_{}\" {{", escape(script)).unwrap();
+            writeln!(dot, "        label=\"{}\";", escape(script)).unwrap();
+            for block in self.blocks.iter().filter(|block| block.script == script) {
+                writeln!(dot, "        \"{}:{}\" [label=\"{}\"];",
+                    escape(script), block.start, escape(&block.title)).unwrap();
+            }
+            dot.push_str("    }\n");
+        }
+        for script in &self.external {
+            writeln!(dot, "    \"{}\" [shape=ellipse];", escape(script)).unwrap();
+        }
+        for (from, to, kind) in &self.edges {
+            let to = match to {
+                Target::Block(id) => id.clone(),
+                Target::Script(script, label) => self.resolve(script, label.as_deref()),
+            };
+            write!(dot, "    \"{}\" -> \"{}\"", escape(from), escape(&to)).unwrap();
+            if !kind.is_empty() {
+                write!(dot, " [label=\"{}\"]", kind).unwrap();
+            }
+            dot.push_str(";\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Node id of the block a jump lands on.
+    fn resolve(&self, script: &str, label: Option<&str>) -> String {
+        if self.external.contains(script) {
+            return script.to_string();
+        }
+        let start = self.blocks
+            .iter()
+            .find(|block| block.script == script && label.is_some() && block.label.as_deref() == label)
+            .map_or(0, |block| block.start);
+        format!("{}:{}", script, start)
+    }
+}
+
+fn label_name(label: &Label) -> String {
+    match label {
+        Label::Offset(x) | Label::Indexed(x) => format!("@{}", x),
+        Label::Named(x) => x.clone(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlowGraph;
+    use crate::testing::TestGame;
+
+    #[test]
+    fn follows_jumps() {
+        let game_dir = TestGame::new("graph");
+        game_dir.script("main.scr", "\
+            text start\n\
+            if x == 1\n\
+            jump a.scr\n\
+            fi\n\
+            jump b.scr\n");
+        game_dir.script("a.scr", "text a\n");
+
+        let dot = FlowGraph::for_game(&game_dir, &Default::default()).unwrap().to_dot();
+
+        assert!(dot.contains("\"main.scr:0\" -> \"main.scr:2\" [label=\"then\"];"));
+        assert!(dot.contains("\"main.scr:0\" -> \"main.scr:3\" [label=\"else\"];"));
+        assert!(dot.contains("\"main.scr:2\" -> \"a.scr:0\" [label=\"jump\"];"));
+        assert!(dot.contains("\"main.scr:3\" -> \"b.scr\" [label=\"jump\"];"));
+    }
+}
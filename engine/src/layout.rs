@@ -0,0 +1,245 @@
+use std::ops::Range;
+use std::str::FromStr;
+
+use unicode_linebreak::{BreakOpportunity, linebreaks};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum LayoutDirection {
+    #[default]
+    LeftToRight,
+    // Only changes the alignment, text stays in logical order and the renderer
+    // reorders it for display.
+    RightToLeft,
+    // Columns are read top to bottom, starting from the rightmost one.
+    Vertical,
+}
+
+impl LayoutDirection {
+    pub fn is_right_aligned(self) -> bool {
+        !matches!(self, LayoutDirection::LeftToRight)
+    }
+}
+
+impl FromStr for LayoutDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ltr" => Ok(LayoutDirection::LeftToRight),
+            "rtl" => Ok(LayoutDirection::RightToLeft),
+            "vertical" => Ok(LayoutDirection::Vertical),
+            _ => Err(format!("unknown layout direction: {}", s)),
+        }
+    }
+}
+
+// How much text fits in the frontend's text box at once.
+#[derive(Debug, Copy, Clone)]
+pub struct Pagination {
+    pub line_len: usize,
+    pub lines: usize,
+    pub direction: LayoutDirection,
+}
+
+impl Pagination {
+    // Splits text which doesn't fit into pages which do, always at least one.
+    pub fn split(&self, text: &str) -> Vec<String> {
+        self.page_ranges(text).into_iter().map(|range| text[range].to_string()).collect()
+    }
+
+    // Byte ranges of the pages in `text`, always at least one.
+    pub fn page_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        let lines = layout_lines(text, self.line_len.max(1), self.direction);
+        let mut pages: Vec<_> = lines
+            .chunks(self.lines.max(1))
+            .map(|lines| lines[0].start..lines[lines.len() - 1].end)
+            .collect();
+        if pages.len() <= 1 {
+            pages.clear();
+            pages.push(0..text.len());
+        }
+        pages
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    direction: LayoutDirection,
+    text: String,
+    // Byte ranges of the lines (or columns for vertical text) in logical order.
+    lines: Vec<Range<usize>>,
+    line_len: usize,
+}
+
+impl TextLayout {
+    pub fn new(text: &str, line_len: usize, direction: LayoutDirection) -> Self {
+        let line_len = line_len.max(1);
+        let lines = layout_lines(text, line_len, direction);
+        Self { direction, text: text.to_string(), lines, line_len }
+    }
+
+    pub fn direction(&self) -> LayoutDirection {
+        self.direction
+    }
+
+    // Counted in graphemes, so an accented letter appears at once.
+    pub fn len(&self) -> usize {
+        self.lines.iter().map(|line| self.text[line.clone()].graphemes(true).count()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Renders the first `visible` graphemes, one row per line.
+    pub fn render(&self, visible: usize) -> String {
+        let mut remaining = visible;
+        let mut shown = vec![];
+        for line in &self.lines {
+            if remaining == 0 {
+                break;
+            }
+            let graphemes: Vec<_> = self.text[line.clone()].graphemes(true).take(remaining).collect();
+            remaining -= graphemes.len();
+            shown.push(graphemes);
+        }
+
+        match self.direction {
+            LayoutDirection::LeftToRight | LayoutDirection::RightToLeft => shown
+                .iter()
+                .map(|line| line.concat())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            LayoutDirection::Vertical => {
+                // Columns which haven't started yet are omitted, so with right alignment
+                // the already visible ones stay in place.
+                let rows = self.lines.first().map_or(0, |line| self.text[line.clone()].graphemes(true).count());
+                let mut rendered = vec![];
+                for row in 0..self.line_len.min(rows) {
+                    let row: String = shown
+                        .iter()
+                        .rev()
+                        .map(|column| column.get(row).copied().unwrap_or("\u{3000}"))
+                        .collect();
+                    rendered.push(row);
+                }
+                rendered.join("\n")
+            }
+        }
+    }
+}
+
+fn layout_lines(text: &str, line_len: usize, direction: LayoutDirection) -> Vec<Range<usize>> {
+    match direction {
+        // Vertical text is broken anywhere, like Japanese usually is.
+        LayoutDirection::Vertical => {
+            let starts: Vec<_> = text.grapheme_indices(true).map(|(idx, _)| idx).collect();
+            starts
+                .chunks(line_len)
+                .enumerate()
+                .map(|(n, chunk)| chunk[0]..starts.get((n + 1) * line_len).copied().unwrap_or(text.len()))
+                .collect()
+        }
+        _ => wrap(text, line_len),
+    }
+}
+
+// Wraps at line break opportunities, which fall between words and also between
+// CJK characters, so text without spaces wraps too.
+fn wrap(text: &str, line_len: usize) -> Vec<Range<usize>> {
+    let width = |range: Range<usize>| text[range].trim_end().graphemes(true).count();
+    let mut lines = vec![];
+    let mut start = skip_spaces(text, 0);
+    let mut fits = None;
+    for (end, opportunity) in linebreaks(text) {
+        if end <= start {
+            continue;
+        }
+        while width(start..end) > line_len {
+            match fits.take() {
+                Some(fit) => {
+                    lines.push(start..trim_end(text, start..fit));
+                    start = skip_spaces(text, fit);
+                }
+                // A word which doesn't fit on its own line is broken where it overflows.
+                None => {
+                    let fit = text[start..end]
+                        .grapheme_indices(true)
+                        .nth(line_len)
+                        .map_or(end, |(idx, _)| start + idx);
+                    lines.push(start..fit);
+                    start = fit;
+                }
+            }
+        }
+        fits = Some(end);
+        if opportunity == BreakOpportunity::Mandatory {
+            if end > start && width(start..end) > 0 {
+                lines.push(start..trim_end(text, start..end));
+            }
+            start = skip_spaces(text, end);
+            fits = None;
+        }
+    }
+    lines
+}
+
+fn skip_spaces(text: &str, from: usize) -> usize {
+    text.len() - text[from..].trim_start().len()
+}
+
+fn trim_end(text: &str, range: Range<usize>) -> usize {
+    range.start + text[range].trim_end().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LayoutDirection, Pagination, TextLayout};
+
+    #[test]
+    fn layouts() {
+        let ltr = TextLayout::new("ab cd efgh", 5, LayoutDirection::LeftToRight);
+        assert_eq!(ltr.render(100), "ab cd\nefgh");
+        assert_eq!(ltr.render(7), "ab cd\nef");
+
+        let rtl = TextLayout::new("שלום עולם", 5, LayoutDirection::RightToLeft);
+        assert_eq!(rtl.render(100), "שלום\nעולם");
+
+        let vertical = TextLayout::new("あいうえお", 2, LayoutDirection::Vertical);
+        assert_eq!(vertical.render(100), "おうあ\n\u{3000}えい");
+        assert_eq!(vertical.render(3), "うあ\n\u{3000}い");
+    }
+
+    #[test]
+    fn wraps_without_spaces() {
+        let cjk = TextLayout::new("今日はいい天気ですね。", 4, LayoutDirection::LeftToRight);
+        assert_eq!(cjk.render(100), "今日はい\nい天気で\nすね。");
+
+        // Closing punctuation doesn't start a line.
+        let punctuation = TextLayout::new("あいう。えお", 3, LayoutDirection::LeftToRight);
+        assert_eq!(punctuation.render(100), "あい\nう。え\nお");
+
+        let long = TextLayout::new("abcdefgh ij", 3, LayoutDirection::LeftToRight);
+        assert_eq!(long.render(100), "abc\ndef\ngh\nij");
+    }
+
+    #[test]
+    fn counts_graphemes() {
+        let combining = TextLayout::new("cafe\u{301} ole\u{301}", 4, LayoutDirection::LeftToRight);
+        assert_eq!(combining.len(), 7);
+        assert_eq!(combining.render(4), "cafe\u{301}");
+        assert_eq!(combining.render(100), "cafe\u{301}\nole\u{301}");
+    }
+
+    #[test]
+    fn paginates() {
+        let pagination = Pagination { line_len: 5, lines: 2, direction: LayoutDirection::LeftToRight };
+        assert_eq!(pagination.split("ab cd efgh ij kl"), vec!["ab cd efgh", "ij kl"]);
+        assert_eq!(pagination.split("ab cd"), vec!["ab cd"]);
+        assert_eq!(pagination.split(""), vec![""]);
+
+        let vertical = Pagination { line_len: 2, lines: 2, direction: LayoutDirection::Vertical };
+        assert_eq!(vertical.split("あいうえお"), vec!["あいうえ", "お"]);
+    }
+}
@@ -1,36 +1,127 @@
 #![feature(str_split_as_str)]
 
-use std::collections::HashMap;
-use std::io::BufRead;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{debug, trace, warn};
+
+use crate::backlog::Backlog;
+use crate::cache::ScriptCache;
+use crate::expr::{Condition, Expr, parse_condition, parse_value};
+use crate::gallery::Gallery;
+use crate::markup::{parse_markup, split_spans};
+use crate::persistent::PersistentStore;
+use crate::preprocess::Preprocessor;
+use crate::saves::{SAVE_VERSION, parse_save, read_save, write_save};
+use crate::slots::ImageSlots;
+use crate::symbol::{Symbol, SymbolTable};
+use crate::translation::Translation;
+
+pub use auto::{AutoMode, ReadingTimes, VoiceStatus};
+pub use backlog::{BacklogEntry, DEFAULT_BACKLOG_LEN};
+pub use bundle::{export_save, import_save};
+pub use crypt::{ScriptDecryptor, XorKey};
+pub use dedup::{TextCluster, TextOccurrence, find_duplicate_text};
+pub use encoding::ScriptEncoding;
+pub use error::{GameError, SaveError};
+pub use gallery::GALLERY_FILE;
+pub use graph::FlowGraph;
+pub use layout::{LayoutDirection, Pagination, TextLayout};
+pub use lineid::{LINE_IDS_FILE, LineIds};
+pub use manifest::{COPYRIGHTED_TAG, Directories, Gaiji, GameManifest, MANIFEST_FILE, SPOILER_TAG};
+pub use markup::{INLINE_IMAGE, TextSpan};
+pub use observer::EngineObserver;
+pub use remap::{REMAP_FILE, RemapTable};
+pub use resource::{ArchiveResources, FileSystem, LayeredResources, ResourceProvider};
+pub use saves::{AUTOSAVE_SLOT, Autosave, SaveFormat, SaveInfo, SaveManager};
+pub use slots::{IMAGE_SLOTS_FILE, ImageSlot};
+pub use stats::{ScriptStats, analyze};
+pub use validate::{Diagnostic, lint, validate};
+pub use value::Value;
+
+mod anchor;
+mod auto;
+mod backlog;
+mod bundle;
+mod cache;
+mod crypt;
+mod dedup;
+mod encoding;
+mod error;
+mod expr;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod gallery;
+mod graph;
+mod layout;
+mod lineid;
+mod manifest;
+mod markup;
+mod observer;
+mod persistent;
+mod preprocess;
+#[cfg(test)]
+mod properties;
+mod remap;
+mod resource;
+mod saves;
+mod slots;
+mod stats;
+mod symbol;
+#[cfg(test)]
+mod testing;
+mod translation;
+mod validate;
+mod value;
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum Instr {
     cleartext,
-    setvar(VarOrConst, String),
-    gsetvar(VarOrConst, String),
+    setvar(VarOrConst, Expr),
+    gsetvar(VarOrConst, Expr),
     bgload(VarOrConst, Option<usize>),
-    setimg(VarOrConst, usize, usize),
+    // The slot is picked from the variable name unless one is given.
+    setimg(VarOrConst, usize, usize, Option<String>),
     delay(usize),
-    branch(VarOrConst, Operator, String, usize),
-    text(Option<String>, String),
+    branch(Expr, Operator, Expr, usize),
+    // The flag tells whether the player has to advance past the line.
+    text(Option<String>, String, bool),
     goto(Label),
-    sound(String, Option<usize>),
-    music(String),
-    choice(Vec<VarOrConst>),
-    jump(String),
+    sound(String, Option<i32>),
+    music(String, Option<usize>),
+    voice(String),
+    // Fade out time in milliseconds.
+    musicstop(Option<usize>),
+    // Options are only shown when their condition, if any, holds.
+    choice(Vec<(VarOrConst, Option<Condition>)>),
+    jump(String, Option<Label>),
+    title(String),
+    // Chapter or scene the script is in, for labelling saves.
+    chapter(String),
+    endscript,
+    ending(String),
+    // `None` clears all variables.
+    unsetvar(Option<VarOrConst>),
+    gunsetvar(Option<VarOrConst>),
+    addvar(VarOrConst, i64),
+    random(VarOrConst, i64, i64),
 }
 
 #[derive(Eq, PartialEq)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 enum Operator {
     Equal,
     NotEqual,
     Less,
     LessEqual,
+    Greater,
+    GreaterEqual,
 }
 
 impl std::fmt::Debug for Operator {
@@ -40,16 +131,22 @@ impl std::fmt::Debug for Operator {
             Operator::NotEqual => "!=",
             Operator::Less => "<",
             Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
         })?;
         Ok(())
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct VarOrConst {
     is_ref: bool,
     name: String,
-    index: Option<usize>,
+    // Either a constant like `[3]` or another variable like `[$i]`.
+    index: Option<Box<VarOrConst>>,
+    // Interned name, set for variables once the script is loaded by an `EngineState`.
+    #[serde(skip)]
+    symbol: Option<Symbol>,
 }
 
 impl std::fmt::Debug for VarOrConst {
@@ -58,8 +155,8 @@ impl std::fmt::Debug for VarOrConst {
             write!(f, "$")?;
         }
         write!(f, "{}", self.name)?;
-        if let Some(idx) = self.index {
-            write!(f, "[{}]", idx)?;
+        if let Some(idx) = &self.index {
+            write!(f, "[{:?}]", idx)?;
         }
         Ok(())
     }
@@ -75,24 +172,65 @@ fn parse_text(s: &str) -> (Option<String>, String) {
     (None, unescape(s))
 }
 
-fn parse_var_ref(s: &str) -> VarOrConst {
-    let (dollar, s) = match s.strip_prefix("$") {
+fn parse_var_ref(s: &str) -> Result<VarOrConst, String> {
+    parse_nested_var_ref(s, 0)
+}
+
+const MAX_SUBSCRIPT_DEPTH: usize = 16;
+
+fn parse_nested_var_ref(s: &str, depth: usize) -> Result<VarOrConst, String> {
+    if depth > MAX_SUBSCRIPT_DEPTH {
+        return Err(format!("subscripts nested too deeply in `{}`", s));
+    }
+    let (dollar, rest) = match s.strip_prefix("$") {
         Some(x) => (true, x),
         None => (false, s),
     };
 
-    let (name, index) = if let Some(iks) = s.strip_suffix("]") {
-        let (name, x) = iks.split_once("[").unwrap();
+    let (name, index) = if let Some(iks) = rest.strip_suffix("]") {
+        let (name, x) = iks.split_once("[").ok_or_else(|| format!("missing `[` in `{}`", s))?;
         (name, Some(x))
     } else {
-        (s, None)
+        (rest, None)
     };
 
-    VarOrConst {
+    Ok(VarOrConst {
         is_ref: dollar,
         name: name.to_string(),
-        index: index.map(|x| x.parse().unwrap()),
-    }
+        index: match index {
+            Some(x) => Some(Box::new(parse_nested_var_ref(x, depth + 1)?)),
+            None => None,
+        },
+        symbol: None,
+    })
+}
+
+// Nearest named label at or before `pc`, the first by name of several at the same place.
+fn resume_label(script: &Script, pc: usize) -> Option<&str> {
+    script.labels
+        .iter()
+        .filter_map(|(label, &at)| match label {
+            Label::Named(name) if at <= pc => Some((name.as_str(), at)),
+            _ => None,
+        })
+        .max_by(|(a, a_at), (b, b_at)| a_at.cmp(b_at).then_with(|| b.cmp(a)))
+        .map(|(name, _)| name)
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number `{}`", s))
+}
+
+// Stable across platforms and compiler versions, unlike `DefaultHasher`, so it's safe to persist.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(0xcbf29ce484222325, bytes)
+}
+
+// Carries on hashing after `hash`, as if `bytes` followed what it was taken from.
+fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
 }
 
 fn strip(s: &str, c: char) -> &str {
@@ -122,10 +260,24 @@ fn unescape(s: &str) -> String {
     out
 }
 
+// A `menu` block being compiled into a choice followed by one branch per option.
+struct Menu {
+    choice: usize,
+    options: Vec<(VarOrConst, Option<Condition>)>,
+    // Branch of the option whose body is being emitted.
+    current_option: Option<usize>,
+    // Gotos at the end of each option body, which jump past the menu.
+    exits: Vec<usize>,
+}
+
 struct Emitter {
     code: Vec<Instr>,
-    last_branch: Option<usize>,
+    // Branches of the `if`s not closed yet, innermost last.
+    branches: Vec<usize>,
     labels: HashMap<Label, usize>,
+    menus: Vec<Menu>,
+    // `fnv1a` of each `include`d file, in the order they were read.
+    included: Vec<u64>,
 }
 
 impl Emitter {
@@ -133,7 +285,9 @@ impl Emitter {
         Self {
             labels: HashMap::new(),
             code: vec![],
-            last_branch: None,
+            branches: vec![],
+            menus: vec![],
+            included: vec![],
         }
     }
 
@@ -142,42 +296,99 @@ impl Emitter {
     }
 
     fn begin_branch(&mut self) {
-        self.last_branch = Some(self.code.len());
+        self.branches.push(self.code.len());
     }
 
-    fn end_branch(&mut self) {
+    fn end_branch(&mut self) -> Result<(), String> {
         let next_instr = self.code.len();
-        let branch_instr = self.last_branch.unwrap();
-        match self.code[branch_instr] {
-            Instr::branch(_, _, _, ref mut else_target) => {
-                *else_target = next_instr;
-            }
-            _ => unimplemented!(),
+        let branch_instr = self.branches.pop().ok_or("fi without a matching if")?;
+        if let Instr::branch(_, _, _, ref mut else_target) = self.code[branch_instr] {
+            *else_target = next_instr;
+        }
+        Ok(())
+    }
+
+    fn begin_menu(&mut self) {
+        self.menus.push(Menu {
+            choice: self.code.len(),
+            options: vec![],
+            current_option: None,
+            exits: vec![],
+        });
+        self.emit(Instr::choice(vec![]));
+    }
+
+    fn begin_option(&mut self, text: &str) -> Result<(), String> {
+        let menu = self.menus.last_mut().ok_or("option outside of a menu")?;
+        if menu.current_option.is_some() {
+            return Err("option inside of another option".to_string());
+        }
+        menu.options.push(parse_choice_option(text)?);
+        menu.current_option = Some(self.code.len());
+        let selected = VarOrConst {
+            is_ref: true,
+            name: "selected".to_string(),
+            index: None,
+            symbol: None,
+        };
+        let index = menu.options.len() as i64;
+        self.emit(Instr::branch(Expr::Var(selected), Operator::Equal, Expr::Const(Value::Int(index)), 0));
+        Ok(())
+    }
+
+    fn end_option(&mut self) -> Result<(), String> {
+        let menu = self.menus.last_mut().ok_or("end outside of a menu")?;
+        let branch_instr = menu.current_option.take().ok_or("end outside of an option")?;
+        let exit = self.code.len();
+        menu.exits.push(exit);
+        self.emit(Instr::goto(Label::Offset(0)));
+
+        let next_instr = self.code.len();
+        if let Instr::branch(_, _, _, ref mut else_target) = self.code[branch_instr] {
+            *else_target = next_instr;
+        }
+        Ok(())
+    }
+
+    fn end_menu(&mut self) -> Result<(), String> {
+        let menu = self.menus.pop().ok_or("endmenu outside of a menu")?;
+        if menu.current_option.is_some() {
+            return Err("endmenu inside of an option".to_string());
+        }
+        let next_instr = self.code.len();
+        self.code[menu.choice] = Instr::choice(menu.options);
+        for exit in menu.exits {
+            self.code[exit] = Instr::goto(Label::Offset(next_instr));
         }
+        Ok(())
     }
 
     fn make_label(&mut self, label: Label) {
         self.labels.insert(label, self.code.len());
     }
 
-    fn into_script(mut self) -> Script {
+    fn into_script(mut self) -> Result<Script, String> {
+        if !self.branches.is_empty() {
+            return Err(format!("{} if without a matching fi", self.branches.len()));
+        }
         for inst in self.code.iter_mut() {
             match inst {
+                Instr::goto(Label::Offset(_)) => (),
                 Instr::goto(ref mut target) => {
                     *target = match self.labels.get(target) {
                         Some(x) => Label::Offset(*x),
-                        None => panic!("unknown label {:?}", target),
+                        None => return Err(format!("unknown label {:?}", target)),
                     };
                 }
                 _ => ()
             }
         }
 
-        Script { code: self.code }
+        Ok(Script { code: self.code, labels: self.labels, hash: 0 })
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 enum Label {
     Offset(usize),
     Indexed(usize),
@@ -206,7 +417,10 @@ fn split_args(line: &str, limit: usize) -> Vec<&str> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{split_args, unescape};
+    use std::time::Duration;
+
+    use crate::{EngineObserver, EngineState, Instr, LayoutDirection, Pagination, SaveError, Script, ScriptOptions, StepResult, TextSpan, fnv1a, load_script, parse, parse_var_ref, split_args, step, unescape};
+    use crate::testing::TestGame;
 
     #[test]
     fn splitting() {
@@ -220,392 +434,2721 @@ mod tests {
         assert_eq!(unescape("My cousin\\'s voice is coming from the alarm clock."),
                    "My cousin\'s voice is coming from the alarm clock.")
     }
-}
-
-struct Script {
-    code: Vec<Instr>,
-}
 
-fn load_script(path: impl AsRef<Path>) -> Result<Script, Box<dyn std::error::Error>> {
-    let file = std::fs::File::open(path)?;
-    let reader = std::io::BufReader::new(file);
+    #[test]
+    fn variable_subscripts() {
+        assert_eq!(format!("{:?}", parse_var_ref("$flags[3]").unwrap()), "$flags[3]");
+        assert_eq!(format!("{:?}", parse_var_ref("$flags[$i]").unwrap()), "$flags[$i]");
+        assert_eq!(format!("{:?}", parse_var_ref("$a[$b[$c]]").unwrap()), "$a[$b[$c]]");
+    }
 
-    let mut emitter = Emitter::new();
+    #[test]
+    fn vnds_compat() {
+        let script = "# comment\nsetvar x ~\nsetvar y + 2\ntext ~\nrandom r 1 6\nunknown 1 2\nendscript\n";
+        let options = ScriptOptions { vnds_compat: true, ..Default::default() };
+        let code = parse(script, None, &options).unwrap().code;
+        assert!(matches!(&code[..], [
+            Instr::unsetvar(Some(_)),
+            Instr::addvar(_, 2),
+            Instr::text(None, text, true),
+            Instr::random(_, 1, 6),
+            Instr::endscript,
+        ] if text.is_empty()));
+    }
 
-    for (lineno, line) in reader.lines().enumerate() {
-        let line = line?;
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+    #[test]
+    fn malformed_scripts_are_errors() {
+        let parse_script = |script: &str| -> Result<Script, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(parse(script, None, &ScriptOptions::default())?.into_script()?)
+        };
+        for script in ["fi\n", "setimg a.png x 0\n", "goto @x\n", "setvar a]", "delay 99999999999999999999\n", "option a\n", "endmenu\n", "unknown 1 2\n", "music a.ogg soon\n"] {
+            assert!(parse_script(script).is_err(), "{:?}", script);
         }
+        let nested = format!("setvar x{}1{} = 1\n", "[".repeat(100), "]".repeat(100));
+        assert!(parse_script(&nested).is_err());
+        let nested = format!("setvar x = {}1{}\n", "(".repeat(1000), ")".repeat(1000));
+        assert!(parse(&nested, None, &ScriptOptions::default()).is_ok());
+    }
 
-        let parts = split_args(line, 3);
-        match &parts[..] {
-            &["cleartext", ..] => {
-                emitter.emit(Instr::cleartext);
-            }
-            &["gsetvar", name, "=" | "-" | "+", value] => {
-                emitter.emit(Instr::gsetvar(
-                    parse_var_ref(name),
-                    unescape(value),
-                ));
-            }
-            &["setvar", name, "=" | "-" | "+", value] => {
-                emitter.emit(Instr::setvar(
-                    parse_var_ref(name),
-                    unescape(value),
-                ));
-            }
-            &["setvar", name, value] => {
-                emitter.emit(Instr::setvar(
-                    parse_var_ref(name),
-                    unescape(value),
-                ));
-            }
-            &["bgload", vref] => {
-                emitter.emit(Instr::bgload(
-                    parse_var_ref(vref),
-                    None,
-                ));
-            }
-            &["bgload", vref, time] => {
-                emitter.emit(Instr::bgload(
-                    parse_var_ref(vref),
-                    Some(time.parse().unwrap()),
-                ));
-            }
-            &["setimg", vref, x, y] => {
-                emitter.emit(Instr::setimg(
-                    parse_var_ref(vref),
-                    x.parse().unwrap(),
-                    y.parse().unwrap(),
-                ));
-            }
-            &["delay", delay] => {
-                emitter.emit(Instr::delay(
-                    delay.parse().unwrap(),
-                ))
-            }
-            &["if", vref, op, val] => {
-                emitter.begin_branch();
-                emitter.emit(Instr::branch(
-                    // TODO: this needs to be changed...
-                    VarOrConst { is_ref: true, ..parse_var_ref(vref) },
-                    match op {
-                        "==" => Operator::Equal,
-                        "!=" => Operator::NotEqual,
-                        "<" => Operator::Less,
-                        "<=" => Operator::LessEqual,
-                        op => panic!("unsupported op: {}", op),
-                    },
-                    val.to_string(),
-                    emitter.code.len(),
-                ));
-            }
-            &["fi"] => {
-                emitter.end_branch();
-            }
-            &["text", ..] => {
-                let x = line[4..].trim();
-                let (name, text) = parse_text(x);
-
-                emitter.emit(Instr::text(
-                    name,
-                    text,
-                ));
-            }
-            &["goto", label] => {
-                let label = if let Some(x) = label.strip_prefix('@') {
-                    Label::Indexed(x.parse().unwrap())
-                } else {
-                    Label::Named(label.to_string())
-                };
-
-                emitter.emit(Instr::goto(
-                    label
-                ));
-            }
-            &["label", ident] => {
-                if let Some(x) = ident.strip_prefix('@') {
-                    emitter.make_label(Label::Indexed(x.parse().unwrap()));
-                } else {
-                    emitter.make_label(Label::Named(ident.to_string()));
-                }
-            }
-            &["sound", file] => {
-                emitter.emit(Instr::sound(
-                    file.to_string(),
-                    None,
-                ));
-            }
-            &["sound", file, param] => {
-                emitter.emit(Instr::sound(
-                    file.to_string(),
-                    Some(param.parse().unwrap()),
-                ));
-            }
-            &["music", file] => {
-                emitter.emit(Instr::music(
-                    file.to_string(),
-                ));
-            }
-            &["choice", ..] => {
-                emitter.emit(Instr::choice(
-                    line[6..].trim_start().split("|").map(parse_var_ref).collect(),
-                ));
-            }
-            &["jump", target] => {
-                emitter.emit(Instr::jump(
-                    target.to_string(),
-                ));
-            }
-            _ => {
-                panic!("{}: {:?}", lineno + 1, parts);
+    #[test]
+    fn iterates_over_steps() {
+        let game_dir = TestGame::new("steps");
+        game_dir.script("main.scr", "setvar x = 1\ntext a\njump b.scr\n");
+        game_dir.script("b.scr", "choice c|d\nif $selected == 2\ntext d\nfi\njump c.scr\n");
+
+        let mut state = EngineState::new(&game_dir);
+        let mut steps = state.steps();
+        let mut texts = vec![];
+        while let Some(result) = steps.next() {
+            match result {
+                StepResult::Text(_, text, _, _) => texts.push(text),
+                StepResult::Choice(..) => steps.state().set_choice(1),
+                _ => (),
             }
         }
+
+        assert_eq!(texts, vec!["a", "d"]);
     }
-    Ok(emitter.into_script())
-}
 
-pub struct EngineState {
-    scripts: HashMap<String, Script>,
-    memory: HashMap<String, HashMap<usize, String>>,
-    pc: usize,
-    current_script: String,
-    directory: PathBuf,
-    last_music: Option<String>,
-    last_background: Option<PathBuf>,
-    last_main_image: Option<PathBuf>,
-    last_date_image: Option<PathBuf>,
-    pc_to_save: usize,
-}
+    #[test]
+    fn starts_new_game() {
+        let game_dir = TestGame::new("new-game");
+        game_dir.script("main.scr", "\
+            if $x == 1\n\
+            text again\n\
+            fi\n\
+            if $g == 1\n\
+            text cleared\n\
+            fi\n\
+            setvar x = 1\n\
+            gsetvar g = 1\n\
+            music bgm.ogg\n\
+            text end\n");
 
-#[derive(Serialize, Deserialize)]
-struct SerializedState {
-    memory: HashMap<String, HashMap<usize, String>>,
-    current_script: String,
-    pc: usize,
-    last_music: Option<String>,
-    last_background: Option<PathBuf>,
-    last_main_image: Option<PathBuf>,
-    last_date_image: Option<PathBuf>,
-}
+        let mut state = EngineState::new(&game_dir);
+        assert_eq!(state.steps().count(), 2);
+        state.new_game().unwrap();
+        let texts: Vec<_> = state.steps()
+            .filter_map(|result| match result {
+                StepResult::Text(_, text, _, _) => Some(text),
+                _ => None,
+            })
+            .collect();
 
-impl EngineState {
-    pub fn new(directory: impl Into<PathBuf>) -> Self {
-        let mut state = Self {
-            scripts: Default::default(),
-            memory: Default::default(),
-            pc: 0,
-            current_script: "main.scr".to_string(),
-            directory: directory.into(),
-            last_music: None,
-            last_background: None,
-            last_date_image: None,
-            last_main_image: None,
-            pc_to_save: 0,
-        };
-        state.load_script("main.scr");
-        state
+        assert_eq!(texts, vec!["cleared", "end"]);
     }
 
-    pub fn save(&self, file: impl AsRef<Path>) -> Result<(), std::io::Error> {
-        let serialized = SerializedState {
-            pc: self.pc_to_save,
-            last_music: self.last_music.clone(),
-            last_background: self.last_background.clone(),
-            current_script: self.current_script.clone(),
-            memory: self.memory.clone(),
-            last_date_image: self.last_date_image.clone(),
-            last_main_image: self.last_main_image.clone(),
-        };
-        let file = std::fs::File::create(file)?;
-        serde_json::to_writer_pretty(file, &serialized)?;
-        Ok(())
+    #[test]
+    fn keeps_globals_set_after_saving() {
+        let game_dir = TestGame::new("merge-globals");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "\
+            gsetvar a = 1\n\
+            text saved\n\
+            if $a == 2\n\
+            text kept a\n\
+            fi\n\
+            if $b == 1\n\
+            text kept b\n\
+            fi\n\
+            gsetvar a = 2\n\
+            gsetvar b = 1\n\
+            text later\n");
+
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        state.save(&save).unwrap();
+        state.run_until_interactive();
+        state.load(&save).unwrap();
+        let texts: Vec<_> = state.steps()
+            .filter_map(|result| match result {
+                StepResult::Text(_, text, _, _) => Some(text),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(texts, vec!["saved", "kept a", "kept b", "later"]);
+    }
+
+    #[test]
+    fn includes_nested_scripts() {
+        let game_dir = TestGame::new("includes");
+        let main = game_dir.script("main.scr", "text a\ninclude b.inc\ntext d\n");
+        game_dir.script("b.inc", "text b\ninclude c.inc\n");
+        let c = game_dir.script("c.inc", "text c\n");
+        let before = load_script(&main, &ScriptOptions::default()).unwrap();
+        let texts: Vec<_> = before.code
+            .iter()
+            .filter_map(|instr| match instr {
+                Instr::text(_, text, _) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, ["a", "b", "c", "d"]);
+
+        std::fs::write(&c, "text changed\n").unwrap();
+        let after = load_script(&main, &ScriptOptions::default()).unwrap();
+        assert_ne!(after.hash, before.hash);
     }
 
-    pub fn load(&mut self, file: impl AsRef<Path>) -> Result<Vec<StepResult>, std::io::Error> {
-        let file = std::fs::File::open(file)?;
-        let serialized: SerializedState = serde_json::from_reader(file)?;
+    #[test]
+    fn rejects_include_cycles() {
+        let game_dir = TestGame::new("include-cycle");
+        let main = game_dir.script("main.scr", "include b.inc\n");
+        game_dir.script("b.inc", "include c.inc\n");
+        game_dir.script("c.inc", "include b.inc\n");
+        let itself = game_dir.script("self.scr", "include self.scr\n");
+
+        let error = load_script(&main, &ScriptOptions::default()).err().unwrap().to_string();
+        assert!(error.contains("include cycle: main.scr -> b.inc -> c.inc -> b.inc"), "{}", error);
+        assert!(load_script(&itself, &ScriptOptions::default()).is_err());
+    }
 
-        self.load_script(&serialized.current_script);
-        self.pc = serialized.pc;
-        self.current_script = serialized.current_script;
-        self.memory = serialized.memory;
+    #[test]
+    fn hashes_scripts_without_includes_by_contents() {
+        let game_dir = TestGame::new("include-hash");
+        let main = game_dir.script("main.scr", "text a\n");
+        assert_eq!(load_script(&main, &ScriptOptions::default()).unwrap().hash, fnv1a(b"text a\n"));
+    }
 
-        let mut steps = vec![];
-        if let Some(background) = serialized.last_background {
-            steps.push(StepResult::Background(background));
-        }
-        if let Some(music) = serialized.last_music {
-            steps.push(StepResult::Music(music));
+    #[test]
+    fn hides_choice_options() {
+        let game_dir = TestGame::new("hidden-choices");
+        game_dir.script("main.scr", "\
+            setvar x = 1\n\
+            choice a?$x == 2|b|c?$x == 1\n\
+            choice d?$x == 2|e?$x == 3\n\
+            if $selected == 0\n\
+            text skipped\n\
+            fi\n");
+        let mut state = EngineState::new(&game_dir);
+        let first = state.run_until_interactive();
+        let shown = state.shown_choices().to_vec();
+        state.set_choice(1);
+        let rest = state.run_until_interactive();
+
+        assert!(matches!(&first[..], [StepResult::Choice(options, _)] if options == &["b", "c"]));
+        assert_eq!(shown, [1, 2]);
+        assert!(matches!(&rest[..], [StepResult::Text(_, text, true, _)] if text == "skipped"));
+    }
+
+    #[test]
+    fn compiled_script_round_trip() {
+        let script = "setvar x[$i] = 1 + 2\nlabel top\nif $x[$i] >= 3\ntext \"Ayu\" Uguu\ngoto top\nfi\nchoice a|b?$x == 3\n";
+        let script = parse(script, None, &ScriptOptions::default()).unwrap().into_script().unwrap();
+        let json = serde_json::to_string(&script).unwrap();
+        let restored: Script = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", restored.code), format!("{:?}", script.code));
+        assert_eq!(restored.labels, script.labels);
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    }
+
+    #[test]
+    fn restores_snapshots() {
+        let game_dir = TestGame::new("snapshot");
+        game_dir.script("main.scr", "\
+            setvar x = 1\n\
+            choice a|b\n\
+            setvar x = $x + $selected\n\
+            jump b.scr\n");
+        game_dir.script("b.scr", "if $x == 2\ntext a\nfi\nif $x == 3\ntext b\nfi\n");
+
+        let mut state = EngineState::new(&game_dir);
+        let mut texts = vec![];
+        let mut snapshot = None;
+        let mut steps = state.steps();
+        while let Some(result) = steps.next() {
+            match result {
+                StepResult::Choice(..) => {
+                    snapshot = Some(steps.state().snapshot());
+                    steps.state().set_choice(0);
+                }
+                // Picks the other option after reading where the first one leads.
+                StepResult::Text(_, text, _, _) if texts.is_empty() => {
+                    texts.push(text);
+                    steps.state().restore(snapshot.as_ref().unwrap()).unwrap();
+                    steps.state().set_choice(1);
+                }
+                StepResult::Text(_, text, _, _) => texts.push(text),
+                _ => (),
+            }
         }
-        if let Some(image) = serialized.last_main_image {
-            // FIXME use actual pos
-            steps.push(StepResult::Image(image, ImageSlot::Main, 0, 0));
+
+        assert_eq!(texts, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn quickloads_from_memory_and_file() {
+        let game_dir = TestGame::new("quicksave");
+        game_dir.script("main.scr", "text one\ntext two\ntext three\n");
+        let file = game_dir.join("quick.sav");
+
+        let mut state = EngineState::new(&game_dir);
+        assert!(!state.has_quicksave());
+        let no_quicksave = state.quickload().unwrap_err();
+        state.set_quicksave_file(Some(file.clone()));
+        let no_quicksave_file = state.quickload().unwrap_err();
+        state.run_until_interactive();
+        state.run_until_interactive();
+        state.quicksave().unwrap();
+        state.run_until_interactive();
+        state.quickload().unwrap();
+        let from_memory = state.run_until_interactive();
+        let mut restarted = EngineState::new(&game_dir);
+        restarted.set_quicksave_file(Some(file));
+        let has_quicksave = restarted.has_quicksave();
+        restarted.quickload().unwrap();
+        let from_file = restarted.run_until_interactive();
+
+        assert_eq!(no_quicksave.save_error(), Some(&SaveError::NoQuicksave));
+        assert_eq!(no_quicksave_file.save_error(), Some(&SaveError::NoQuicksave));
+        assert!(matches!(&from_memory[..], [StepResult::Text(_, text, true, _)] if text == "two"));
+        assert!(has_quicksave);
+        assert!(matches!(&from_file[..], [StepResult::Text(_, text, true, _)] if text == "two"));
+    }
+
+    #[test]
+    fn warns_about_changed_scripts() {
+        let game_dir = TestGame::new("save-hashes");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text a\njump b.scr\n");
+        game_dir.script("b.scr", "label b\ntext b\ninclude c.inc\n");
+        game_dir.script("c.inc", "text c\n");
+        let mut state = EngineState::new(&game_dir);
+        for _ in 0..2 {
+            state.run_until_interactive();
         }
-        if let Some(image) = serialized.last_date_image {
-            // FIXME use actual pos
-            steps.push(StepResult::Image(image, ImageSlot::Date, 0, 0));
+        state.save(&save).unwrap();
+        let unchanged = EngineState::new(&game_dir).load(&save).unwrap();
+        game_dir.script("c.inc", "text c2\n");
+        let included = EngineState::new(&game_dir).load(&save).unwrap();
+        game_dir.script("main.scr", "text a2\njump b.scr\n");
+        let changed = EngineState::new(&game_dir).load(&save).unwrap();
+
+        assert!(!unchanged.iter().any(|step| matches!(step, StepResult::SaveWarning(_))));
+        assert!(matches!(&included[0], StepResult::SaveWarning(SaveError::ScriptMismatch(scripts)) if scripts == &["b.scr"]));
+        assert!(matches!(&changed[0], StepResult::SaveWarning(SaveError::ScriptMismatch(scripts)) if scripts == &["b.scr", "main.scr"]));
+    }
+
+    #[test]
+    fn restores_checkpoints_at_labels() {
+        let game_dir = TestGame::new("checkpoints");
+        game_dir.script("main.scr", "\
+            label one\n\
+            setvar x = 1\n\
+            text a\n\
+            label two\n\
+            setvar x = 2\n\
+            text b\n\
+            label three\n\
+            text c\n");
+        let mut state = EngineState::new(&game_dir);
+        state.set_checkpoint_limit(2);
+        for _ in 0..3 {
+            state.run_until_interactive();
         }
-        Ok(steps)
+        let labels: Vec<_> = state.checkpoints().iter().map(|checkpoint| checkpoint.label.clone()).collect();
+        state.restore_checkpoint(0).unwrap();
+        let remaining = state.checkpoints().len();
+        let resumed = state.run_until_interactive();
+        let missing = state.restore_checkpoint(5);
+
+        assert_eq!(labels, ["two", "three"]);
+        assert_eq!(remaining, 0);
+        assert!(matches!(&resumed[..], [StepResult::Text(_, text, true, _)] if text == "b"));
+        assert_eq!(state.checkpoints().len(), 1);
+        assert!(missing.is_err());
     }
 
-    fn insert(&mut self, var: &VarOrConst, val: String) {
-        let (name, index) = match var {
-            VarOrConst { is_ref: false, name, index } => {
-                (name, index.unwrap_or(0))
-            }
-            _ => unimplemented!(),
-        };
+    #[test]
+    fn names_checkpoints_by_the_first_label_at_a_line() {
+        let game_dir = TestGame::new("checkpoint-labels");
+        game_dir.script("main.scr", "label c\nlabel a\nlabel b\ntext a\n");
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        let labels: Vec<_> = state.checkpoints().iter().map(|checkpoint| checkpoint.label.clone()).collect();
 
-        self.memory
-            .entry(name.clone())
-            .or_insert_with(HashMap::new)
-            .insert(index, val);
+        assert_eq!(labels, ["a"]);
     }
 
-    fn get_var<'a, 'b: 'a>(&'a self, var: &'b VarOrConst) -> Option<&str> {
-        if !var.is_ref {
-            return Some(&var.name);
+    #[test]
+    fn restores_scenes_from_the_timeline() {
+        let game_dir = TestGame::new("timeline");
+        game_dir.script("main.scr", "\
+            label prologue\n\
+            bgload room.png\n\
+            text a\n\
+            label morning\n\
+            chapter Morning\n\
+            bgload street.png\n\
+            text b\n\
+            label evening\n\
+            chapter Evening\n\
+            text c\n");
+        let mut state = EngineState::new(&game_dir);
+        for _ in 0..3 {
+            state.run_until_interactive();
         }
+        let timeline: Vec<_> = state.checkpoints().iter().map(|checkpoint| checkpoint.chapter.clone()).collect();
+        let scene = state.restore_checkpoint(1).unwrap();
+        let resumed = state.run_until_interactive();
 
-        let index = var.index.unwrap_or(0);
-        let val = self.memory
-            .get(&var.name)?
-            .get(&index)?
-            .as_str();
-        Some(val)
+        assert_eq!(timeline, [None, Some("Morning".to_string()), Some("Evening".to_string())]);
+        assert!(matches!(&scene[0], StepResult::Clear));
+        assert!(scene.iter().any(|step| matches!(step, StepResult::Background(path) if path.ends_with("room.png"))));
+        assert!(!scene.iter().any(|step| matches!(step, StepResult::Chapter(_))));
+        assert!(resumed.iter().any(|step| matches!(step, StepResult::Chapter(chapter) if chapter == "Morning")));
+        assert!(matches!(resumed.last(), Some(StepResult::Text(_, text, true, _)) if text == "b"));
     }
 
-    pub fn load_script(&mut self, name: &str) {
-        let path = self.directory.join("Scripts").join(name);
-        self.scripts.insert(name.to_string(), load_script(path).unwrap());
-        self.current_script = name.to_string();
-        self.pc = 0;
+    #[test]
+    fn resumes_changed_scripts_from_labels() {
+        let game_dir = TestGame::new("changed-save");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text a\nlabel b\ntext b\ntext c\n");
+        let mut state = EngineState::new(&game_dir);
+        assert_eq!(state.steps().nth(2).map(|result| matches!(result, StepResult::Text(_, text, _, _) if text == "c")), Some(true));
+        state.save(&save).unwrap();
+
+        game_dir.script("main.scr", "text a\ntext new\nlabel b\ntext b\ntext c\n");
+        let mut state = EngineState::new(&game_dir);
+        state.load(&save).unwrap();
+        let resumed = state.steps().next();
+        game_dir.script("main.scr", "text new\n");
+        let unlabeled = EngineState::new(&game_dir).load(&save).map(|_| ()).map_err(|e| e.to_string());
+
+        assert!(matches!(resumed, Some(StepResult::Text(_, text, _, _)) if text == "b"));
+        assert!(unlabeled.unwrap_err().contains("changed since the save (3 instructions, now 1)"));
     }
 
-    pub fn set_choice(&mut self, index: usize) {
-        self.insert(&VarOrConst {
-            is_ref: false,
-            name: "selected".to_string(),
-            index: None,
-        }, (index + 1).to_string());
+    #[test]
+    fn leaves_the_game_alone_when_loading_fails() {
+        let game_dir = TestGame::new("failed-load");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text a\ntext m\njump b.scr\n");
+        game_dir.script("b.scr", "text b\ntext c\n");
+        let mut state = EngineState::new(&game_dir);
+        state.steps().find(|result| matches!(result, StepResult::Text(_, text, _, _) if text == "c"));
+        state.save(&save).unwrap();
+
+        game_dir.script("b.scr", "text new\n");
+        let mut state = EngineState::new(&game_dir);
+        state.steps().next();
+        let pc = state.pc();
+        let loaded = state.load(&save);
+
+        assert!(loaded.is_err());
+        assert_eq!(state.current_script(), "main.scr");
+        assert_eq!(state.pc(), pc);
+        assert!(matches!(state.steps().next(), Some(StepResult::Text(_, text, _, _)) if text == "m"));
     }
-}
 
-#[derive(Debug)]
-pub enum ImageSlot {
-    Date,
-    Main,
-}
+    #[test]
+    fn resumes_from_the_saved_label() {
+        let game_dir = TestGame::new("saved-label");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text a\nlabel b\ntext b\ntext c\n");
+        let mut state = EngineState::new(&game_dir);
+        state.steps().nth(2);
+        state.save(&save).unwrap();
 
-#[derive(Debug)]
-pub enum StepResult {
-    Clear,
-    Continue,
-    Exit,
-    Jump(String),
-    Choice(Vec<String>),
-    Text(Option<String>, String),
-    Background(PathBuf),
-    Image(PathBuf, ImageSlot, usize, usize),
-    Sound(String),
-    Music(String),
-}
+        // The nearest label before the saved position is now another one.
+        game_dir.script("main.scr", "label intro\ntext new\ntext newer\ntext a\nlabel b\ntext b\ntext c\n");
+        let mut state = EngineState::new(&game_dir);
+        state.load(&save).unwrap();
+        let resumed = state.steps().next();
 
-pub fn step(state: &mut EngineState) -> StepResult {
-    let curr_inst = match state.scripts[&state.current_script].code.get(state.pc).cloned() {
-        Some(ci) => ci,
-        None => return StepResult::Exit,
-    };
-    println!("Executing {} @ {}", &state.current_script, state.pc);
-    match curr_inst {
-        Instr::cleartext => {
-            state.pc += 1;
-            return StepResult::Clear;
-        }
-        Instr::gsetvar(ident, value) => {
-            state.insert(&ident, value.to_string());
-        }
-        Instr::setvar(ident, value) => {
-            state.insert(&ident, value.to_string());
-        }
-        Instr::bgload(file, time) => {
-            println!("// Loading background from {:?} {:?}", file, time);
-            state.pc += 1;
-            let name = state.get_var(&file).unwrap();
-            let path = state.directory.join("CG").join(name);
-            state.last_background = Some(path.clone());
-            return StepResult::Background(path);
-        }
-        Instr::setimg(file, x, y) => {
-            println!("// Loading image from {:?} and placing it at {} {}", file, x, y);
-            state.pc += 1;
-            let name = state.get_var(&file).unwrap();
-            let path = state.directory.join("CGAlt").join(name);
-            return if &file.name == "DATEIMAGE" {
-                state.last_date_image = Some(path.clone());
-                StepResult::Image(path, ImageSlot::Date, x, y)
-            } else {
-                state.last_main_image = Some(path.clone());
-                StepResult::Image(path, ImageSlot::Main, x, y)
-            };
-        }
-        Instr::delay(delay) => {
-            println!("// Waiting for {} units of time", delay);
-        }
-        Instr::branch(lhs, op, rhs, else_target) => {
-            let lhs = state.get_var(&lhs).unwrap();
-            let result = match op {
-                Operator::Equal => lhs == rhs,
-                Operator::NotEqual => lhs != rhs,
-                Operator::Less => lhs < &rhs,
-                Operator::LessEqual => lhs <= &rhs,
-            };
+        assert!(matches!(resumed, Some(StepResult::Text(_, text, _, _)) if text == "b"));
+    }
 
-            if result {
-                state.pc += 1;
-            } else {
-                state.pc = else_target;
-            }
-            return StepResult::Continue;
-        }
-        Instr::text(who, what) => {
-            state.pc_to_save = state.pc;
-            state.pc += 1;
-            return StepResult::Text(who, what);
-        }
-        Instr::goto(target) => {
-            state.pc = match target {
-                Label::Offset(x) => x,
-                _ => unreachable!()
-            };
-            return StepResult::Continue;
-        }
-        Instr::sound(file, arg) => {
-            println!("// Playing {} with {:?}", file, arg);
-            state.pc += 1;
-            return StepResult::Sound(file);
-        }
-        Instr::music(file) => {
-            println!("// Playing {}", file);
-            state.last_music = Some(file.clone());
-            state.pc += 1;
-            return StepResult::Music(file);
+    #[test]
+    fn saves_chapters() {
+        let game_dir = TestGame::new("chapter");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "chapter \"April 1 – Morning\"\ntext a\n");
+        let mut state = EngineState::new(&game_dir);
+        let chapters: Vec<_> = state.steps()
+            .filter_map(|result| match result {
+                StepResult::Chapter(chapter) => Some(chapter),
+                _ => None,
+            })
+            .collect();
+        state.save(&save).unwrap();
+        let saved = EngineState::saved_chapter(&save).unwrap();
+        let restored = EngineState::new(&game_dir).load(&save).unwrap();
+
+        assert_eq!(chapters, vec!["April 1 – Morning"]);
+        assert_eq!(saved.as_deref(), Some("April 1 – Morning"));
+        assert!(restored.iter().any(|result| matches!(result, StepResult::Chapter(chapter) if chapter == "April 1 – Morning")));
+    }
+
+    #[test]
+    fn reconstructs_scenes_from_saves() {
+        let game_dir = TestGame::new("scene");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "\
+            bgload bg.png\n\
+            setimg DATEIMAGE 5 6\n\
+            sound rain.ogg -1\n\
+            sound door.ogg\n\
+            text a\n\
+            sound ~\n\
+            text b\n");
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        state.save(&save).unwrap();
+        state.run_until_interactive();
+        let mut loaded = EngineState::new(&game_dir);
+        let steps: Vec<_> = loaded.load(&save).unwrap().iter().map(|step| format!("{:?}", step)).collect();
+        state.save(&save).unwrap();
+        let after_stop = loaded.load(&save).unwrap();
+
+        let background = game_dir.join("CG").join("bg.png");
+        let image = game_dir.join("CGAlt").join("DATEIMAGE");
+        assert_eq!(steps, vec![
+            "Clear".to_string(),
+            format!("Background({:?})", background),
+            "MusicStop { fade: None }".to_string(),
+            "Sound(\"rain.ogg\", Some(-1))".to_string(),
+            "ClearImage(ImageSlot { name: \"main\", layer: 1 })".to_string(),
+            format!("Image({:?}, ImageSlot {{ name: \"date\", layer: 2 }}, 5, 6)", image),
+        ]);
+        assert!(after_stop.iter().any(|step| matches!(step, StepResult::Sound(sound, None) if sound == "~")));
+    }
+
+    #[test]
+    fn saves_backlog() {
+        let game_dir = TestGame::new("backlog");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text \"Ayu\" a\ntext b\ntext c\ntext d\n");
+        let mut state = EngineState::new(&game_dir);
+        state.set_backlog_len(2);
+        for _ in 0..3 {
+            state.run_until_interactive();
         }
-        Instr::choice(choices) => {
-            state.pc_to_save = state.pc;
-            state.pc += 1;
-            state.set_choice(0); // default choice
-            return StepResult::Choice(
-                choices.iter().map(|ch| {
-                    state.get_var(ch).unwrap().to_string()
-                }).collect()
-            );
-        }
-        Instr::jump(file) => {
-            return StepResult::Jump(file);
+        state.save(&save).unwrap();
+        let mut loaded = EngineState::new(&game_dir);
+        loaded.load(&save).unwrap();
+        let mut shorter = EngineState::new(&game_dir);
+        shorter.set_backlog_len(1);
+        shorter.load(&save).unwrap();
+
+        let texts = |state: &EngineState| state.backlog().iter().map(|entry| entry.text.clone()).collect::<Vec<_>>();
+        assert_eq!(texts(&state), ["b", "c"]);
+        assert_eq!(texts(&loaded), ["b", "c"]);
+        assert_eq!(texts(&shorter), ["c"]);
+    }
+
+    #[test]
+    fn skips_only_read_text() {
+        let game_dir = TestGame::new("skip-read");
+        game_dir.script("main.scr", "text a\ntext b\n");
+        let mut first = EngineState::new(&game_dir);
+        first.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        first.run_until_interactive();
+        let first_unread = first.can_skip();
+        first.run_until_interactive();
+        drop(first);
+
+        let mut second = EngineState::new(&game_dir);
+        second.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        second.run_until_interactive();
+        let read = second.can_skip();
+        second.run_until_interactive();
+        let unread = second.can_skip();
+        second.set_skip_seen_only(false);
+
+        assert!(!first_unread);
+        assert!(read);
+        assert!(!unread);
+        assert!(second.can_skip());
+    }
+
+    #[test]
+    fn unlocks_gallery_images() {
+        let game_dir = TestGame::new("gallery");
+        game_dir.write("gallery.json", r#"["CGAlt/ev02.png", "CG/ev01.png", "CG/ev03.png"]"#);
+        game_dir.script("main.scr", "bgload ev01.png\nsetimg ev02.png 0 0\nbgload other.png\ntext a\n");
+        let mut state = EngineState::new(&game_dir);
+        state.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        state.run_until_interactive();
+        drop(state);
+
+        let mut reopened = EngineState::new(&game_dir);
+        reopened.open_persistent_store(game_dir.join("global.sav")).unwrap();
+
+        assert_eq!(reopened.unlocked_cgs(), ["CGAlt/ev02.png", "CG/ev01.png"]);
+    }
+
+    #[test]
+    fn hides_tagged_content_when_streamer_safe() {
+        let game_dir = TestGame::new("streamer-safe");
+        game_dir.write("game.toml", "\
+            [content_tags]\n\
+            \"CG/ev01.png\" = [\"spoiler\"]\n\
+            \"bgm01.ogg\" = [\"copyrighted\"]\n");
+        game_dir.write("gallery.json", r#"["CG/ev01.png", "CG/ev02.png"]"#);
+        game_dir.script("main.scr", "bgload ev01.png\nbgload ev02.png\nmusic bgm01.ogg\ntext a\n");
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        let shown = (state.unlocked_cgs().len(), state.is_muted("bgm01.ogg"));
+        state.set_streamer_safe(true);
+
+        assert_eq!(shown, (2, false));
+        assert_eq!(state.unlocked_cgs(), ["CG/ev02.png"]);
+        assert!(state.is_muted("bgm01.ogg"));
+        assert!(!state.is_muted("bgm02.ogg"));
+    }
+
+    #[test]
+    fn unlocks_played_tracks() {
+        let game_dir = TestGame::new("music-room");
+        game_dir.script("main.scr", "music b.ogg\nmusic a.ogg 500\nmusic b.ogg\nmusic ~\ntext a\n");
+        let mut state = EngineState::new(&game_dir);
+        state.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        state.run_until_interactive();
+        drop(state);
+
+        let mut reopened = EngineState::new(&game_dir);
+        reopened.open_persistent_store(game_dir.join("global.sav")).unwrap();
+
+        assert_eq!(reopened.unlocked_tracks(), ["a.ogg", "b.ogg"]);
+    }
+
+    #[test]
+    fn records_endings() {
+        let game_dir = TestGame::new("endings");
+        game_dir.script("main.scr", "ending true\nending bad\nending true\ntext a\n");
+        let mut state = EngineState::new(&game_dir);
+        state.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        let steps = state.run_until_interactive();
+        drop(state);
+
+        let mut reopened = EngineState::new(&game_dir);
+        reopened.open_persistent_store(game_dir.join("global.sav")).unwrap();
+
+        let endings: Vec<_> = steps.iter()
+            .filter_map(|step| match step {
+                StepResult::Ending(id) => Some(id.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(endings, ["true", "bad", "true"]);
+        assert_eq!(reopened.completed_endings(), ["bad", "true"]);
+    }
+
+    #[test]
+    fn switches_language_mid_script() {
+        let game_dir = TestGame::new("language");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text a\ntext b\ntext c\n");
+        game_dir.script("de/main.scr", "text A\ntext B\ntext C\n");
+        let text = |steps: Vec<StepResult>| steps.into_iter().find_map(|step| match step {
+            StepResult::Text(_, text, true, _) => Some(text),
+            _ => None,
+        });
+
+        let mut state = EngineState::new(&game_dir);
+        let before = text(state.run_until_interactive());
+        state.set_language(Some("de")).unwrap();
+        let again = text(state.run_until_interactive());
+        let next = text(state.run_until_interactive());
+        state.save(&save).unwrap();
+        let mut loaded = EngineState::new(&game_dir);
+        let scene = loaded.load(&save).unwrap();
+        let resumed = text(loaded.run_until_interactive());
+
+        assert_eq!(before.as_deref(), Some("a"));
+        assert_eq!(again.as_deref(), Some("A"));
+        assert_eq!(next.as_deref(), Some("B"));
+        assert_eq!(loaded.language(), Some("de"));
+        assert!(!scene.iter().any(|step| matches!(step, StepResult::SaveWarning(_))));
+        assert_eq!(resumed.as_deref(), Some("B"));
+    }
+
+    #[test]
+    fn restores_position_and_scene_on_load() {
+        let game_dir = TestGame::new("load-scene");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "bgload a.png\nmusic a.ogg\ntext a\nbgload b.png\nmusic b.ogg\ntext b\ntext c\n");
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        state.run_until_interactive();
+        state.save(&save).unwrap();
+
+        let mut loaded = EngineState::new(&game_dir);
+        let scene = loaded.load(&save).unwrap();
+        let background = game_dir.join("CG").join("b.png");
+
+        assert_eq!(loaded.pc_to_save, 5);
+        assert_eq!(loaded.pc, 5);
+        assert_eq!(loaded.last_background.as_ref(), Some(&background));
+        assert_eq!(loaded.last_music.as_deref(), Some("b.ogg"));
+        assert!(scene.iter().any(|step| matches!(step, StepResult::Background(path) if *path == background)));
+        assert!(scene.iter().any(|step| matches!(step, StepResult::Music { file, .. } if file == "b.ogg")));
+        let resumed = loaded.run_until_interactive();
+        assert!(matches!(resumed.as_slice(), [StepResult::Text(_, text, true, _)] if text == "b"));
+    }
+
+    #[test]
+    fn counts_play_time() {
+        let game_dir = TestGame::new("play-time");
+        let save = game_dir.join("save.sav");
+        game_dir.script("main.scr", "text a\n");
+        let mut state = EngineState::new(&game_dir);
+        state.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        state.tick(Duration::from_secs(50));
+        state.tick(Duration::from_secs(40));
+        state.save(&save).unwrap();
+        state.new_game().unwrap();
+        state.tick(Duration::from_secs(5));
+        let restarted = state.play_time();
+        state.load(&save).unwrap();
+        let mut reopened = EngineState::new(&game_dir);
+        reopened.open_persistent_store(game_dir.join("global.sav")).unwrap();
+
+        assert_eq!(restarted, Duration::from_secs(5));
+        assert_eq!(state.play_time(), Duration::from_secs(90));
+        assert_eq!(state.total_play_time(), Duration::from_secs(95));
+        // Written once the first minute was over.
+        assert_eq!(reopened.total_play_time(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn runs_until_interactive() {
+        let game_dir = TestGame::new("interactive");
+        game_dir.script("main.scr", "bgload bg.png\nmusic bgm.ogg\ntext a\njump b.scr\n");
+        game_dir.script("b.scr", "choice c|d\n");
+
+        let mut state = EngineState::new(&game_dir);
+        let first = state.run_until_interactive();
+        let second = state.run_until_interactive();
+        let third = state.run_until_interactive();
+
+        assert!(matches!(first.as_slice(), [StepResult::Background(_), StepResult::Music { .. }, StepResult::Text(_, _, true, _)]));
+        assert!(matches!(second.as_slice(), [StepResult::Jump(_), StepResult::Choice(..)]));
+        assert!(matches!(third.as_slice(), [StepResult::Exit]));
+    }
+
+    #[test]
+    fn fades_music() {
+        let game_dir = TestGame::new("music-fade");
+        game_dir.script("main.scr", "music a.ogg\nmusic b.ogg 500\nmusic ~ 250\n");
+
+        let mut state = EngineState::new(&game_dir);
+        let steps: Vec<_> = state.steps().collect();
+
+        assert!(matches!(&steps[0], StepResult::Music { file, fade: None } if file == "a.ogg"));
+        assert!(matches!(&steps[1], StepResult::Music { file, fade: Some(fade) } if file == "b.ogg" && fade.as_millis() == 500));
+        assert!(matches!(&steps[2], StepResult::MusicStop { fade: Some(fade) } if fade.as_millis() == 250));
+        assert_eq!(steps.len(), 3);
+    }
+
+    #[test]
+    fn lists_upcoming_assets() {
+        let game_dir = TestGame::new("upcoming");
+        game_dir.script("main.scr", "\
+            setvar bg = night.png\n\
+            text a\n\
+            bgload $bg\n\
+            goto next\n\
+            bgload skipped.png\n\
+            label next\n\
+            setimg ~\n\
+            music bgm.ogg\n\
+            setimg ayu.png 0 0\n\
+            if $x == 1\n\
+            bgload maybe.png\n\
+            fi\n");
+
+        let mut state = EngineState::new(&game_dir);
+        state.steps().next();
+        let upcoming = state.upcoming_assets(5);
+        let limited = state.upcoming_assets(1);
+
+        assert_eq!(upcoming, vec![
+            game_dir.join("CG").join("night.png"),
+            game_dir.join("bgm.ogg"),
+            game_dir.join("CGAlt").join("ayu.png"),
+        ]);
+        assert_eq!(limited, &upcoming[..1]);
+    }
+
+    #[test]
+    fn paginates_long_text() {
+        let game_dir = TestGame::new("pages");
+        game_dir.script("main.scr", "text \"Ayu\" one two three four five\ntext six\n");
+
+        let mut state = EngineState::new(&game_dir);
+        state.set_pagination(Some(Pagination { line_len: 9, lines: 1, direction: LayoutDirection::LeftToRight }));
+        let pages: Vec<_> = state.steps()
+            .filter_map(|result| match result {
+                StepResult::Text(who, what, _, _) => Some((who, what)),
+                _ => None,
+            })
+            .collect();
+
+        let ayu = Some("Ayu".to_string());
+        assert_eq!(pages, vec![
+            (ayu.clone(), "one two".to_string()),
+            (ayu.clone(), "three".to_string()),
+            (ayu, "four five".to_string()),
+            (None, "six".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn paginates_without_control_codes() {
+        let game_dir = TestGame::new("markup-pages");
+        game_dir.script("main.scr", "text {w=500}{i}one two{/i} three {b}four{/b}\n");
+
+        let mut state = EngineState::new(&game_dir);
+        state.set_pagination(Some(Pagination { line_len: 9, lines: 1, direction: LayoutDirection::LeftToRight }));
+        let pages: Vec<_> = state.steps()
+            .filter_map(|result| match result {
+                StepResult::Text(_, what, _, spans) => Some((what, spans)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(pages, vec![
+            ("one two".to_string(), vec![TextSpan::Wait(500), TextSpan::Italic(true), TextSpan::Text("one two".to_string())]),
+            ("three".to_string(), vec![TextSpan::Italic(true), TextSpan::Italic(false), TextSpan::Text("three".to_string())]),
+            ("four".to_string(), vec![TextSpan::Bold(true), TextSpan::Text("four".to_string()), TextSpan::Bold(false)]),
+        ]);
+    }
+
+    #[test]
+    fn yields_from_endless_loops() {
+        let mut state = EngineState::new(std::env::temp_dir().join("madenon-missing"));
+        let script = parse("label a\ngoto a\n", None, &ScriptOptions::default()).unwrap().into_script().unwrap();
+        let name = state.symbols.intern("loop.scr");
+        state.cache_script(name, script);
+        state.load_script("loop.scr").unwrap();
+        state.set_instruction_budget(Some(10));
+
+        let results: Vec<_> = (0..22).map(|_| step(&mut state)).collect();
+        assert!(results[..10].iter().all(|result| matches!(result, StepResult::Continue)));
+        assert!(matches!(results[10], StepResult::Yield));
+        assert!(results[11..21].iter().all(|result| matches!(result, StepResult::Continue)));
+        assert!(matches!(results[21], StepResult::Yield));
+
+        let steps: Vec<_> = state.steps().collect();
+        assert!(matches!(steps.as_slice(), [StepResult::Yield]));
+    }
+
+    #[test]
+    fn notifies_observers() {
+        #[derive(Clone, Default)]
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+        impl EngineObserver for Recorder {
+            fn on_instruction(&mut self, script: &str, pc: usize, instruction: &dyn std::fmt::Debug) {
+                let instruction = format!("{:?}", instruction);
+                let name = instruction.split('(').next().unwrap_or_default().to_string();
+                self.0.lock().unwrap().push(format!("{} @ {}: {}", script, pc, name));
+            }
+            fn on_var_changed(&mut self, name: &str, index: usize, value: &str) {
+                self.0.lock().unwrap().push(format!("{}[{}] = {}", name, index, value));
+            }
+        }
+
+        let game_dir = TestGame::new("observer");
+        game_dir.script("main.scr", "setvar x = 1\ntext a\njump b.scr\n");
+        game_dir.script("b.scr", "text b\n");
+        let recorder = Recorder::default();
+        let mut state = EngineState::new(&game_dir);
+        state.add_observer(recorder.clone());
+        state.steps().count();
+
+        assert_eq!(*recorder.0.lock().unwrap(), [
+            "main.scr @ 0: setvar",
+            "x[0] = 1",
+            "main.scr @ 1: text",
+            "main.scr @ 2: jump",
+            "b.scr @ 0: text",
+        ]);
+    }
+
+    #[test]
+    fn runs_on_another_thread() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<EngineState>();
+        assert_send_sync::<StepResult>();
+        assert_send_sync::<crate::GameError>();
+
+        let game_dir = TestGame::new("thread");
+        game_dir.script("main.scr", "text a\ntext b\n");
+        let state = std::sync::Arc::new(std::sync::Mutex::new(EngineState::new(&game_dir)));
+        let worker = {
+            let state = state.clone();
+            std::thread::spawn(move || state.lock().unwrap().steps().count())
+        };
+        let steps = worker.join().unwrap();
+        let finished = state.lock().unwrap().load_script("missing.scr").is_err();
+
+        assert_eq!(steps, 2);
+        assert!(finished);
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Script {
+    code: Vec<Instr>,
+    #[serde(serialize_with = "serialize_labels", deserialize_with = "deserialize_labels")]
+    labels: HashMap<Label, usize>,
+    // `fnv1a` of the script file, extended with the hashes of the files it includes, so
+    // it changes when any of them does.
+    hash: u64,
+}
+
+// Labels can't be keys of JSON objects, so they're stored as a list of pairs instead.
+fn serialize_labels<S: Serializer>(labels: &HashMap<Label, usize>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut pairs: Vec<(&Label, &usize)> = labels.iter().collect();
+    pairs.sort_by_key(|&(label, &pc)| (pc, format!("{:?}", label)));
+    pairs.serialize(serializer)
+}
+
+fn deserialize_labels<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Label, usize>, D::Error> {
+    Ok(Vec::<(Label, usize)>::deserialize(deserializer)?.into_iter().collect())
+}
+
+fn list_scripts(scripts_dir: &Path, options: &ScriptOptions) -> Result<Vec<String>, std::io::Error> {
+    let mut names: Vec<String> = options.resources()
+        .list(scripts_dir)?
+        .into_iter()
+        .filter(|name| Path::new(name).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("scr")))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+#[derive(Default, Clone)]
+pub struct ScriptOptions {
+    pub encoding: ScriptEncoding,
+    // Accepts the rest of the VNDS instruction set and skips unknown instructions
+    // instead of failing, so unmodified VNDS games can run.
+    pub vnds_compat: bool,
+    pub decryptor: Option<Arc<dyn ScriptDecryptor>>,
+    // Scripts are read from the game directory when not set.
+    pub resources: Option<Arc<dyn ResourceProvider>>,
+}
+
+impl ScriptOptions {
+    fn resources(&self) -> &dyn ResourceProvider {
+        self.resources.as_deref().unwrap_or(&FileSystem)
+    }
+}
+
+fn load_script(path: impl AsRef<Path>, options: &ScriptOptions) -> Result<Script, Box<dyn std::error::Error + Send + Sync>> {
+    let path = path.as_ref();
+    let bytes = options.resources().read(path)?;
+    let hash = fnv1a(&bytes);
+    let emitter = parse_bytes(bytes, path, options)?;
+    let hash = emitter.included.iter().fold(hash, |hash, included| fnv1a_extend(hash, &included.to_le_bytes()));
+    let script = emitter.into_script()?;
+    Ok(Script { hash, ..script })
+}
+
+fn parse_script(path: impl AsRef<Path>, options: &ScriptOptions) -> Result<Emitter, Box<dyn std::error::Error + Send + Sync>> {
+    let path = path.as_ref();
+    parse_bytes(options.resources().read(path)?, path, options)
+}
+
+fn parse_bytes(bytes: Vec<u8>, path: &Path, options: &ScriptOptions) -> Result<Emitter, Box<dyn std::error::Error + Send + Sync>> {
+    parse(&decode_script(bytes, options)?, Some(path), options)
+}
+
+fn decode_script(mut bytes: Vec<u8>, options: &ScriptOptions) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(decryptor) = &options.decryptor {
+        bytes = decryptor.decrypt(bytes)?;
+    }
+    Ok(options.encoding.decode(&bytes)?.into_owned())
+}
+
+// Where `include`d scripts are read from, and the chain of files currently being
+// included to detect cycles.
+struct Includes<'a> {
+    directory: Option<&'a Path>,
+    stack: Vec<String>,
+    // `fnv1a` of every file included so far.
+    hashes: Vec<u64>,
+}
+
+// Splits a script into preprocessed lines, each with the file and line it came from,
+// splicing in `include`d scripts.
+fn read_lines(
+    text: &str,
+    file: &str,
+    options: &ScriptOptions,
+    preprocessor: &mut Preprocessor,
+    includes: &mut Includes,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut lines = vec![];
+    for (lineno, line) in text.lines().enumerate() {
+        let location = if file.is_empty() {
+            (lineno + 1).to_string()
+        } else {
+            format!("{}:{}", file, lineno + 1)
+        };
+
+        if let Some(included) = line.trim().strip_prefix("include ") {
+            let included = included.trim();
+            let directory = includes.directory
+                .ok_or_else(|| format!("{}: include is only supported in script files", location))?;
+            if includes.stack.iter().any(|f| f == included) {
+                return Err(format!("{}: include cycle: {} -> {}", location, includes.stack.join(" -> "), included).into());
+            }
+
+            let bytes = options.resources().read(&directory.join(included))
+                .map_err(|e| format!("{}: can't include {}: {}", location, included, e))?;
+            includes.hashes.push(fnv1a(&bytes));
+            includes.stack.push(included.to_string());
+            lines.extend(read_lines(&decode_script(bytes, options)?, included, options, preprocessor, includes)?);
+            includes.stack.pop();
+            continue;
+        }
+
+        let expanded = preprocessor.process(line)
+            .map_err(|e| format!("{}: {}", location, e))?;
+        lines.extend(expanded.into_iter().map(|line| (location.clone(), line)));
+    }
+    Ok(lines)
+}
+
+// `text?condition` only shows the option when the condition holds, a `?` not followed
+// by a condition is part of the text.
+fn parse_choice_option(s: &str) -> Result<(VarOrConst, Option<Condition>), String> {
+    if let Some((text, condition)) = s.rsplit_once('?') {
+        if let Ok(condition) = parse_condition(condition) {
+            return Ok((parse_var_ref(text)?, Some(condition)));
+        }
+    }
+    Ok((parse_var_ref(s)?, None))
+}
+
+fn parse_label(s: &str) -> Result<Label, String> {
+    match s.strip_prefix('@') {
+        Some(x) => Ok(Label::Indexed(parse_number(x)?)),
+        None => Ok(Label::Named(s.to_string())),
+    }
+}
+
+// `path` is the file the script was read from, which `include`s are resolved against.
+fn parse(text: &str, path: Option<&Path>, options: &ScriptOptions) -> Result<Emitter, Box<dyn std::error::Error + Send + Sync>> {
+    let name = path
+        .and_then(Path::file_name)
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let mut preprocessor = Preprocessor::default();
+    let mut includes = Includes {
+        directory: path.and_then(Path::parent),
+        stack: vec![name.clone()],
+        hashes: vec![],
+    };
+    let lines = read_lines(text, &name, options, &mut preprocessor, &mut includes)?;
+    preprocessor.finish()?;
+    let emitter = parse_lines(lines, options)?;
+    Ok(Emitter { included: includes.hashes, ..emitter })
+}
+
+fn parse_lines(lines: Vec<(String, String)>, options: &ScriptOptions) -> Result<Emitter, Box<dyn std::error::Error + Send + Sync>> {
+    let mut emitter = Emitter::new();
+
+    for (location, line) in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if options.vnds_compat && line.starts_with('#') {
+            continue;
+        }
+        parse_line(&mut emitter, line, &location, options)
+            .map_err(|e| format!("{}: {}", location, e))?;
+    }
+    Ok(emitter)
+}
+
+fn parse_line(emitter: &mut Emitter, line: &str, location: &str, options: &ScriptOptions) -> Result<(), String> {
+    let parts = split_args(line, 3);
+    match &parts[..] {
+        &["setvar", name, "~"] | &["setvar", name, "=", "~"] => {
+            emitter.emit(Instr::unsetvar(
+                Some(name).filter(|&n| n != "~").map(parse_var_ref).transpose()?,
+            ));
+        }
+        &["gsetvar", name, "~"] | &["gsetvar", name, "=", "~"] => {
+            emitter.emit(Instr::gunsetvar(
+                Some(name).filter(|&n| n != "~").map(parse_var_ref).transpose()?,
+            ));
+        }
+        &["setvar", name, op @ ("+" | "-"), value] if options.vnds_compat => {
+            let value: i64 = parse_number(value)?;
+            emitter.emit(Instr::addvar(
+                parse_var_ref(name)?,
+                if op == "-" { value.wrapping_neg() } else { value },
+            ));
+        }
+        &["endscript"] if options.vnds_compat => {
+            emitter.emit(Instr::endscript);
+        }
+        &["random", name, low, high] if options.vnds_compat => {
+            emitter.emit(Instr::random(
+                parse_var_ref(name)?,
+                parse_number(low)?,
+                parse_number(high)?,
+            ));
+        }
+        &["jump", target, label] if options.vnds_compat => {
+            emitter.emit(Instr::jump(
+                target.to_string(),
+                Some(parse_label(label)?),
+            ));
+        }
+        &["cleartext", ..] => {
+            emitter.emit(Instr::cleartext);
+        }
+        &["gsetvar", name, "=" | "-" | "+", value] => {
+            emitter.emit(Instr::gsetvar(
+                parse_var_ref(name)?,
+                parse_value(value),
+            ));
+        }
+        &["setvar", name, "=" | "-" | "+", value] => {
+            emitter.emit(Instr::setvar(
+                parse_var_ref(name)?,
+                parse_value(value),
+            ));
+        }
+        &["setvar", name, value] => {
+            emitter.emit(Instr::setvar(
+                parse_var_ref(name)?,
+                parse_value(value),
+            ));
+        }
+        &["bgload", vref] => {
+            emitter.emit(Instr::bgload(
+                parse_var_ref(vref)?,
+                None,
+            ));
+        }
+        &["bgload", vref, time] => {
+            emitter.emit(Instr::bgload(
+                parse_var_ref(vref)?,
+                Some(parse_number(time)?),
+            ));
+        }
+        &["setimg", "~"] => {
+            emitter.emit(Instr::setimg(parse_var_ref("~")?, 0, 0, None));
+        }
+        &["setimg", vref, x, rest] => {
+            let (y, slot) = rest
+                .split_once(|c: char| c.is_ascii_whitespace())
+                .map_or((rest, None), |(y, slot)| (y, Some(slot.trim().to_string())));
+            emitter.emit(Instr::setimg(
+                parse_var_ref(vref)?,
+                parse_number(x)?,
+                parse_number(y)?,
+                slot,
+            ));
+        }
+        &["delay", delay] => {
+            emitter.emit(Instr::delay(
+                parse_number(delay)?,
+            ))
+        }
+        &["if", ..] => {
+            let (lhs, op, rhs) = parse_condition(&line[2..])?;
+            emitter.begin_branch();
+            emitter.emit(Instr::branch(
+                lhs,
+                op,
+                rhs,
+                emitter.code.len(),
+            ));
+        }
+        &["fi"] => {
+            emitter.end_branch()?;
+        }
+        &["menu"] => {
+            emitter.begin_menu();
+        }
+        &["option", ..] => {
+            emitter.begin_option(line[6..].trim())?;
+        }
+        &["end"] => {
+            emitter.end_option()?;
+        }
+        &["endmenu"] => {
+            emitter.end_menu()?;
+        }
+        &["text", "~"] => {
+            emitter.emit(Instr::text(
+                None,
+                String::new(),
+                true,
+            ));
+        }
+        &["text", "!"] => {
+            emitter.emit(Instr::text(
+                None,
+                String::new(),
+                false,
+            ));
+        }
+        &["text", ..] => {
+            let x = line[4..].trim();
+            let (name, text) = parse_text(x);
+
+            emitter.emit(Instr::text(
+                name,
+                text,
+                true,
+            ));
+        }
+        &["goto", label] => {
+            emitter.emit(Instr::goto(
+                parse_label(label)?
+            ));
+        }
+        &["label", ident] => {
+            emitter.make_label(parse_label(ident)?);
+        }
+        &["sound", file] => {
+            emitter.emit(Instr::sound(
+                file.to_string(),
+                None,
+            ));
+        }
+        &["sound", file, param] => {
+            emitter.emit(Instr::sound(
+                file.to_string(),
+                Some(parse_number(param)?),
+            ));
+        }
+        &["ending", id] => {
+            emitter.emit(Instr::ending(
+                id.to_string(),
+            ));
+        }
+        &["voice", file] => {
+            emitter.emit(Instr::voice(
+                file.to_string(),
+            ));
+        }
+        &["music", "~"] => {
+            emitter.emit(Instr::musicstop(None));
+        }
+        &["music", "~", fade] => {
+            emitter.emit(Instr::musicstop(
+                Some(parse_number(fade)?),
+            ));
+        }
+        &["music", file] => {
+            emitter.emit(Instr::music(
+                file.to_string(),
+                None,
+            ));
+        }
+        &["music", file, fade] => {
+            emitter.emit(Instr::music(
+                file.to_string(),
+                Some(parse_number(fade)?),
+            ));
+        }
+        &["choice", ..] => {
+            emitter.emit(Instr::choice(
+                line[6..].trim_start().split("|").map(parse_choice_option).collect::<Result<_, _>>()?,
+            ));
+        }
+        &["jump", target] => {
+            emitter.emit(Instr::jump(
+                target.to_string(),
+                None,
+            ));
+        }
+        &["title", ..] => {
+            emitter.emit(Instr::title(
+                unescape(line[5..].trim()),
+            ));
+        }
+        &["chapter", ..] => {
+            emitter.emit(Instr::chapter(
+                unescape(line[7..].trim()),
+            ));
+        }
+        _ if options.vnds_compat => {
+            warn!(%location, ?parts, "skipping unsupported instruction");
+        }
+        _ => {
+            return Err(format!("unknown instruction {:?}", parts));
+        }
+    }
+    Ok(())
+}
+
+const DEFAULT_SCRIPT_CACHE_CAPACITY: usize = 32;
+const DEFAULT_INSTRUCTION_BUDGET: usize = 100_000;
+
+// Send + Sync, so frontends can run scripts on a background thread or task and hand
+// the state between threads. Everything it holds, observers and providers included,
+// must stay that way.
+pub struct EngineState {
+    scripts: ScriptCache,
+    // Names of variables and scripts, which everything else refers to by symbol.
+    symbols: SymbolTable,
+    memory: HashMap<Symbol, HashMap<usize, Value>>,
+    globals: HashMap<Symbol, HashMap<usize, Value>>,
+    pc: usize,
+    current_script: Symbol,
+    directory: PathBuf,
+    manifest: GameManifest,
+    // Scripts in `Scripts/<language>` replace the default ones with the same name.
+    language: Option<String>,
+    translation: Option<Translation>,
+    last_music: Option<String>,
+    // Sound played with `-1`, which loops until the next `sound ~`.
+    sound_loop: Option<String>,
+    last_background: Option<PathBuf>,
+    // Image currently shown in each slot, by slot name.
+    last_images: BTreeMap<String, ShownImage>,
+    image_slots: ImageSlots,
+    gallery: Gallery,
+    current_title: Option<String>,
+    current_chapter: Option<String>,
+    // Speaker and text of the last line which waited for the player.
+    last_line: Option<(Option<String>, String)>,
+    backlog: Backlog,
+    // Time played in this playthrough, as counted by `tick`.
+    play_time: Duration,
+    pc_to_save: usize,
+    observers: Vec<Box<dyn EngineObserver + Send + Sync>>,
+    script_options: ScriptOptions,
+    pending_jump_label: Option<Label>,
+    // Indices of the options of the current choice which passed their conditions.
+    shown_choices: Vec<usize>,
+    // Choice whose selection is recorded once execution moves past it.
+    pending_choice: Option<(Symbol, usize)>,
+    // Text line which counts as read once the player advances past it.
+    pending_text: Option<(Symbol, usize)>,
+    skip_seen_only: bool,
+    auto_mode: Option<AutoMode>,
+    streamer_safe: bool,
+    persistent: PersistentStore,
+    rng: u64,
+    pagination: Option<Pagination>,
+    // Pages of the text line at `pc` which haven't been shown yet, with their spans.
+    pending_pages: VecDeque<(String, Vec<TextSpan>)>,
+    // Instructions `step` may run without the player seeing text or a choice.
+    instruction_budget: Option<usize>,
+    instructions_run: usize,
+    autosave: Option<Autosave>,
+    quicksave: Option<SerializedState>,
+    quicksave_file: Option<PathBuf>,
+    // Overrides picking the format by file extension.
+    save_format: Option<SaveFormat>,
+    // Oldest first.
+    checkpoints: VecDeque<Checkpoint>,
+    checkpoint_limit: usize,
+    // Named labels of the current script by position, keyed by the script and its hash.
+    label_positions: Option<(Symbol, u64, HashMap<usize, String>)>,
+}
+
+// Everything a playthrough changes, kept in memory. Only meaningful to the engine which
+// took it, as variables and scripts are referred to by symbol.
+#[derive(Clone)]
+pub struct StateSnapshot {
+    memory: HashMap<Symbol, HashMap<usize, Value>>,
+    globals: HashMap<Symbol, HashMap<usize, Value>>,
+    pc: usize,
+    pc_to_save: usize,
+    current_script: Symbol,
+    last_music: Option<String>,
+    sound_loop: Option<String>,
+    last_background: Option<PathBuf>,
+    last_images: BTreeMap<String, ShownImage>,
+    current_title: Option<String>,
+    current_chapter: Option<String>,
+    backlog: Backlog,
+    pending_jump_label: Option<Label>,
+    shown_choices: Vec<usize>,
+    pending_choice: Option<(Symbol, usize)>,
+    pending_text: Option<(Symbol, usize)>,
+    rng: u64,
+}
+
+// Snapshot taken when execution reached a label, to go back to the start of a scene.
+pub struct Checkpoint {
+    pub script: String,
+    pub label: String,
+    // Chapter the scene is in, set by the last `chapter` before the next checkpoint, so
+    // frontends can show a timeline of the playthrough.
+    pub chapter: Option<String>,
+    snapshot: StateSnapshot,
+}
+
+// Checkpoints kept unless the frontend asks for another amount.
+pub const DEFAULT_CHECKPOINTS: usize = 10;
+
+// There's no view mode in here: scripts can't switch between ADV and NVL, how text is
+// laid out is the frontend's `Pagination`, which outlives loading a save.
+#[derive(Clone, Serialize, Deserialize)]
+struct SerializedState {
+    #[serde(default)]
+    version: u32,
+    memory: HashMap<String, HashMap<usize, Value>>,
+    #[serde(default)]
+    globals: HashMap<String, HashMap<usize, Value>>,
+    current_script: String,
+    pc: usize,
+    // Hash of the script `pc` points into, to tell whether it has been patched since.
+    #[serde(default)]
+    script_hash: Option<u64>,
+    // Of every script loaded when saving, by name.
+    #[serde(default)]
+    script_hashes: BTreeMap<String, u64>,
+    // Length of that script, for the error when it has changed.
+    #[serde(default)]
+    script_len: Option<usize>,
+    // Last named label at or before `pc`, to resume from when the script has changed.
+    #[serde(default)]
+    resume_label: Option<String>,
+    last_music: Option<String>,
+    last_background: Option<PathBuf>,
+    #[serde(default)]
+    images: BTreeMap<String, ShownImage>,
+    // Saves from before image slots only had these two.
+    #[serde(default, skip_serializing)]
+    last_main_image: Option<PathBuf>,
+    #[serde(default, skip_serializing)]
+    last_date_image: Option<PathBuf>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    chapter: Option<String>,
+    #[serde(default)]
+    sound_loop: Option<String>,
+    // Seconds since the Unix epoch.
+    #[serde(default)]
+    saved_at: Option<u64>,
+    #[serde(default)]
+    last_line: Option<(Option<String>, String)>,
+    #[serde(default)]
+    backlog: Vec<BacklogEntry>,
+    // In seconds.
+    #[serde(default)]
+    play_time: u64,
+    // Scripts of this language were played, see `set_language`.
+    #[serde(default)]
+    language: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ShownImage {
+    path: PathBuf,
+    x: usize,
+    y: usize,
+}
+
+impl EngineState {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self::with_options(directory, ScriptOptions::default())
+    }
+
+    pub fn with_options(directory: impl Into<PathBuf>, mut script_options: ScriptOptions) -> Self {
+        let directory = directory.into();
+        let manifest = GameManifest::load(&directory);
+        // A key given by the frontend wins over the one in the manifest.
+        if script_options.decryptor.is_none() {
+            match manifest.script_key() {
+                Ok(key) => script_options.decryptor = key.map(|key| Arc::new(key) as _),
+                Err(e) => warn!(%e, "scripts can't be decrypted"),
+            }
+        }
+        let mut symbols = SymbolTable::default();
+        let mut state = Self {
+            scripts: ScriptCache::new(Some(DEFAULT_SCRIPT_CACHE_CAPACITY)),
+            current_script: symbols.intern(&manifest.entry_script),
+            symbols,
+            memory: Default::default(),
+            globals: Default::default(),
+            pc: 0,
+            language: None,
+            translation: None,
+            last_music: None,
+            sound_loop: None,
+            last_background: None,
+            last_images: BTreeMap::new(),
+            image_slots: ImageSlots::load(&directory, script_options.resources()),
+            gallery: Gallery::load(&directory, script_options.resources()),
+            directory,
+            manifest,
+            current_title: None,
+            current_chapter: None,
+            last_line: None,
+            backlog: Backlog::default(),
+            play_time: Duration::ZERO,
+            pc_to_save: 0,
+            observers: vec![],
+            script_options,
+            pending_jump_label: None,
+            shown_choices: vec![],
+            pending_choice: None,
+            pending_text: None,
+            skip_seen_only: true,
+            auto_mode: None,
+            streamer_safe: false,
+            persistent: PersistentStore::default(),
+            rng: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64) | 1,
+            pagination: None,
+            pending_pages: VecDeque::new(),
+            instruction_budget: Some(DEFAULT_INSTRUCTION_BUDGET),
+            instructions_run: 0,
+            autosave: None,
+            quicksave: None,
+            quicksave_file: None,
+            save_format: None,
+            checkpoints: VecDeque::new(),
+            checkpoint_limit: DEFAULT_CHECKPOINTS,
+            label_positions: None,
+        };
+        let entry_script = state.manifest.entry_script.clone();
+        if let Err(e) = state.load_script(&entry_script) {
+            warn!(%e, "can't start the game");
+        }
+        state
+    }
+
+    pub fn save(&self, file: impl AsRef<Path>) -> Result<(), GameError> {
+        let file = file.as_ref();
+        let format = self.save_format.unwrap_or_else(|| SaveFormat::for_path(file));
+        write_save(file, &self.serialize(), format)
+    }
+
+    // For saves which don't live in files, like browser storage. Errors don't name a file.
+    pub fn save_to(&self, mut writer: impl Write, format: SaveFormat) -> Result<(), GameError> {
+        let mut write = || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            writer.write_all(&format.encode(&self.serialize())?)?;
+            Ok(writer.flush()?)
+        };
+        write().map_err(|e| GameError::Save(PathBuf::new(), e))
+    }
+
+    // Reads saves in either format.
+    pub fn load_from(&mut self, mut reader: impl Read) -> Result<Vec<StepResult>, GameError> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data).map_err(|e| GameError::Save(PathBuf::new(), e.into()))?;
+        let serialized = parse_save(&data).map_err(|e| GameError::Save(PathBuf::new(), Box::new(e)))?;
+        self.load_serialized(serialized, Path::new(""))
+    }
+
+    fn serialize(&self) -> SerializedState {
+        SerializedState {
+            version: SAVE_VERSION,
+            pc: self.pc_to_save,
+            last_music: self.last_music.clone(),
+            sound_loop: self.sound_loop.clone(),
+            last_background: self.last_background.clone(),
+            current_script: self.current_script().to_string(),
+            script_hash: self.scripts.get(self.current_script).map(|script| script.hash),
+            script_hashes: self.scripts.iter().map(|(name, script)| (self.symbols.name(name).to_string(), script.hash)).collect(),
+            script_len: self.scripts.get(self.current_script).map(|script| script.code.len()),
+            resume_label: self.scripts
+                .get(self.current_script)
+                .and_then(|script| resume_label(script, self.pc_to_save))
+                .map(str::to_string),
+            memory: self.named_variables(false),
+            globals: self.named_variables(true),
+            images: self.last_images.clone(),
+            last_main_image: None,
+            last_date_image: None,
+            title: self.current_title.clone(),
+            chapter: self.current_chapter.clone(),
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()),
+            last_line: self.last_line.clone(),
+            backlog: self.backlog.entries().iter().cloned().collect(),
+            play_time: self.play_time().as_secs(),
+            language: self.language.clone(),
+        }
+    }
+
+    pub fn load(&mut self, file: impl AsRef<Path>) -> Result<Vec<StepResult>, GameError> {
+        let file = file.as_ref();
+        let serialized = read_save(file)?;
+        self.load_serialized(serialized, file)
+    }
+
+    // Quicksaves are kept in memory, so loading them is instant, and also written to
+    // the quicksave file if there is one.
+    pub fn quicksave(&mut self) -> Result<(), GameError> {
+        let serialized = self.serialize();
+        if let Some(file) = &self.quicksave_file {
+            write_save(file, &serialized, self.save_format.unwrap_or_else(|| SaveFormat::for_path(file)))?;
+        }
+        self.quicksave = Some(serialized);
+        Ok(())
+    }
+
+    // Falls back to the quicksave file when nothing was quicksaved since starting.
+    // Quicksaves only kept in memory have no file to report errors against.
+    pub fn quickload(&mut self) -> Result<Vec<StepResult>, GameError> {
+        let file = self.quicksave_file.clone().unwrap_or_default();
+        let serialized = match &self.quicksave {
+            Some(serialized) => serialized.clone(),
+            None if file.is_file() => read_save(&file)?,
+            None => return Err(GameError::Save(file, Box::new(SaveError::NoQuicksave))),
+        };
+        self.load_serialized(serialized, &file)
+    }
+
+    pub fn has_quicksave(&self) -> bool {
+        self.quicksave.is_some() || matches!(&self.quicksave_file, Some(file) if file.is_file())
+    }
+
+    // `None` keeps quicksaves in memory only.
+    pub fn set_quicksave_file(&mut self, file: Option<PathBuf>) {
+        self.quicksave_file = file;
+        self.quicksave = None;
+    }
+
+    fn load_serialized(&mut self, serialized: SerializedState, file: &Path) -> Result<Vec<StepResult>, GameError> {
+        // Everything that can fail is worked out first, so a save that can't be loaded
+        // leaves the game running where it was.
+        let language = serialized.language.as_deref();
+        let changed = self.changed_scripts(&serialized);
+        let name = &serialized.current_script;
+        let path = self.language_script_path(language, name);
+        if !self.script_options.resources().is_file(&path) {
+            return Err(GameError::Save(file.to_path_buf(), Box::new(SaveError::ScriptMismatch(vec![name.clone()]))));
+        }
+        let symbol = self.symbols.intern(name);
+        let same_language = self.language == serialized.language;
+        // Parsed aside when it isn't cached, it only becomes current once it's known to fit.
+        let (pc, parsed) = match self.scripts.get(symbol).filter(|_| same_language) {
+            Some(script) => (self.saved_pc(script, &serialized, file)?, None),
+            None => {
+                let script = load_script(&path, &self.script_options).map_err(|e| GameError::Parse(name.to_string(), e))?;
+                (self.saved_pc(&script, &serialized, file)?, Some(script))
+            }
+        };
+
+        if !same_language {
+            self.language = serialized.language.clone();
+            self.scripts.clear();
+        }
+        self.checkpoints.clear();
+        if let Some(script) = parsed {
+            self.cache_script(symbol, script);
+        }
+        self.load_script(name)?;
+        self.pc = pc;
+        self.pending_choice = None;
+        self.pending_text = None;
+        self.pc_to_save = self.pc;
+        self.memory = self.interned_variables(serialized.memory);
+        // Globals outlive playthroughs, so ones set since the save was made are kept.
+        let mut globals = self.interned_variables(serialized.globals);
+        for (name, values) in std::mem::take(&mut self.globals) {
+            globals.entry(name).or_default().extend(values);
+        }
+        self.globals = globals;
+        self.current_title = serialized.title.clone();
+        self.current_chapter = serialized.chapter.clone();
+        self.last_line = serialized.last_line.clone();
+        self.backlog.replace(serialized.backlog.clone());
+        self.play_time = Duration::from_secs(serialized.play_time);
+
+        self.last_background = serialized.last_background;
+        // Saves from before `MusicStop` kept `~` as the last music.
+        self.last_music = serialized.last_music.filter(|music| music != "~");
+        self.sound_loop = serialized.sound_loop;
+        let mut images = serialized.images;
+        let legacy_images = vec![("main", serialized.last_main_image), ("date", serialized.last_date_image)];
+        for (name, path) in legacy_images {
+            if let Some(path) = path {
+                // Positions weren't saved back then.
+                images.entry(name.to_string()).or_insert(ShownImage { path, x: 0, y: 0 });
+            }
+        }
+        for name in images.keys().filter(|name| self.image_slots.get(name).is_none()) {
+            warn!(slot = %name, "saved image slot doesn't exist anymore");
+        }
+        images.retain(|name, _| self.image_slots.get(name).is_some());
+        self.last_images = images;
+
+        let mut steps = self.scene();
+        if !changed.is_empty() {
+            warn!(scripts = ?changed, "game files changed since the save");
+            steps.insert(0, StepResult::SaveWarning(SaveError::ScriptMismatch(changed)));
+        }
+        Ok(steps)
+    }
+
+    // Everything on screen and playing is set, so the scene is the same whatever was
+    // shown before. The text box starts out empty, resuming shows the line.
+    fn scene(&self) -> Vec<StepResult> {
+        let mut steps = vec![StepResult::Clear];
+        if let Some(title) = &self.current_title {
+            steps.push(StepResult::Title(title.clone()));
+        }
+        if let Some(chapter) = &self.current_chapter {
+            steps.push(StepResult::Chapter(chapter.clone()));
+        }
+        if let Some(background) = &self.last_background {
+            steps.push(StepResult::Background(background.clone()));
+        }
+        steps.push(match self.last_music.clone() {
+            Some(music) => StepResult::Music { file: music, fade: None },
+            None => StepResult::MusicStop { fade: None },
+        });
+        steps.push(match self.sound_loop.clone() {
+            Some(sound) => StepResult::Sound(sound, Some(-1)),
+            None => StepResult::Sound("~".to_string(), None),
+        });
+        // From the lowest layer up, so frontends which stack sprites in order get it right.
+        for slot in self.image_slots.by_layer() {
+            match self.last_images.get(&slot.name) {
+                Some(image) => steps.push(StepResult::Image(image.path.clone(), slot.clone(), image.x, image.y)),
+                None => steps.push(StepResult::ClearImage(slot.clone())),
+            }
+        }
+        steps
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            memory: self.memory.clone(),
+            globals: self.globals.clone(),
+            pc: self.pc,
+            pc_to_save: self.pc_to_save,
+            current_script: self.current_script,
+            last_music: self.last_music.clone(),
+            sound_loop: self.sound_loop.clone(),
+            last_background: self.last_background.clone(),
+            last_images: self.last_images.clone(),
+            current_title: self.current_title.clone(),
+            current_chapter: self.current_chapter.clone(),
+            backlog: self.backlog.clone(),
+            pending_jump_label: self.pending_jump_label.clone(),
+            shown_choices: self.shown_choices.clone(),
+            pending_choice: self.pending_choice,
+            pending_text: self.pending_text,
+            rng: self.rng,
+        }
+    }
+
+    // Goes back to exactly where the snapshot was taken. Persistent data isn't rolled
+    // back, read text stays read.
+    pub fn restore(&mut self, snapshot: &StateSnapshot) -> Result<(), GameError> {
+        // The script may have been evicted from the cache since.
+        let name = self.symbols.name(snapshot.current_script).to_string();
+        self.load_script(&name)?;
+        self.memory = snapshot.memory.clone();
+        self.globals = snapshot.globals.clone();
+        self.pc = snapshot.pc;
+        self.pc_to_save = snapshot.pc_to_save;
+        self.last_music = snapshot.last_music.clone();
+        self.sound_loop = snapshot.sound_loop.clone();
+        self.last_background = snapshot.last_background.clone();
+        self.last_images = snapshot.last_images.clone();
+        self.current_title = snapshot.current_title.clone();
+        self.current_chapter = snapshot.current_chapter.clone();
+        self.backlog = snapshot.backlog.clone();
+        self.pending_jump_label = snapshot.pending_jump_label.clone();
+        self.shown_choices = snapshot.shown_choices.clone();
+        self.pending_choice = snapshot.pending_choice;
+        self.pending_text = snapshot.pending_text;
+        self.rng = snapshot.rng;
+        Ok(())
+    }
+
+    // Scripts of a save which are now different, going by their file contents.
+    fn changed_scripts(&self, serialized: &SerializedState) -> Vec<String> {
+        let mut hashes = serialized.script_hashes.clone();
+        // Saves from before all hashes were kept only have the current one.
+        if let Some(hash) = serialized.script_hash {
+            hashes.entry(serialized.current_script.clone()).or_insert(hash);
+        }
+        hashes
+            .into_iter()
+            .filter(|(name, hash)| {
+                let path = self.language_script_path(serialized.language.as_deref(), name);
+                let current = load_script(path, &self.script_options).map(|script| script.hash);
+                current.ok() != Some(*hash)
+            })
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    pub fn checkpoints(&self) -> &VecDeque<Checkpoint> {
+        &self.checkpoints
+    }
+
+    // Goes back to where the checkpoint at `index` in `checkpoints()` was taken, and
+    // returns the steps setting up the scene as it was then, like loading does. Later
+    // checkpoints are dropped, they're taken again when the player gets there.
+    pub fn restore_checkpoint(&mut self, index: usize) -> Result<Vec<StepResult>, GameError> {
+        let checkpoint = match self.checkpoints.get(index) {
+            Some(checkpoint) => checkpoint.snapshot.clone(),
+            None => return Err(GameError::Runtime(format!("there's no checkpoint {}", index))),
+        };
+        self.restore(&checkpoint)?;
+        self.checkpoints.truncate(index);
+        Ok(self.scene())
+    }
+
+    // How many checkpoints to keep, 0 turns them off.
+    pub fn set_checkpoint_limit(&mut self, limit: usize) {
+        self.checkpoint_limit = limit;
+        while self.checkpoints.len() > limit {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    fn record_checkpoint(&mut self, script: &Script) {
+        let is_current = matches!(&self.label_positions, Some((name, hash, _)) if *name == self.current_script && *hash == script.hash);
+        if !is_current {
+            // Of several labels at the same place, the first by name, like `resume_label`.
+            let mut positions: HashMap<usize, String> = HashMap::new();
+            for (label, &at) in &script.labels {
+                if let Label::Named(name) = label {
+                    positions
+                        .entry(at)
+                        .and_modify(|kept| if name < kept { kept.clone_from(name) })
+                        .or_insert_with(|| name.clone());
+                }
+            }
+            self.label_positions = Some((self.current_script, script.hash, positions));
+        }
+        let label = match &self.label_positions {
+            Some((_, _, positions)) => positions.get(&self.pc).cloned(),
+            None => None,
+        };
+        if let Some(label) = label {
+            self.checkpoints.push_back(Checkpoint {
+                script: self.current_script().to_string(),
+                label,
+                chapter: self.current_chapter.clone(),
+                snapshot: self.snapshot(),
+            });
+            if self.checkpoints.len() > self.checkpoint_limit {
+                self.checkpoints.pop_front();
+            }
+        }
+    }
+
+    // Where to resume a save in the installed version of its script. Saves from another
+    // version are remapped when the game ships a table for it, or else resume from the
+    // nearest label before the saved line.
+    fn saved_pc(&self, script: &Script, serialized: &SerializedState, file: &Path) -> Result<usize, GameError> {
+        let remap_file = self.directory.join(REMAP_FILE);
+        let remap = if remap_file.exists() {
+            Some(RemapTable::load(&remap_file).map_err(|e| GameError::Save(remap_file, e.into()))?)
+        } else {
+            None
+        };
+        let is_same_version = serialized.script_hash.is_none_or(|hash| hash == script.hash);
+        let pc = match remap {
+            Some(remap) if is_same_version || remap.contains(&serialized.current_script) => {
+                remap.remap(&serialized.current_script, serialized.script_hash, script.hash, serialized.pc)
+            }
+            _ if is_same_version => serialized.pc,
+            _ => {
+                // Saves from before the label was kept can only guess it from the saved
+                // position, which is right as long as nothing was added above it.
+                let label = serialized.resume_label.as_deref().or_else(|| resume_label(script, serialized.pc));
+                match label.and_then(|label| script.labels.get(&Label::Named(label.to_string())).map(|&at| (label, at))) {
+                    Some((label, at)) => {
+                        warn!(script = %serialized.current_script, ?label, "script changed since the save, resuming from a label");
+                        at
+                    }
+                    None => {
+                        return Err(GameError::Save(file.to_path_buf(), Box::new(SaveError::NoResumePoint {
+                            script: serialized.current_script.clone(),
+                            saved_len: serialized.script_len,
+                            len: script.code.len(),
+                        })));
+                    }
+                }
+            }
+        };
+        if pc > script.code.len() {
+            return Err(GameError::Save(file.to_path_buf(), Box::new(SaveError::Invalid(format!(
+                "line {} is past the end of script {} ({} instructions)",
+                pc, serialized.current_script, script.code.len(),
+            )))));
+        }
+        Ok(pc)
+    }
+
+    // Saves refer to variables by name, symbols are only valid for the running game.
+    fn named_variables(&self, global: bool) -> HashMap<String, HashMap<usize, Value>> {
+        let memory = if global { &self.globals } else { &self.memory };
+        memory
+            .iter()
+            .map(|(&symbol, values)| (self.symbols.name(symbol).to_string(), values.clone()))
+            .collect()
+    }
+
+    fn interned_variables(
+        &mut self,
+        memory: HashMap<String, HashMap<usize, Value>>,
+    ) -> HashMap<Symbol, HashMap<usize, Value>> {
+        memory
+            .into_iter()
+            .map(|(name, values)| (self.symbols.intern(&name), values))
+            .collect()
+    }
+
+    fn insert(&mut self, var: &VarOrConst, val: Value) {
+        self.store(false, var, val);
+    }
+
+    fn insert_global(&mut self, var: &VarOrConst, val: Value) {
+        self.store(true, var, val);
+    }
+
+    fn store(&mut self, global: bool, var: &VarOrConst, val: Value) {
+        let index = self.resolve_index(var);
+        if var.is_ref {
+            warn!("can't assign to ${}, ignoring", var.name);
+            return;
+        }
+        let symbol = match var.symbol {
+            Some(symbol) => symbol,
+            None => self.symbols.intern(&var.name),
+        };
+
+        for observer in self.observers.iter_mut() {
+            observer.on_var_changed(&var.name, index, &val.to_string());
+        }
+
+        let memory = if global { &mut self.globals } else { &mut self.memory };
+        memory
+            .entry(symbol)
+            .or_insert_with(HashMap::new)
+            .insert(index, val);
+    }
+
+    // Removes a single variable or, when `var` is `None`, all of them.
+    fn remove(&mut self, global: bool, var: Option<&VarOrConst>) {
+        let index = var.map_or(0, |var| self.resolve_index(var));
+        let symbol = var.and_then(|var| self.symbol(var));
+        let memory = if global { &mut self.globals } else { &mut self.memory };
+        let removed: Vec<(Symbol, usize)> = match (var, symbol) {
+            (Some(_), Some(symbol)) => {
+                memory.get_mut(&symbol)
+                    .and_then(|values| values.remove(&index))
+                    .map(|_| (symbol, index))
+                    .into_iter()
+                    .collect()
+            }
+            (Some(_), None) => vec![],
+            (None, _) => memory
+                .drain()
+                .flat_map(|(symbol, values)| values.into_keys().map(move |index| (symbol, index)))
+                .collect(),
+        };
+
+        for (symbol, index) in removed {
+            for observer in self.observers.iter_mut() {
+                observer.on_var_changed(self.symbols.name(symbol), index, "");
+            }
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    // Keeps data which outlives save slots, like which choice options were ever picked,
+    // in `file`.
+    pub fn open_persistent_store(&mut self, file: impl Into<PathBuf>) -> Result<(), std::io::Error> {
+        self.persistent = PersistentStore::open(file)?;
+        Ok(())
+    }
+
+    // Imports a bundle from `SaveManager::export_all` into `saves`. The persistent store
+    // is closed meanwhile and opened again, so its global data is the imported one
+    // instead of being written over it.
+    pub fn import_saves(&mut self, saves: &SaveManager, bundle: impl AsRef<Path>) -> Result<Vec<u32>, GameError> {
+        let file = self.persistent.file().map(Path::to_path_buf);
+        self.persistent = PersistentStore::default();
+        let imported = saves.import(bundle);
+        if let Some(file) = file {
+            self.persistent = PersistentStore::open(&file).map_err(|e| GameError::Save(file, e.into()))?;
+        }
+        imported
+    }
+
+    // Writes out persistent data changed since it was last written. This also happens
+    // when the state is dropped, but frontends should call it when the game is closed.
+    pub fn flush_persistent_store(&mut self) -> Result<(), std::io::Error> {
+        self.persistent.flush()
+    }
+
+    // Whether the text line at `pc` of the current script was read in any playthrough.
+    pub fn has_seen(&self, pc: usize) -> bool {
+        self.persistent.was_seen(self.current_script(), pc)
+    }
+
+    // Splits text lines which don't fit into several `Text` results with the same
+    // speaker, `None` passes them on whole.
+    pub fn set_pagination(&mut self, pagination: Option<Pagination>) {
+        self.pagination = pagination;
+    }
+
+    // `None` lets scripts run for as long as they want.
+    pub fn set_instruction_budget(&mut self, budget: Option<usize>) {
+        self.instruction_budget = budget;
+        self.instructions_run = 0;
+    }
+
+    // Lines read so far, oldest first, as kept in saves.
+    pub fn backlog(&self) -> &VecDeque<BacklogEntry> {
+        self.backlog.entries()
+    }
+
+    // How many lines the backlog and saves keep.
+    pub fn set_backlog_len(&mut self, len: usize) {
+        self.backlog.set_capacity(len);
+    }
+
+    // `None` picks the format from the extension of each save file.
+    pub fn set_save_format(&mut self, format: Option<SaveFormat>) {
+        self.save_format = format;
+    }
+
+    // Saves whenever a choice (or chapter, if enabled) is reached, `None` turns it off.
+    pub fn set_autosave(&mut self, autosave: Option<Autosave>) {
+        self.autosave = autosave;
+    }
+
+    // Lets skipping go past lines which haven't been read yet.
+    pub fn set_skip_seen_only(&mut self, skip_seen_only: bool) {
+        self.skip_seen_only = skip_seen_only;
+    }
+
+    // `None` turns auto mode off. Frontends pace lines with `AutoMode::should_advance`.
+    pub fn set_auto_mode(&mut self, auto_mode: Option<AutoMode>) {
+        self.auto_mode = auto_mode;
+    }
+
+    pub fn auto_mode(&self) -> Option<AutoMode> {
+        self.auto_mode
+    }
+
+    // Records how long the player took to move on from a fully shown line of `len`
+    // characters, without auto mode or skipping. Kept in the persistent store, for
+    // `AutoMode::calibrated`.
+    pub fn record_reading(&mut self, len: usize, shown_for: Duration) {
+        self.persistent.record_reading(len, shown_for);
+    }
+
+    pub fn reading_times(&self) -> ReadingTimes {
+        self.persistent.reading_times()
+    }
+
+    // Keeps music tagged `COPYRIGHTED_TAG` in the manifest silent and images tagged
+    // `SPOILER_TAG` out of the gallery, for players streaming the game.
+    pub fn set_streamer_safe(&mut self, streamer_safe: bool) {
+        self.streamer_safe = streamer_safe;
+    }
+
+    pub fn is_streamer_safe(&self) -> bool {
+        self.streamer_safe
+    }
+
+    // Whether the frontend should play `track`, as named in `StepResult::Music`, muted.
+    // Muted rather than stopped, so it's heard again when streamer-safe mode is turned off.
+    pub fn is_muted(&self, track: &str) -> bool {
+        self.streamer_safe && self.manifest.has_tag(track, COPYRIGHTED_TAG)
+    }
+
+    // Whether the text line being shown may be skipped.
+    pub fn can_skip(&self) -> bool {
+        !self.skip_seen_only || self.pending_text
+            .as_ref()
+            .is_some_and(|&(script, pc)| self.persistent.was_seen(self.symbols.name(script), pc))
+    }
+
+    // Gallery images shown in any playthrough, in gallery order.
+    pub fn unlocked_cgs(&self) -> Vec<&str> {
+        self.gallery
+            .images()
+            .iter()
+            .filter(|image| self.persistent.is_cg_unlocked(image))
+            .filter(|image| !self.streamer_safe || !self.manifest.has_tag(image, SPOILER_TAG))
+            .map(String::as_str)
+            .collect()
+    }
+
+    // Music files played in any playthrough, sorted by name.
+    pub fn unlocked_tracks(&self) -> Vec<&str> {
+        self.persistent.played_tracks().collect()
+    }
+
+    // Ids of the endings reached in any playthrough, sorted.
+    pub fn completed_endings(&self) -> Vec<&str> {
+        self.persistent.completed_endings().collect()
+    }
+
+    // Failing to autosave shouldn't stop the game.
+    fn write_autosave(&self) {
+        if let Some(autosave) = &self.autosave {
+            if let Err(e) = autosave.saves.save(self, autosave.slot) {
+                warn!(%e, "autosave failed");
+            }
+        }
+    }
+
+    // Where the frontend finds an asset of the game, as the resources of the game say.
+    // Backgrounds and images come resolved already, sounds, music and voice clips are
+    // named like in the script so saves and the music room don't depend on it.
+    pub fn asset_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.script_options.resources().resolve(&self.directory.join(path))
+    }
+
+    fn unlock_cg(&mut self, folder: &str, name: &str) {
+        let image = format!("{}/{}", folder, name);
+        if self.gallery.contains(&image) {
+            self.persistent.unlock_cg(&image);
+        }
+    }
+
+    pub fn add_observer(&mut self, observer: impl EngineObserver + Send + Sync + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    fn resolve_index(&self, var: &VarOrConst) -> usize {
+        let index = match &var.index {
+            Some(index) => index,
+            None => return 0,
+        };
+        match self.get_var(index).and_then(|index| index.as_int()).map(usize::try_from) {
+            Some(Ok(index)) => index,
+            _ => {
+                warn!("invalid index {:?} of variable {}, using 0", index, var.name);
+                0
+            }
+        }
+    }
+
+    fn get_var(&self, var: &VarOrConst) -> Option<Value> {
+        if !var.is_ref {
+            return Some(Value::parse(&var.name));
+        }
+
+        let index = self.resolve_index(var);
+        let symbol = self.symbol(var)?;
+        self.memory
+            .get(&symbol)
+            .and_then(|values| values.get(&index))
+            .or_else(|| self.globals.get(&symbol)?.get(&index))
+            .cloned()
+    }
+
+    // Variables which were never assigned have no symbol yet.
+    fn symbol(&self, var: &VarOrConst) -> Option<Symbol> {
+        var.symbol.or_else(|| self.symbols.get(&var.name))
+    }
+
+    pub fn current_script(&self) -> &str {
+        self.symbols.name(self.current_script)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn manifest(&self) -> &GameManifest {
+        &self.manifest
+    }
+
+    pub fn current_title(&self) -> Option<&str> {
+        self.current_title.as_deref()
+    }
+
+    pub fn current_chapter(&self) -> Option<&str> {
+        self.current_chapter.as_deref()
+    }
+
+    // Including the time played before the playthrough was last loaded.
+    pub fn play_time(&self) -> Duration {
+        self.play_time
+    }
+
+    // Time played in all playthroughs, kept with the persistent data.
+    pub fn total_play_time(&self) -> Duration {
+        self.persistent.play_time()
+    }
+
+    // Frontends call this every frame the game is being played with the time since
+    // the last one, so time spent in menus or minimized can be left out.
+    pub fn tick(&mut self, delta: Duration) {
+        self.play_time += delta;
+        self.persistent.add_play_time(delta);
+    }
+
+    // Chapter a save was made in, to label save slots without loading them.
+    pub fn saved_chapter(file: impl AsRef<Path>) -> Result<Option<String>, GameError> {
+        Ok(read_save(file.as_ref())?.chapter)
+    }
+
+    // Limits how many compiled scripts are kept in memory, `None` keeps all of them.
+    pub fn set_script_cache_capacity(&mut self, capacity: Option<usize>) {
+        self.scripts.set_capacity(capacity);
+    }
+
+    // Compiles every script of the game upfront instead of on first use.
+    // Only useful together with an unbounded (or large enough) cache.
+    pub fn preload_scripts(&mut self) -> Result<(), GameError> {
+        let scripts_dir = self.manifest.scripts_dir(&self.directory);
+        let names = list_scripts(&scripts_dir, &self.script_options).map_err(|e| GameError::Asset(scripts_dir.clone(), e.into()))?;
+        for name in names {
+            let symbol = self.symbols.intern(&name);
+            if !self.scripts.contains(symbol) {
+                let script = load_script(self.script_path(&name), &self.script_options)
+                    .map_err(|e| GameError::Parse(name.clone(), e))?;
+                self.cache_script(symbol, script);
+            }
+        }
+        self.scripts.touch(self.current_script);
+        Ok(())
+    }
+
+    pub fn cached_scripts(&self) -> usize {
+        self.scripts.len()
+    }
+
+    // Re-parses the current script from disk and moves the pc to the nearest text or label
+    // preceding it, so the current scene can continue with the edited script.
+    pub fn reload_current_script(&mut self) -> Result<(), GameError> {
+        let name = self.current_script().to_string();
+        let script = load_script(self.script_path(&name), &self.script_options)
+            .map_err(|e| GameError::Parse(name.clone(), e))?;
+        let pc = match self.scripts.get(self.current_script) {
+            Some(old) => anchor::resolve(&script, &anchor::find(old, self.pc)),
+            None => 0,
+        };
+
+        self.cache_script(self.current_script, script);
+        self.pc = pc;
+        self.pc_to_save = pc;
+        for observer in self.observers.iter_mut() {
+            observer.on_script_loaded(&name);
+        }
+        Ok(())
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    // Switches to the scripts of another language, `None` going back to the default ones.
+    // Translations are expected to keep the instructions of the originals in place, so the
+    // current line is shown again in the new language on the next step.
+    pub fn set_language(&mut self, language: Option<&str>) -> Result<(), GameError> {
+        self.language = language.map(str::to_string);
+        self.scripts.clear();
+        let name = self.current_script().to_string();
+        let script = load_script(self.script_path(&name), &self.script_options)
+            .map_err(|e| GameError::Parse(name.clone(), e))?;
+        self.pc_to_save = self.pc_to_save.min(script.code.len());
+        self.pc = self.pc_to_save;
+        self.cache_script(self.current_script, script);
+        for observer in self.observers.iter_mut() {
+            observer.on_script_loaded(&name);
+        }
+        Ok(())
+    }
+
+    // Loads a table of translated lines by line id, as assigned in the game's line id file.
+    pub fn load_translation(&mut self, file: impl AsRef<Path>) -> Result<(), GameError> {
+        let file = file.as_ref();
+        let translation = Translation::load(&self.directory.join(LINE_IDS_FILE), file)
+            .map_err(|e| GameError::Asset(file.to_path_buf(), e.into()))?;
+        self.translation = Some(translation);
+        Ok(())
+    }
+
+    pub fn clear_translation(&mut self) {
+        self.translation = None;
+    }
+
+    fn script_path(&self, name: &str) -> PathBuf {
+        self.language_script_path(self.language.as_deref(), name)
+    }
+
+    fn language_script_path(&self, language: Option<&str>, name: &str) -> PathBuf {
+        let scripts_dir = self.manifest.scripts_dir(&self.directory);
+        language
+            .map(|language| scripts_dir.join(language).join(name))
+            .filter(|path| self.script_options.resources().is_file(path))
+            .unwrap_or_else(|| scripts_dir.join(name))
+    }
+
+    pub fn load_script(&mut self, name: &str) -> Result<(), GameError> {
+        let symbol = self.symbols.intern(name);
+        if self.scripts.contains(symbol) {
+            self.scripts.touch(symbol);
+        } else {
+            let script = load_script(self.script_path(name), &self.script_options)
+                .map_err(|e| GameError::Parse(name.to_string(), e))?;
+            self.cache_script(symbol, script);
+        }
+        self.current_script = symbol;
+        self.pending_pages.clear();
+        self.pc = match self.pending_jump_label.take() {
+            Some(label) => self.scripts.get(symbol).and_then(|s| s.labels.get(&label)).copied().unwrap_or(0),
+            None => 0,
+        };
+        for observer in self.observers.iter_mut() {
+            observer.on_script_loaded(name);
+        }
+        Ok(())
+    }
+
+    fn cache_script(&mut self, name: Symbol, mut script: Script) {
+        self.symbols.intern_script(&mut script);
+        self.scripts.insert(name, script);
+    }
+
+    // Starts over from the entry script, as after returning to the title. Global variables and
+    // persistent data are kept, and compiled scripts stay cached.
+    pub fn new_game(&mut self) -> Result<(), GameError> {
+        self.remove(false, None);
+        self.last_music = None;
+        self.sound_loop = None;
+        self.last_background = None;
+        self.last_images.clear();
+        self.current_title = None;
+        self.current_chapter = None;
+        self.last_line = None;
+        self.backlog.clear();
+        self.checkpoints.clear();
+        self.play_time = Duration::ZERO;
+        self.pc_to_save = 0;
+        self.pending_jump_label = None;
+        self.shown_choices.clear();
+        self.pending_choice = None;
+        self.pending_text = None;
+        let entry_script = self.manifest.entry_script.clone();
+        self.load_script(&entry_script)
+    }
+
+    // Runs the game until it exits, for frontends which don't need every `Continue`.
+    pub fn steps(&mut self) -> Steps<'_> {
+        Steps { state: self, done: false }
+    }
+
+    // Images and music the script will most likely need next, up to `limit` of them, for
+    // frontends to preload. Only follows the flow up to the next branch, choice or jump,
+    // as where those go isn't known yet. Variables are read with their current values.
+    pub fn upcoming_assets(&self, limit: usize) -> Vec<PathBuf> {
+        let script = match self.scripts.get(self.current_script) {
+            Some(script) => script,
+            None => return vec![],
+        };
+        let mut assets: Vec<PathBuf> = vec![];
+        let mut visited = HashSet::new();
+        let mut pc = self.pc;
+        while assets.len() < limit && visited.insert(pc) {
+            let asset = match script.code.get(pc) {
+                Some(Instr::bgload(file, _)) => self.get_var(file)
+                    .map(|name| self.asset_path(Path::new(&self.manifest.directories.backgrounds).join(name.to_string()))),
+                Some(Instr::setimg(file, ..)) => self.get_var(file)
+                    .map(|name| name.to_string())
+                    .filter(|name| !name.is_empty() && name != "~")
+                    .map(|name| self.asset_path(Path::new(&self.manifest.directories.images).join(name))),
+                Some(Instr::music(file, _)) => Some(self.asset_path(file)),
+                Some(Instr::goto(label)) => {
+                    pc = match label {
+                        Label::Offset(target) => *target,
+                        label => match script.labels.get(label) {
+                            Some(&target) => target,
+                            None => break,
+                        },
+                    };
+                    continue;
+                }
+                Some(Instr::branch(..)) | Some(Instr::choice(_)) | Some(Instr::jump(..)) | Some(Instr::endscript) | None => break,
+                Some(_) => None,
+            };
+            if let Some(asset) = asset.filter(|asset| !assets.contains(asset)) {
+                assets.push(asset);
+            }
+            pc += 1;
+        }
+        assets
+    }
+
+    // Runs up to the next text line which waits, choice or exit, which comes last, so
+    // frontends can apply everything before it in one go. Also stops when the
+    // instruction budget runs out.
+    pub fn run_until_interactive(&mut self) -> Vec<StepResult> {
+        let mut results = vec![];
+        for result in self.steps() {
+            let is_interactive = matches!(result, StepResult::Text(_, _, true, _) | StepResult::Choice(..) | StepResult::Yield);
+            results.push(result);
+            if is_interactive {
+                return results;
+            }
+        }
+        results.push(StepResult::Exit);
+        results
+    }
+
+    // Indices into all options of the current choice of the ones which were shown, in
+    // the order they were, as some may be hidden by their conditions.
+    pub fn shown_choices(&self) -> &[usize] {
+        &self.shown_choices
+    }
+
+    // `index` is into the options which were shown.
+    pub fn set_choice(&mut self, index: usize) {
+        let index = self.shown_choices.get(index).copied().unwrap_or(index);
+        self.insert(&VarOrConst {
+            is_ref: false,
+            name: "selected".to_string(),
+            index: None,
+            symbol: None,
+        }, Value::Int(index as i64 + 1));
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub enum StepResult {
+    Clear,
+    Continue,
+    // The instruction budget ran out, the script may be stuck in a loop. Stepping again
+    // carries on with a fresh budget.
+    Yield,
+    Exit,
+    Jump(String),
+    // Options, and whether each was picked in any earlier playthrough.
+    Choice(Vec<String>, Vec<bool>),
+    // An empty line which waits is a blank line, one that doesn't is only a marker.
+    // Text without control codes, and the spans it consists of.
+    Text(Option<String>, String, bool, Vec<TextSpan>),
+    Background(PathBuf),
+    Image(PathBuf, ImageSlot, usize, usize),
+    // Takes down the image of the slot, for `setimg ~` or an unset variable.
+    ClearImage(ImageSlot),
+    // Number of times to play the sound as in VNDS, where -1 loops it until the next
+    // `sound ~`. Played once when missing.
+    Sound(String, Option<i32>),
+    // Fades in over `fade` when given, like `MusicStop` fades out.
+    Music { file: String, fade: Option<Duration> },
+    MusicStop { fade: Option<Duration> },
+    // Voice clip of the text line which follows it.
+    Voice(String),
+    Title(String),
+    Chapter(String),
+    // An ending was reached, it's already listed in `completed_endings`.
+    Ending(String),
+    // Only returned by `load`, before the rest of the scene.
+    SaveWarning(SaveError),
+}
+
+// Yields everything but `Continue` until `Exit`, which isn't yielded. Scripts are loaded
+// before their `Jump` is yielded, one which fails to load ends the iteration. A `Yield`
+// ends it too, so scripts stuck in a loop can't make it endless. Choices keep their
+// default unless picked through `state()`.
+pub struct Steps<'a> {
+    state: &'a mut EngineState,
+    done: bool,
+}
+
+impl Steps<'_> {
+    pub fn state(&mut self) -> &mut EngineState {
+        self.state
+    }
+}
+
+impl Iterator for Steps<'_> {
+    type Item = StepResult;
+
+    fn next(&mut self) -> Option<StepResult> {
+        while !self.done {
+            match step(self.state) {
+                StepResult::Continue => (),
+                StepResult::Exit => self.done = true,
+                StepResult::Yield => {
+                    self.done = true;
+                    return Some(StepResult::Yield);
+                }
+                StepResult::Jump(file) => {
+                    if let Err(e) = self.state.load_script(&file) {
+                        warn!(%e, "stopping");
+                        self.done = true;
+                    } else {
+                        return Some(StepResult::Jump(file));
+                    }
+                }
+                result => return Some(result),
+            }
+        }
+        None
+    }
+}
+
+pub fn step(state: &mut EngineState) -> StepResult {
+    if state.instruction_budget.is_some_and(|budget| state.instructions_run >= budget) {
+        warn!(script = state.current_script(), pc = state.pc, "script didn't wait for the player in a while, yielding");
+        state.instructions_run = 0;
+        return StepResult::Yield;
+    }
+    let result = execute(state);
+    match result {
+        StepResult::Text(_, _, true, _) | StepResult::Choice(..) | StepResult::Exit => state.instructions_run = 0,
+        _ => state.instructions_run += 1,
+    }
+    result
+}
+
+fn execute(state: &mut EngineState) -> StepResult {
+    if let Some((script, pc)) = state.pending_choice.take() {
+        let selected = state.get_var(&VarOrConst {
+            is_ref: true,
+            name: "selected".to_string(),
+            index: None,
+            symbol: None,
+        });
+        if let Some(selected) = selected.map(|v| v.to_int()).filter(|&v| v > 0) {
+            state.persistent.mark_selected(state.symbols.name(script), pc, selected as usize - 1);
+        }
+    }
+    if let Some((script, pc)) = state.pending_text.take() {
+        state.persistent.mark_seen(state.symbols.name(script), pc);
+    }
+
+    // Instructions are executed in place, only what ends up in the result is cloned.
+    let script = match state.scripts.get_shared(state.current_script) {
+        Some(script) => script,
+        None => return StepResult::Exit,
+    };
+    let curr_inst = match script.code.get(state.pc) {
+        Some(ci) => ci,
+        None => return StepResult::Exit,
+    };
+    let _span = tracing::debug_span!("step", script = state.current_script(), pc = state.pc).entered();
+    trace!(instruction = ?curr_inst, "executing");
+    // Later pages of a line are shown without moving past its label again.
+    if state.checkpoint_limit > 0 && state.pending_pages.is_empty() {
+        state.record_checkpoint(&script);
+    }
+    for observer in state.observers.iter_mut() {
+        observer.on_instruction(state.symbols.name(state.current_script), state.pc, curr_inst);
+    }
+    match curr_inst {
+        Instr::cleartext => {
+            state.pc += 1;
+            return StepResult::Clear;
+        }
+        Instr::gsetvar(ident, value) => {
+            let value = value.eval(state);
+            state.insert_global(ident, value);
+        }
+        Instr::setvar(ident, value) => {
+            let value = value.eval(state);
+            state.insert(ident, value);
+        }
+        Instr::bgload(file, time) => {
+            debug!(?file, ?time, "loading background");
+            state.pc += 1;
+            let name = state.get_var(file).map(|v| v.to_string()).unwrap_or_default();
+            let folder = state.manifest.directories.backgrounds.clone();
+            state.unlock_cg(&folder, &name);
+            let path = state.asset_path(Path::new(&folder).join(&name));
+            state.last_background = Some(path.clone());
+            return StepResult::Background(path);
+        }
+        Instr::setimg(file, x, y, slot) => {
+            debug!(?file, x, y, ?slot, "loading image");
+            state.pc += 1;
+            let name = state.get_var(file).map(|value| value.to_string()).unwrap_or_default();
+            let slot = match slot.as_deref().map(|name| (name, state.image_slots.get(name))) {
+                Some((_, Some(slot))) => slot.clone(),
+                Some((name, None)) => {
+                    warn!(slot = name, "unknown image slot, using the default one");
+                    state.image_slots.for_variable(&file.name).clone()
+                }
+                None => state.image_slots.for_variable(&file.name).clone(),
+            };
+            if name.is_empty() || name == "~" {
+                state.last_images.remove(&slot.name);
+                return StepResult::ClearImage(slot);
+            }
+            let folder = state.manifest.directories.images.clone();
+            state.unlock_cg(&folder, &name);
+            let path = state.asset_path(Path::new(&folder).join(&name));
+            state.last_images.insert(slot.name.clone(), ShownImage { path: path.clone(), x: *x, y: *y });
+            return StepResult::Image(path, slot, *x, *y);
+        }
+        Instr::delay(delay) => {
+            debug!(delay, "waiting");
+        }
+        Instr::branch(lhs, op, rhs, else_target) => {
+            if expr::compare(lhs, *op, rhs, state) {
+                state.pc += 1;
+            } else {
+                state.pc = *else_target;
+            }
+            return StepResult::Continue;
+        }
+        Instr::text(who, what, wait) => {
+            if *wait {
+                state.pc_to_save = state.pc;
+                state.pending_text = Some((state.current_script, state.pc));
+            }
+            // Stays on the line until its last page is shown.
+            let (what, spans) = match state.pending_pages.pop_front() {
+                Some(page) => page,
+                None => {
+                    let what = match state.translation.as_ref().and_then(|t| t.text(state.symbols.name(state.current_script), state.pc)) {
+                        Some(translated) => translated.to_string(),
+                        None => what.clone(),
+                    };
+                    // Control codes don't take up room, so only the plain text is paginated.
+                    let (plain, mut spans) = parse_markup(&what, &state.manifest.gaiji);
+                    for span in &mut spans {
+                        if let TextSpan::Image(image) = span {
+                            *image = state.asset_path(&*image);
+                        }
+                    }
+                    let mut pages: VecDeque<(String, Vec<TextSpan>)> = match &state.pagination {
+                        Some(pagination) => {
+                            let ranges = pagination.page_ranges(&plain);
+                            let texts = ranges.iter().map(|range| plain[range.clone()].to_string());
+                            texts.zip(split_spans(&spans, &ranges)).collect()
+                        }
+                        None => vec![(plain, spans)].into(),
+                    };
+                    let first = pages.pop_front().unwrap_or_default();
+                    state.pending_pages = pages;
+                    first
+                }
+            };
+            if state.pending_pages.is_empty() {
+                state.pc += 1;
+            }
+            if *wait {
+                state.last_line = Some((who.clone(), what.clone()));
+                state.backlog.push(who.clone(), what.clone());
+            }
+            return StepResult::Text(who.clone(), what, *wait, spans);
+        }
+        Instr::goto(target) => {
+            state.pc = match target {
+                Label::Offset(x) => *x,
+                label => match script.labels.get(label) {
+                    Some(&x) => x,
+                    None => {
+                        warn!(?label, "goto to a missing label, stopping");
+                        return StepResult::Exit;
+                    }
+                },
+            };
+            return StepResult::Continue;
+        }
+        Instr::sound(file, times) => {
+            debug!(%file, ?times, "playing sound");
+            if file == "~" {
+                state.sound_loop = None;
+            } else if *times == Some(-1) {
+                state.sound_loop = Some(file.clone());
+            }
+            state.pc += 1;
+            return StepResult::Sound(file.clone(), *times);
+        }
+        Instr::music(file, fade) => {
+            debug!(%file, ?fade, "playing music");
+            state.persistent.mark_played(file);
+            state.last_music = Some(file.clone());
+            state.pc += 1;
+            return StepResult::Music {
+                file: file.clone(),
+                fade: fade.map(|ms| Duration::from_millis(ms as u64)),
+            };
+        }
+        Instr::voice(file) => {
+            debug!(%file, "playing voice");
+            state.pc += 1;
+            return StepResult::Voice(file.clone());
+        }
+        Instr::musicstop(fade) => {
+            debug!(?fade, "stopping music");
+            state.last_music = None;
+            state.pc += 1;
+            return StepResult::MusicStop {
+                fade: fade.map(|ms| Duration::from_millis(ms as u64)),
+            };
+        }
+        Instr::choice(choices) => {
+            let pc = state.pc;
+            state.pc_to_save = pc;
+            state.pc += 1;
+            let (shown, texts): (Vec<usize>, Vec<String>) = choices.iter()
+                .enumerate()
+                .filter(|(_, (_, condition))| condition.as_ref().is_none_or(|c| expr::check(c, state)))
+                .map(|(idx, (ch, _))| (idx, state.get_var(ch).map(|v| v.to_string()).unwrap_or_default()))
+                .unzip();
+            // There's nothing to pick, so the choice is passed with nothing selected.
+            if shown.is_empty() {
+                warn!(script = state.current_script(), pc, "every option of the choice is hidden, skipping it");
+                state.shown_choices.clear();
+                state.insert(&VarOrConst {
+                    is_ref: false,
+                    name: "selected".to_string(),
+                    index: None,
+                    symbol: None,
+                }, Value::Int(0));
+                return StepResult::Continue;
+            }
+            let seen = shown.iter()
+                .map(|&idx| state.persistent.was_selected(state.current_script(), pc, idx))
+                .collect();
+            state.shown_choices = shown;
+            state.pending_choice = Some((state.current_script, pc));
+            state.set_choice(0); // default choice
+            state.write_autosave();
+            return StepResult::Choice(texts, seen);
+        }
+        Instr::jump(file, label) => {
+            state.pending_jump_label = label.clone();
+            // Frontends take images down when switching scripts.
+            state.last_images.clear();
+            return StepResult::Jump(file.clone());
+        }
+        Instr::endscript => {
+            return StepResult::Exit;
+        }
+        Instr::ending(id) => {
+            debug!(%id, "ending reached");
+            state.persistent.complete_ending(id);
+            state.pc += 1;
+            return StepResult::Ending(id.clone());
+        }
+        Instr::unsetvar(ident) => {
+            state.remove(false, ident.as_ref());
+        }
+        Instr::gunsetvar(ident) => {
+            state.remove(true, ident.as_ref());
+        }
+        Instr::addvar(ident, delta) => {
+            let current = state.get_var(&VarOrConst { is_ref: true, ..ident.clone() })
+                .map_or(0, |v| v.to_int());
+            state.insert(ident, Value::Int(current.wrapping_add(*delta)));
+        }
+        Instr::random(ident, low, high) => {
+            // In i128, so that bounds spanning all of i64 don't overflow.
+            let range = (*high as i128 - *low as i128).max(0) as u128 + 1;
+            let value = (*low as i128 + (state.next_random() as u128 % range) as i128) as i64;
+            state.insert(ident, Value::Int(value));
+        }
+        Instr::title(title) => {
+            state.current_title = Some(title.clone());
+            state.pc += 1;
+            return StepResult::Title(title.clone());
+        }
+        Instr::chapter(chapter) => {
+            state.current_chapter = Some(chapter.clone());
+            if let Some(checkpoint) = state.checkpoints.back_mut() {
+                checkpoint.chapter = Some(chapter.clone());
+            }
+            state.pc += 1;
+            // Loading resumes at the start of the chapter rather than at the last line of
+            // the one before.
+            state.pc_to_save = state.pc;
+            if let Some(Autosave { at_chapters: true, .. }) = state.autosave {
+                state.write_autosave();
+            }
+            return StepResult::Chapter(chapter.clone());
         }
     }
     state.pc += 1;
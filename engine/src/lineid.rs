@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::scripts_dir;
+use crate::{Instr, ScriptOptions, list_scripts, load_script};
+
+// Sidecar map in the game directory, so scripts don't need to be modified.
+pub const LINE_IDS_FILE: &str = "line_ids.json";
+
+// Entries of a script are kept in script order, which is what tells identical lines
+// apart when matching them up with an edited version.
+#[derive(Serialize, Deserialize)]
+struct LineEntry {
+    id: String,
+    pc: usize,
+    text: String,
+}
+
+// Stable ids of all text lines, for anything that has to refer to a line across
+// script edits, like voice packs or translation memories.
+#[derive(Default, Serialize, Deserialize)]
+pub struct LineIds {
+    next_id: u64,
+    scripts: BTreeMap<String, Vec<LineEntry>>,
+}
+
+impl LineIds {
+    pub fn load(file: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        Ok(serde_json::from_reader(std::fs::File::open(file)?)?)
+    }
+
+    pub fn save(&self, file: impl AsRef<Path>) -> Result<(), std::io::Error> {
+        serde_json::to_writer_pretty(std::fs::File::create(file)?, self)?;
+        Ok(())
+    }
+
+    // Assigns ids to the text lines of all scripts in the game. Lines which are still
+    // present keep their ids, new lines get fresh ones. Returns the number of new ids.
+    pub fn update(
+        &mut self,
+        directory: impl AsRef<Path>,
+        options: &ScriptOptions,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let scripts_dir = scripts_dir(directory.as_ref());
+        let mut scripts = BTreeMap::new();
+        let mut assigned = 0;
+        for name in list_scripts(&scripts_dir, options)? {
+            let script = load_script(scripts_dir.join(&name), options)?;
+            let lines: Vec<(usize, &String)> = script.code
+                .iter()
+                .enumerate()
+                .filter_map(|(pc, instr)| match instr {
+                    Instr::text(_, text, true) if !text.is_empty() => Some((pc, text)),
+                    _ => None,
+                })
+                .collect();
+            let old = self.scripts.remove(&name).unwrap_or_default();
+            let old_texts: Vec<&str> = old.iter().map(|entry| entry.text.as_str()).collect();
+            let new_texts: Vec<&str> = lines.iter().map(|(_, text)| text.as_str()).collect();
+            let mut kept: HashMap<usize, usize> = align(&old_texts, &new_texts)
+                .into_iter()
+                .map(|(old, new)| (new, old))
+                .collect();
+
+            let mut entries = vec![];
+            for (n, (pc, text)) in lines.into_iter().enumerate() {
+                let id = match kept.remove(&n) {
+                    Some(o) => old[o].id.clone(),
+                    None => {
+                        self.next_id += 1;
+                        assigned += 1;
+                        format!("{:06}", self.next_id)
+                    }
+                };
+                entries.push(LineEntry { id, pc, text: text.clone() });
+            }
+            scripts.insert(name, entries);
+        }
+        self.scripts = scripts;
+        Ok(assigned)
+    }
+
+    // Script and pc of the line with the given id.
+    pub fn resolve(&self, id: &str) -> Option<(&str, usize)> {
+        self.scripts.iter().find_map(|(script, entries)| {
+            let entry = entries.iter().find(|entry| entry.id == id)?;
+            Some((script.as_str(), entry.pc))
+        })
+    }
+
+    pub fn id_at(&self, script: &str, pc: usize) -> Option<&str> {
+        self.scripts
+            .get(script)?
+            .iter()
+            .find(|entry| entry.pc == pc)
+            .map(|entry| entry.id.as_str())
+    }
+}
+
+// Pairs up the lines of two versions of a script, in order. Identical lines are paired
+// first, with lines unique to both versions as anchors like patience diff does, so
+// repeated lines are told apart by where they are. Lines left between two pairs are
+// paired by position, as they're most likely edits of each other.
+fn align(old: &[&str], new: &[&str]) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    let mut ranges = vec![(0..old.len(), 0..new.len())];
+    while let Some((mut o, mut n)) = ranges.pop() {
+        while o.start < o.end && n.start < n.end && old[o.start] == new[n.start] {
+            pairs.push((o.start, n.start));
+            o.start += 1;
+            n.start += 1;
+        }
+        while o.start < o.end && n.start < n.end && old[o.end - 1] == new[n.end - 1] {
+            pairs.push((o.end - 1, n.end - 1));
+            o.end -= 1;
+            n.end -= 1;
+        }
+        let anchors = unique_anchors(old, o.clone(), new, n.clone());
+        if anchors.is_empty() {
+            pairs.extend(o.zip(n));
+            continue;
+        }
+        let (mut old_start, mut new_start) = (o.start, n.start);
+        for (a, b) in anchors {
+            pairs.push((a, b));
+            ranges.push((old_start..a, new_start..b));
+            old_start = a + 1;
+            new_start = b + 1;
+        }
+        ranges.push((old_start..o.end, new_start..n.end));
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+// Lines which appear exactly once in both ranges, the longest run of them in the same
+// order in both.
+fn unique_anchors(old: &[&str], o: Range<usize>, new: &[&str], n: Range<usize>) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<&str, (usize, usize, usize, usize)> = HashMap::new();
+    for i in o {
+        let count = counts.entry(old[i]).or_default();
+        count.0 += 1;
+        count.1 = i;
+    }
+    for j in n {
+        let count = counts.entry(new[j]).or_default();
+        count.2 += 1;
+        count.3 = j;
+    }
+    let mut candidates: Vec<(usize, usize)> = counts
+        .into_values()
+        .filter(|&(in_old, _, in_new, _)| in_old == 1 && in_new == 1)
+        .map(|(_, i, _, j)| (i, j))
+        .collect();
+    candidates.sort_unstable_by_key(|&(_, j)| j);
+
+    // Longest increasing run of old positions, by patience sorting.
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; candidates.len()];
+    for (k, &(i, _)) in candidates.iter().enumerate() {
+        let pile = tails.partition_point(|&t| candidates[t].0 < i);
+        previous[k] = pile.checked_sub(1).map(|p| tails[p]);
+        if pile == tails.len() {
+            tails.push(k);
+        } else {
+            tails[pile] = k;
+        }
+    }
+    let mut anchors = vec![];
+    let mut k = tails.last().copied();
+    while let Some(at) = k {
+        anchors.push(candidates[at]);
+        k = previous[at];
+    }
+    anchors.reverse();
+    anchors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineIds, align};
+    use crate::testing::TestGame;
+
+    #[test]
+    fn keeps_ids_across_edits() {
+        let game_dir = TestGame::new("line-ids");
+        let mut ids = LineIds::default();
+        game_dir.script("main.scr", "text a\ntext b\ntext a\n");
+        assert_eq!(ids.update(&game_dir, &Default::default()).unwrap(), 3);
+        let second_a = ids.id_at("main.scr", 2).unwrap().to_string();
+
+        game_dir.script("main.scr", "text new\ntext a\ntext b\ntext a\n");
+        assert_eq!(ids.update(&game_dir, &Default::default()).unwrap(), 1);
+        assert_eq!(ids.resolve(&second_a), Some(("main.scr", 3)));
+    }
+
+    #[test]
+    fn keeps_ids_of_edited_and_repeated_lines() {
+        let game_dir = TestGame::new("line-ids-edits");
+        let mut ids = LineIds::default();
+        game_dir.script("main.scr", "text a\ntext x\ntext b\ntext a\n");
+        ids.update(&game_dir, &Default::default()).unwrap();
+        let before: Vec<_> = (0..4).map(|pc| ids.id_at("main.scr", pc).unwrap().to_string()).collect();
+
+        // The second line is reworded and the first one is repeated after it.
+        game_dir.script("main.scr", "text a\ntext y\ntext a\ntext b\ntext a\n");
+        assert_eq!(ids.update(&game_dir, &Default::default()).unwrap(), 1);
+
+        assert_eq!(ids.resolve(&before[0]), Some(("main.scr", 0)));
+        assert_eq!(ids.resolve(&before[1]), Some(("main.scr", 1)));
+        assert_eq!(ids.resolve(&before[2]), Some(("main.scr", 3)));
+        assert_eq!(ids.resolve(&before[3]), Some(("main.scr", 4)));
+    }
+
+    #[test]
+    fn aligns_lines() {
+        assert_eq!(align(&["a", "b", "c"], &["a", "c"]), [(0, 0), (2, 1)]);
+        assert_eq!(align(&["a", "x", "a"], &["a", "a", "x", "a"]), [(0, 0), (1, 2), (2, 3)]);
+        assert_eq!(align(&["a", "b"], &["c", "d", "e"]), [(0, 0), (1, 1)]);
+        assert_eq!(align(&["c", "a", "b"], &["a", "b", "c"]), [(1, 0), (2, 1)]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::XorKey;
+
+// Describes games which don't follow the usual layout, so ports work unchanged.
+pub const MANIFEST_FILE: &str = "game.toml";
+
+// Content tags streamer-safe mode goes by, on music and gallery images respectively.
+pub const COPYRIGHTED_TAG: &str = "copyrighted";
+pub const SPOILER_TAG: &str = "spoiler";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GameManifest {
+    pub title: Option<String>,
+    // Width and height of the screen the game was made for.
+    pub resolution: Option<(u32, u32)>,
+    // First script run by a new game.
+    pub entry_script: String,
+    pub directories: Directories,
+    // Hex XOR key the scripts are obfuscated with, see `script_key`.
+    pub script_key: Option<String>,
+    // Key the entries of the game's archives are encrypted with, see `archive_key`.
+    pub archive_key: Option<String>,
+    // Custom glyphs text refers to with `{gaiji=code}`, by code.
+    pub gaiji: BTreeMap<String, Gaiji>,
+    // Tags of music files and gallery images, by the names scripts and the gallery use.
+    pub content_tags: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Gaiji {
+    // Replaced by this text, usually a single character like `♥`.
+    Text(String),
+    // Shown as a small image in the line, relative to the game directory.
+    Image { image: PathBuf },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Directories {
+    pub scripts: String,
+    pub backgrounds: String,
+    // Images shown with `setimg`.
+    pub images: String,
+}
+
+impl Default for GameManifest {
+    fn default() -> Self {
+        Self {
+            title: None,
+            resolution: None,
+            entry_script: "main.scr".to_string(),
+            directories: Directories::default(),
+            script_key: None,
+            archive_key: None,
+            gaiji: BTreeMap::new(),
+            content_tags: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Directories {
+    fn default() -> Self {
+        Self {
+            scripts: "Scripts".to_string(),
+            backgrounds: "CG".to_string(),
+            images: "CGAlt".to_string(),
+        }
+    }
+}
+
+impl GameManifest {
+    pub fn load(directory: &Path) -> Self {
+        let file = directory.join(MANIFEST_FILE);
+        if !file.exists() {
+            return Self::default();
+        }
+        let manifest: Result<Self, Box<dyn std::error::Error>> = std::fs::read_to_string(&file)
+            .map_err(Into::into)
+            .and_then(|text| Ok(toml::from_str(&text)?));
+        manifest.unwrap_or_else(|e| {
+            warn!(file = %file.display(), %e, "invalid manifest, using the default layout");
+            Self::default()
+        })
+    }
+
+    // Frontends should refuse to start with an invalid key rather than show garbage.
+    pub fn script_key(&self) -> Result<Option<XorKey>, String> {
+        match &self.script_key {
+            Some(key) => XorKey::from_hex(key).map(Some).ok_or_else(|| format!("invalid script key in {}: {}", MANIFEST_FILE, key)),
+            None => Ok(None),
+        }
+    }
+
+    // Written as `aes:` or `xor:` followed by the key in hex.
+    pub fn archive_key(&self) -> Result<Option<leg_archive::ArchiveKey>, String> {
+        match &self.archive_key {
+            Some(key) => leg_archive::ArchiveKey::parse(key).map(Some).ok_or_else(|| format!("invalid archive key in {}: {}", MANIFEST_FILE, key)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn has_tag(&self, name: &str, tag: &str) -> bool {
+        self.content_tags.get(name).is_some_and(|tags| tags.contains(tag))
+    }
+
+    pub fn scripts_dir(&self, directory: &Path) -> PathBuf {
+        directory.join(&self.directories.scripts)
+    }
+}
+
+// Where the scripts of the game in `directory` are, for tools working on them.
+pub(crate) fn scripts_dir(directory: &Path) -> PathBuf {
+    GameManifest::load(directory).scripts_dir(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{Gaiji, GameManifest, MANIFEST_FILE};
+    use crate::testing::TestGame;
+
+    #[test]
+    fn loads_manifest() {
+        let game_dir = TestGame::new("manifest");
+        assert_eq!(GameManifest::load(&game_dir).entry_script, "main.scr");
+
+        game_dir.write(MANIFEST_FILE, "\
+            title = \"Kanon\"\n\
+            resolution = [256, 192]\n\
+            entry_script = \"start.scr\"\n\
+            \n\
+            [directories]\n\
+            scripts = \"script\"\n\
+            \n\
+            [gaiji]\n\
+            heart = \"♥\"\n\
+            note = { image = \"gaiji/note.png\" }\n\
+            \n\
+            [content_tags]\n\
+            \"sound/bgm01.ogg\" = [\"copyrighted\"]\n");
+        let manifest = GameManifest::load(&game_dir);
+
+        assert_eq!(manifest.title.as_deref(), Some("Kanon"));
+        assert_eq!(manifest.resolution, Some((256, 192)));
+        assert_eq!(manifest.entry_script, "start.scr");
+        assert_eq!(manifest.directories.scripts, "script");
+        assert_eq!(manifest.directories.backgrounds, "CG");
+        assert_eq!(manifest.gaiji["heart"], Gaiji::Text("♥".to_string()));
+        assert_eq!(manifest.gaiji["note"], Gaiji::Image { image: PathBuf::from("gaiji/note.png") });
+        assert!(manifest.has_tag("sound/bgm01.ogg", "copyrighted"));
+        assert!(!manifest.has_tag("sound/bgm01.ogg", "spoiler"));
+    }
+}
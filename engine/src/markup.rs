@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::manifest::Gaiji;
+
+// Stands in for inline images in the plain text, so they take up a character when
+// laying it out.
+pub const INLINE_IMAGE: char = '\u{fffc}';
+
+// Part of a text line, split at the control codes in it. Styles apply to the text
+// after them, until they're turned off again.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum TextSpan {
+    Text(String),
+    // `{w=500}`, pauses for that many milliseconds.
+    Wait(u64),
+    // `{speed=2}`, multiplies the typing speed for the rest of the line, 1 is normal.
+    Speed(f32),
+    // `{color=red}` or `{color=#ff0000}`, back to the default color with `{/color}`.
+    Color(Option<String>),
+    // `{i}` and `{/i}`.
+    Italic(bool),
+    // `{b}` and `{/b}`.
+    Bold(bool),
+    // `{gaiji=code}` of a glyph the manifest has an image for, shown in place of the
+    // `INLINE_IMAGE` at the same position in the plain text. Glyphs with text are
+    // part of the text instead.
+    Image(PathBuf),
+}
+
+// Returns the text without control codes and the spans it consists of. Braces which
+// aren't a known control code, or glyph in `gaiji`, are kept as text.
+pub(crate) fn parse_markup(text: &str, gaiji: &BTreeMap<String, Gaiji>) -> (String, Vec<TextSpan>) {
+    let mut plain = String::new();
+    let mut spans = vec![];
+    let mut current = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let code = rest[start + 1..].find('}').and_then(|end| parse_code(&rest[start + 1..start + 1 + end], gaiji).map(|code| (code, end)));
+        match code {
+            Some((Code::Glyph(glyph), end)) => {
+                current.push_str(&rest[..start]);
+                current.push_str(glyph);
+                rest = &rest[start + end + 2..];
+            }
+            Some((Code::Span(code), end)) => {
+                current.push_str(&rest[..start]);
+                if !current.is_empty() {
+                    plain.push_str(&current);
+                    spans.push(TextSpan::Text(std::mem::take(&mut current)));
+                }
+                if let TextSpan::Image(_) = code {
+                    plain.push(INLINE_IMAGE);
+                }
+                spans.push(code);
+                rest = &rest[start + end + 2..];
+            }
+            None => {
+                current.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    current.push_str(rest);
+    if !current.is_empty() || spans.is_empty() {
+        plain.push_str(&current);
+        spans.push(TextSpan::Text(current));
+    }
+    (plain, spans)
+}
+
+// Splits the spans of a line between pages of its plain text, given as byte ranges.
+// Codes go with the text after them, and styles still on when a page starts are
+// turned on again there, so every page can be shown on its own.
+pub(crate) fn split_spans(spans: &[TextSpan], pages: &[Range<usize>]) -> Vec<Vec<TextSpan>> {
+    let mut split = vec![];
+    let mut style = Style::default();
+    let mut next = 0;
+    // Offset of `spans[next]` in the plain text.
+    let mut at = 0;
+    for (i, page) in pages.iter().enumerate() {
+        let is_last = i + 1 == pages.len();
+        let mut page_spans = style.restate();
+        while let Some(span) = spans.get(next) {
+            match span {
+                TextSpan::Text(text) => {
+                    let (start, end) = (at.max(page.start), (at + text.len()).min(page.end));
+                    if start < end {
+                        page_spans.push(TextSpan::Text(text[start - at..end - at].to_string()));
+                    }
+                    if at + text.len() > page.end && !is_last {
+                        break;
+                    }
+                    at += text.len();
+                }
+                _ if at >= page.end && !is_last => break,
+                TextSpan::Image(_) => {
+                    page_spans.push(span.clone());
+                    at += INLINE_IMAGE.len_utf8();
+                }
+                _ => {
+                    style.update(span);
+                    page_spans.push(span.clone());
+                }
+            }
+            next += 1;
+        }
+        split.push(page_spans);
+    }
+    split
+}
+
+// Styles in effect at some point of a line.
+#[derive(Default)]
+struct Style {
+    color: Option<String>,
+    italic: bool,
+    bold: bool,
+    speed: Option<f32>,
+}
+
+impl Style {
+    fn update(&mut self, span: &TextSpan) {
+        match span {
+            TextSpan::Color(color) => self.color = color.clone(),
+            TextSpan::Italic(italic) => self.italic = *italic,
+            TextSpan::Bold(bold) => self.bold = *bold,
+            TextSpan::Speed(speed) => self.speed = Some(*speed),
+            _ => (),
+        }
+    }
+
+    fn restate(&self) -> Vec<TextSpan> {
+        let mut spans = vec![];
+        if let Some(color) = &self.color {
+            spans.push(TextSpan::Color(Some(color.clone())));
+        }
+        if self.italic {
+            spans.push(TextSpan::Italic(true));
+        }
+        if self.bold {
+            spans.push(TextSpan::Bold(true));
+        }
+        if let Some(speed) = self.speed {
+            spans.push(TextSpan::Speed(speed));
+        }
+        spans
+    }
+}
+
+enum Code<'a> {
+    Span(TextSpan),
+    // Text of a glyph, which joins the text around it.
+    Glyph(&'a str),
+}
+
+fn parse_code<'a>(code: &str, gaiji: &'a BTreeMap<String, Gaiji>) -> Option<Code<'a>> {
+    match code.split_once('=') {
+        Some((name, value)) if name.trim() == "gaiji" => match gaiji.get(value.trim())? {
+            Gaiji::Text(text) => Some(Code::Glyph(text)),
+            Gaiji::Image { image } => Some(Code::Span(TextSpan::Image(image.clone()))),
+        },
+        _ => parse_style(code).map(Code::Span),
+    }
+}
+
+fn parse_style(code: &str) -> Option<TextSpan> {
+    match code.trim() {
+        "i" => return Some(TextSpan::Italic(true)),
+        "/i" => return Some(TextSpan::Italic(false)),
+        "b" => return Some(TextSpan::Bold(true)),
+        "/b" => return Some(TextSpan::Bold(false)),
+        "/color" => return Some(TextSpan::Color(None)),
+        _ => (),
+    }
+    let (name, value) = code.split_once('=')?;
+    let value = value.trim();
+    match name.trim() {
+        "w" => value.parse().ok().map(TextSpan::Wait),
+        "speed" => value.parse().ok().filter(|&speed: &f32| speed > 0.0).map(TextSpan::Speed),
+        "color" if !value.is_empty() => Some(TextSpan::Color(Some(value.to_string()))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::{INLINE_IMAGE, TextSpan, split_spans};
+    use crate::manifest::Gaiji;
+
+    fn parse_markup(gaiji: &BTreeMap<String, Gaiji>, text: &str) -> (String, Vec<TextSpan>) {
+        super::parse_markup(text, gaiji)
+    }
+
+    #[test]
+    fn parses_control_codes() {
+        let no_gaiji = BTreeMap::new();
+        let (plain, spans) = parse_markup(&no_gaiji, "Uguu...{w=500} {speed=0.5}I'm {not} late!");
+        assert_eq!(plain, "Uguu... I'm {not} late!");
+        assert_eq!(spans, vec![
+            TextSpan::Text("Uguu...".to_string()),
+            TextSpan::Wait(500),
+            TextSpan::Text(" ".to_string()),
+            TextSpan::Speed(0.5),
+            TextSpan::Text("I'm {not} late!".to_string()),
+        ]);
+
+        assert_eq!(parse_markup(&no_gaiji, "").1, vec![TextSpan::Text(String::new())]);
+        assert_eq!(parse_markup(&no_gaiji, "{w=1}").1, vec![TextSpan::Wait(1)]);
+        assert_eq!(parse_markup(&no_gaiji, "a {w=x} {").0, "a {w=x} {");
+    }
+
+    #[test]
+    fn parses_styles() {
+        let no_gaiji = BTreeMap::new();
+        let (plain, spans) = parse_markup(&no_gaiji, "{color=red}Red{/color} and {i}{b}bold{/b}{/i}");
+        assert_eq!(plain, "Red and bold");
+        assert_eq!(spans, vec![
+            TextSpan::Color(Some("red".to_string())),
+            TextSpan::Text("Red".to_string()),
+            TextSpan::Color(None),
+            TextSpan::Text(" and ".to_string()),
+            TextSpan::Italic(true),
+            TextSpan::Bold(true),
+            TextSpan::Text("bold".to_string()),
+            TextSpan::Bold(false),
+            TextSpan::Italic(false),
+        ]);
+        assert_eq!(parse_markup(&no_gaiji, "{color=}").0, "{color=}");
+    }
+
+    #[test]
+    fn substitutes_gaiji() {
+        let mut gaiji = BTreeMap::new();
+        gaiji.insert("heart".to_string(), Gaiji::Text("♥".to_string()));
+        gaiji.insert("note".to_string(), Gaiji::Image { image: PathBuf::from("gaiji/note.png") });
+        let (plain, spans) = parse_markup(&gaiji, "I {gaiji=heart} it{gaiji=note}! {gaiji=star}");
+
+        assert_eq!(plain, format!("I ♥ it{}! {{gaiji=star}}", INLINE_IMAGE));
+        assert_eq!(spans, vec![
+            TextSpan::Text("I ♥ it".to_string()),
+            TextSpan::Image(PathBuf::from("gaiji/note.png")),
+            TextSpan::Text("! {gaiji=star}".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn splits_spans_between_pages() {
+        let no_gaiji = BTreeMap::new();
+        let (plain, spans) = parse_markup(&no_gaiji, "{color=red}{b}one two{/b} {w=5}three{/color} four");
+        assert_eq!(plain, "one two three four");
+
+        let pages = split_spans(&spans, &[0..7, 8..18]);
+        assert_eq!(pages, vec![
+            vec![
+                TextSpan::Color(Some("red".to_string())),
+                TextSpan::Bold(true),
+                TextSpan::Text("one two".to_string()),
+            ],
+            vec![
+                TextSpan::Color(Some("red".to_string())),
+                TextSpan::Bold(true),
+                TextSpan::Bold(false),
+                TextSpan::Wait(5),
+                TextSpan::Text("three".to_string()),
+                TextSpan::Color(None),
+                TextSpan::Text(" four".to_string()),
+            ],
+        ]);
+        let whole = 0..plain.len();
+        assert_eq!(split_spans(&spans, &[whole]), vec![spans]);
+    }
+}
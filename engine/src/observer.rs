@@ -0,0 +1,11 @@
+use std::fmt::Debug;
+
+// Hooks invoked by the engine while it executes scripts. All methods default to no-ops,
+// so implementors only override what they're interested in.
+pub trait EngineObserver {
+    fn on_instruction(&mut self, _script: &str, _pc: usize, _instruction: &dyn Debug) {}
+
+    fn on_var_changed(&mut self, _name: &str, _index: usize, _value: &str) {}
+
+    fn on_script_loaded(&mut self, _name: &str) {}
+}
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::auto::ReadingTimes;
+
+// Data kept across playthroughs, independently of save slots.
+#[derive(Default, Serialize, Deserialize)]
+struct PersistentData {
+    // Options picked at each choice, by `script:pc`, as indices into all of its options.
+    #[serde(default)]
+    selected_choices: BTreeMap<String, BTreeSet<usize>>,
+    // Hashes of the `script:pc` of every text line shown so far.
+    #[serde(default)]
+    seen_text: BTreeSet<u64>,
+    // Gallery images shown so far, as listed in the gallery.
+    #[serde(default)]
+    unlocked_cgs: BTreeSet<String>,
+    // Every music file played so far.
+    #[serde(default)]
+    played_tracks: BTreeSet<String>,
+    #[serde(default)]
+    completed_endings: BTreeSet<String>,
+    // In milliseconds, over all playthroughs.
+    #[serde(default)]
+    play_time: u64,
+    #[serde(default)]
+    reading_times: ReadingTimes,
+}
+
+const PLAY_TIME_WRITE_INTERVAL_MS: u64 = 60_000;
+
+// Files of the stores open in this process, once for each store. Importing global data
+// into them is refused, as the store would write its own data over it.
+static OPEN_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn open_files() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn is_open(file: &Path) -> bool {
+    let file = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+    open_files().contains(&file)
+}
+
+// Changes are only written out on `flush`, when enough play time has passed, and when
+// the store is dropped, as text lines are marked seen every few seconds.
+#[derive(Default)]
+pub(crate) struct PersistentStore {
+    data: PersistentData,
+    file: Option<PathBuf>,
+    // Whether there are changes which aren't in the file yet.
+    dirty: bool,
+    // Play time below a millisecond, which isn't in `data` yet.
+    play_time_remainder: Duration,
+}
+
+impl PersistentStore {
+    pub(crate) fn open(file: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let file = file.into();
+        let data = if file.exists() {
+            serde_json::from_reader(std::fs::File::open(&file)?)?
+        } else {
+            PersistentData::default()
+        };
+        let file = std::path::absolute(&file).unwrap_or(file);
+        open_files().push(file.clone());
+        Ok(Self { data, file: Some(file), ..Self::default() })
+    }
+
+    pub(crate) fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub(crate) fn was_selected(&self, script: &str, pc: usize, option: usize) -> bool {
+        self.data.selected_choices
+            .get(&choice_key(script, pc))
+            .is_some_and(|options| options.contains(&option))
+    }
+
+    pub(crate) fn mark_selected(&mut self, script: &str, pc: usize, option: usize) {
+        if self.data.selected_choices.entry(choice_key(script, pc)).or_default().insert(option) {
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn was_seen(&self, script: &str, pc: usize) -> bool {
+        self.data.seen_text.contains(&text_key(script, pc))
+    }
+
+    pub(crate) fn mark_seen(&mut self, script: &str, pc: usize) {
+        if self.data.seen_text.insert(text_key(script, pc)) {
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn is_cg_unlocked(&self, image: &str) -> bool {
+        self.data.unlocked_cgs.contains(image)
+    }
+
+    pub(crate) fn unlock_cg(&mut self, image: &str) {
+        if self.data.unlocked_cgs.insert(image.to_string()) {
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn played_tracks(&self) -> impl Iterator<Item = &str> {
+        self.data.played_tracks.iter().map(String::as_str)
+    }
+
+    pub(crate) fn mark_played(&mut self, track: &str) {
+        if self.data.played_tracks.insert(track.to_string()) {
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn completed_endings(&self) -> impl Iterator<Item = &str> {
+        self.data.completed_endings.iter().map(String::as_str)
+    }
+
+    pub(crate) fn complete_ending(&mut self, ending: &str) {
+        if self.data.completed_endings.insert(ending.to_string()) {
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn reading_times(&self) -> ReadingTimes {
+        self.data.reading_times
+    }
+
+    pub(crate) fn record_reading(&mut self, len: usize, shown_for: Duration) {
+        self.data.reading_times.record(len, shown_for);
+        self.dirty = true;
+    }
+
+    pub(crate) fn play_time(&self) -> Duration {
+        Duration::from_millis(self.data.play_time)
+    }
+
+    // Ticks come every frame, so the file is only written once a minute of play.
+    pub(crate) fn add_play_time(&mut self, delta: Duration) {
+        let before = self.data.play_time;
+        let delta = self.play_time_remainder + delta;
+        let millis = delta.as_millis() as u64;
+        self.play_time_remainder = delta - Duration::from_millis(millis);
+        self.data.play_time = before.saturating_add(millis);
+        self.dirty = true;
+        if self.data.play_time / PLAY_TIME_WRITE_INTERVAL_MS != before / PLAY_TIME_WRITE_INTERVAL_MS {
+            if let Err(e) = self.flush() {
+                warn!(%e, "can't write persistent data");
+            }
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), std::io::Error> {
+        if let (Some(file), true) = (&self.file, self.dirty) {
+            save(file, &self.data)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PersistentStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!(%e, "can't write persistent data");
+        }
+        if let Some(file) = &self.file {
+            let mut open = open_files();
+            if let Some(index) = open.iter().position(|open| open == file) {
+                open.swap_remove(index);
+            }
+        }
+    }
+}
+
+fn choice_key(script: &str, pc: usize) -> String {
+    format!("{}:{}", script, pc)
+}
+
+fn text_key(script: &str, pc: usize) -> u64 {
+    crate::fnv1a(choice_key(script, pc).as_bytes())
+}
+
+fn save(file: &Path, data: &PersistentData) -> Result<(), std::io::Error> {
+    crate::saves::write_atomically(file, &serde_json::to_vec_pretty(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::PersistentStore;
+    use crate::testing::TestGame;
+
+    #[test]
+    fn tracks_seen_text() {
+        let mut store = PersistentStore::default();
+        store.mark_seen("a.scr", 3);
+        assert!(store.was_seen("a.scr", 3));
+        assert!(!store.was_seen("a.scr", 4));
+        assert!(!store.was_seen("b.scr", 3));
+    }
+
+    #[test]
+    fn writes_changes_on_flush_and_drop() {
+        let game_dir = TestGame::new("persistent-flush");
+        let file = game_dir.join("global.json");
+
+        let mut store = PersistentStore::open(&file).unwrap();
+        store.mark_seen("a.scr", 3);
+        assert!(!file.exists());
+        store.flush().unwrap();
+        assert!(PersistentStore::open(&file).unwrap().was_seen("a.scr", 3));
+
+        store.unlock_cg("cg1.png");
+        store.record_reading(20, Duration::from_secs(2));
+        drop(store);
+        let store = PersistentStore::open(&file).unwrap();
+        assert!(store.was_seen("a.scr", 3));
+        assert!(store.is_cg_unlocked("cg1.png"));
+        assert_eq!(store.reading_times().samples(), 1);
+        assert!(!game_dir.join("global.json.tmp").exists());
+    }
+
+    #[test]
+    fn adds_up_play_time_below_a_millisecond() {
+        let mut store = PersistentStore::default();
+        for _ in 0..60 {
+            store.add_play_time(Duration::from_micros(16_667));
+        }
+        assert_eq!(store.play_time(), Duration::from_millis(1000));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+const MAX_EXPANSION_DEPTH: usize = 16;
+
+// Instructions whose arguments are shown to the player, where defines aren't replaced
+// so a define named like a word doesn't change the line. Macro parameters still are.
+const TEXT_INSTRUCTIONS: [&str; 3] = ["text", "title", "chapter"];
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+// Expands `define NAME value` constants and `macro NAME params...` blocks closed by
+// `endmacro` before lines reach the parser. Names and parameters are replaced as whole
+// words, and a macro is invoked by using its name as an instruction.
+#[derive(Default)]
+pub(crate) struct Preprocessor {
+    defines: HashMap<String, String>,
+    macros: HashMap<String, Macro>,
+    recording: Option<(String, Macro)>,
+}
+
+impl Preprocessor {
+    // Returns the lines `line` expands to, which is none for definitions.
+    pub(crate) fn process(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let trimmed = line.trim();
+        if let Some((name, mut definition)) = self.recording.take() {
+            if trimmed == "endmacro" {
+                self.macros.insert(name, definition);
+            } else {
+                definition.body.push(trimmed.to_string());
+                self.recording = Some((name, definition));
+            }
+            return Ok(vec![]);
+        }
+
+        let mut words = trimmed.split_ascii_whitespace();
+        match words.next() {
+            Some("define") => {
+                let name = words.next().ok_or("define without a name")?;
+                let value = trimmed["define".len()..].trim_start_matches(|c: char| c.is_ascii_whitespace())[name.len()..].trim();
+                let value = substitute(value, |word| self.defines.get(word).map(String::as_str));
+                self.defines.insert(name.to_string(), value);
+                Ok(vec![])
+            }
+            Some("macro") => {
+                let name = words.next().ok_or("macro without a name")?;
+                let definition = Macro {
+                    params: words.map(str::to_string).collect(),
+                    body: vec![],
+                };
+                self.recording = Some((name.to_string(), definition));
+                Ok(vec![])
+            }
+            Some("endmacro") => Err("endmacro outside of a macro".to_string()),
+            _ => self.expand(line, 0),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> Result<(), String> {
+        match &self.recording {
+            Some((name, _)) => Err(format!("macro {} is missing endmacro", name)),
+            None => Ok(()),
+        }
+    }
+
+    fn expand(&self, line: &str, depth: usize) -> Result<Vec<String>, String> {
+        let line = match line.split_ascii_whitespace().next() {
+            Some(instruction) if TEXT_INSTRUCTIONS.contains(&instruction) => line.to_string(),
+            _ => substitute(line, |word| self.defines.get(word).map(String::as_str)),
+        };
+        let mut words = line.split_ascii_whitespace();
+        let (name, definition) = match words.next().and_then(|word| Some((word, self.macros.get(word)?))) {
+            Some(found) => found,
+            None => return Ok(vec![line]),
+        };
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(format!("macro {} expands too deep, is it recursive?", name));
+        }
+
+        let args: Vec<&str> = words.collect();
+        if args.len() != definition.params.len() {
+            return Err(format!(
+                "macro {} takes {} arguments, got {}",
+                name,
+                definition.params.len(),
+                args.len(),
+            ));
+        }
+        let args: HashMap<&str, &str> = definition.params
+            .iter()
+            .map(String::as_str)
+            .zip(args)
+            .collect();
+
+        let mut lines = vec![];
+        for body_line in &definition.body {
+            let body_line = substitute(body_line, |word| args.get(word).copied());
+            lines.extend(self.expand(&body_line, depth + 1)?);
+        }
+        Ok(lines)
+    }
+}
+
+// Replaces whole words, keeping the original spacing.
+fn substitute<'a>(line: &str, lookup: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while !rest.is_empty() {
+        let word_end = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        let (word, tail) = rest.split_at(word_end);
+        out.push_str(lookup(word).unwrap_or(word));
+
+        let space_end = tail.find(|c: char| !c.is_ascii_whitespace()).unwrap_or(tail.len());
+        out.push_str(&tail[..space_end]);
+        rest = &tail[space_end..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Preprocessor;
+
+    #[test]
+    fn expands_defines_and_macros() {
+        let mut preprocessor = Preprocessor::default();
+        let mut lines = vec![];
+        for line in &[
+            "define FADE 30",
+            "macro show bg who",
+            "bgload bg FADE",
+            "text who appears.",
+            "endmacro",
+            "show park.jpg Ayu",
+        ] {
+            lines.extend(preprocessor.process(line).unwrap());
+        }
+        preprocessor.finish().unwrap();
+        assert_eq!(lines, vec!["bgload park.jpg 30", "text Ayu appears."]);
+
+        assert!(preprocessor.process("show park.jpg").is_err());
+        preprocessor.process("macro loop").unwrap();
+        preprocessor.process("loop").unwrap();
+        preprocessor.process("endmacro").unwrap();
+        assert!(preprocessor.process("loop").is_err());
+    }
+
+    #[test]
+    fn leaves_text_alone() {
+        let mut preprocessor = Preprocessor::default();
+        let mut lines = vec![];
+        for line in &[
+            "define Ayu 3",
+            "define RED #ff0000",
+            "macro say who",
+            "text who says Uguu!",
+            "endmacro",
+            "text Ayu appears in RED.",
+            "chapter Ayu",
+            "say Nayuki",
+            "setvar x = Ayu",
+        ] {
+            lines.extend(preprocessor.process(line).unwrap());
+        }
+        assert_eq!(lines, vec!["text Ayu appears in RED.", "chapter Ayu", "text Nayuki says Uguu!", "setvar x = 3"]);
+    }
+}
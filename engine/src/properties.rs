@@ -0,0 +1,198 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use proptest::prelude::*;
+
+use crate::{EngineState, Instr, Label, ScriptOptions, StepResult, parse, step};
+use crate::testing::TestGame;
+
+const VARIABLES: usize = 3;
+const LABELS: usize = 3;
+const MAX_STEPS: usize = 500;
+
+#[derive(Debug, Clone)]
+enum Line {
+    Set(usize, i64),
+    GlobalSet(usize, i64),
+    Add(usize, i64),
+    Unset(usize),
+    SetIndexed(usize, i64),
+    Text(usize),
+    If(usize, &'static str, i64),
+    Fi,
+    Label(usize),
+    Goto(usize),
+    Choice(usize),
+    Menu(usize),
+    Background(usize),
+    Image(usize, usize, usize),
+    ClearImage,
+    Sound(usize),
+    Music(usize),
+    MusicStop,
+    Delay(u32),
+    ClearText,
+    Title(usize),
+}
+
+fn line() -> impl Strategy<Value = Line> {
+    prop_oneof![
+        (0..VARIABLES, -3i64..3).prop_map(|(var, value)| Line::Set(var, value)),
+        (0..VARIABLES, -3i64..3).prop_map(|(var, value)| Line::GlobalSet(var, value)),
+        (0..VARIABLES, -3i64..3).prop_map(|(var, value)| Line::Add(var, value)),
+        (0..VARIABLES).prop_map(Line::Unset),
+        (0..VARIABLES, -3i64..3).prop_map(|(var, value)| Line::SetIndexed(var, value)),
+        (0..5usize).prop_map(Line::Text),
+        (0..VARIABLES, prop_oneof![Just("=="), Just("!="), Just("<"), Just(">=")], -3i64..3)
+            .prop_map(|(var, op, value)| Line::If(var, op, value)),
+        Just(Line::Fi),
+        (0..LABELS).prop_map(Line::Label),
+        (0..LABELS).prop_map(Line::Goto),
+        (1..4usize).prop_map(Line::Choice),
+        (1..4usize).prop_map(Line::Menu),
+        (0..3usize).prop_map(Line::Background),
+        (0..3usize, 0..100usize, 0..100usize).prop_map(|(image, x, y)| Line::Image(image, x, y)),
+        Just(Line::ClearImage),
+        (0..3usize).prop_map(Line::Sound),
+        (0..3usize).prop_map(Line::Music),
+        Just(Line::MusicStop),
+        (0..100u32).prop_map(Line::Delay),
+        Just(Line::ClearText),
+        (0..3usize).prop_map(Line::Title),
+    ]
+}
+
+// Renders lines into a script which parses: nested `if`s are all closed, labels are
+// defined once and `goto`s only go to defined labels.
+fn render(lines: &[Line]) -> String {
+    let labels: BTreeSet<usize> = lines
+        .iter()
+        .filter_map(|line| match line {
+            Line::Label(label) => Some(*label),
+            _ => None,
+        })
+        .collect();
+    let mut defined = BTreeSet::new();
+    let mut open_ifs = 0;
+    let mut script = String::new();
+    for line in lines {
+        let rendered = match *line {
+            Line::Set(var, value) => format!("setvar v{} = {}", var, value),
+            Line::GlobalSet(var, value) => format!("gsetvar g{} = {}", var, value),
+            Line::Add(var, value) => format!("setvar v{} = $v{} + {}", var, var, value),
+            Line::Unset(var) => format!("setvar v{} ~", var),
+            Line::SetIndexed(var, value) => format!("setvar a[$v{}] = {}", var, value),
+            Line::Text(text) => format!("text line {}", text),
+            Line::If(var, op, value) => {
+                open_ifs += 1;
+                format!("if $v{} {} {}", var, op, value)
+            }
+            Line::Fi if open_ifs == 0 => continue,
+            Line::Fi => {
+                open_ifs -= 1;
+                "fi".to_string()
+            }
+            Line::Label(label) if !defined.insert(label) => continue,
+            Line::Label(label) => format!("label l{}", label),
+            Line::Goto(label) if !labels.contains(&label) => continue,
+            Line::Goto(label) => format!("goto l{}", label),
+            Line::Choice(options) => {
+                let options: Vec<String> = (0..options).map(|option| format!("option {}", option)).collect();
+                format!("choice {}", options.join("|"))
+            }
+            Line::Menu(options) => {
+                let mut menu = String::from("menu\n");
+                for option in 0..options {
+                    menu.push_str(&format!("option {}\nsetvar picked = {}\nend\n", option, option));
+                }
+                menu.push_str("endmenu");
+                menu
+            }
+            Line::Background(image) => format!("bgload bg{}.png", image),
+            Line::Image(image, x, y) => format!("setimg ch{}.png {} {}", image, x, y),
+            Line::ClearImage => "setimg ~".to_string(),
+            Line::Sound(sound) => format!("sound se{}.ogg", sound),
+            Line::Music(music) => format!("music bgm{}.ogg", music),
+            Line::MusicStop => "music ~".to_string(),
+            Line::Delay(delay) => format!("delay {}", delay),
+            Line::ClearText => "cleartext".to_string(),
+            Line::Title(title) => format!("title Chapter {}", title),
+        };
+        script.push_str(&rendered);
+        script.push('\n');
+    }
+    for _ in 0..open_ifs {
+        script.push_str("fi\n");
+    }
+    script
+}
+
+// Saves the state, loads it into a fresh engine and checks that nothing was lost.
+fn check_round_trip(state: &EngineState, directory: &Path) -> Result<(), TestCaseError> {
+    let first = directory.join("first.sav");
+    let second = directory.join("second.sav");
+    state.save(&first).unwrap();
+    let mut loaded = EngineState::new(directory);
+    loaded.load(&first).unwrap();
+    loaded.save(&second).unwrap();
+
+    prop_assert_eq!(loaded.pc, state.pc_to_save);
+    prop_assert_eq!(loaded.pc_to_save, state.pc_to_save);
+    prop_assert_eq!(&loaded.last_background, &state.last_background);
+    prop_assert_eq!(&loaded.last_music, &state.last_music);
+    prop_assert_eq!(loaded.current_script(), state.current_script());
+    prop_assert_eq!(loaded.named_variables(false), state.named_variables(false));
+    prop_assert_eq!(loaded.named_variables(true), state.named_variables(true));
+    // When and for how long the game was played depends on the clock.
+    let read = |file| -> serde_json::Value {
+        let mut value: serde_json::Value = serde_json::from_slice(&std::fs::read(file).unwrap()).unwrap();
+        for key in ["saved_at", "play_time"] {
+            value.as_object_mut().unwrap().remove(key);
+        }
+        value
+    };
+    prop_assert_eq!(read(&first), read(&second));
+    Ok(())
+}
+
+fn check_invariants(source: &str, directory: &TestGame) -> Result<(), TestCaseError> {
+    let script = parse(source, None, &ScriptOptions::default()).unwrap().into_script().unwrap();
+    let len = script.code.len();
+    for (pc, instr) in script.code.iter().enumerate() {
+        match *instr {
+            Instr::branch(_, _, _, target) => prop_assert!(pc < target && target <= len, "branch at {} to {}", pc, target),
+            Instr::goto(Label::Offset(target)) => prop_assert!(target <= len, "goto at {} to {}", pc, target),
+            Instr::goto(ref label) => {
+                return Err(TestCaseError::fail(format!("unresolved goto at {} to {:?}", pc, label)));
+            }
+            _ => (),
+        }
+    }
+
+    directory.script("main.scr", source);
+    let mut state = EngineState::new(directory);
+    for steps in 0..MAX_STEPS {
+        let result = step(&mut state);
+        prop_assert!(state.pc <= len, "pc {} out of {}", state.pc, len);
+        prop_assert!(state.pc_to_save <= len, "saved pc {} out of {}", state.pc_to_save, len);
+        match result {
+            StepResult::Exit => break,
+            StepResult::Choice(options, _) => {
+                state.set_choice(steps % options.len());
+                check_round_trip(&state, directory)?;
+            }
+            StepResult::Text(_, _, true, _) => check_round_trip(&state, directory)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn step_keeps_invariants(lines in prop::collection::vec(line(), 0..40)) {
+        let directory = TestGame::new("properties");
+        let result = check_invariants(&render(&lines), &directory);
+        result?;
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::scripts_dir;
+use crate::{ScriptOptions, anchor, list_scripts, load_script};
+
+// Shipped in the game directory by patches which change the line counts of scripts.
+pub const REMAP_FILE: &str = "remap.json";
+
+#[derive(Serialize, Deserialize)]
+struct ScriptRemap {
+    // Hash of the script version whose pcs are remapped.
+    from: String,
+    // Old pc to new pc, pcs which didn't move are left out.
+    pcs: BTreeMap<usize, usize>,
+}
+
+// Maps pcs stored in saves made before a patch to the matching pcs in the patched scripts.
+#[derive(Default, Serialize, Deserialize)]
+pub struct RemapTable {
+    scripts: BTreeMap<String, ScriptRemap>,
+}
+
+impl RemapTable {
+    // Builds a table from the scripts of two versions of the same game.
+    pub fn generate(
+        old_directory: impl AsRef<Path>,
+        new_directory: impl AsRef<Path>,
+        options: &ScriptOptions,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let old_scripts = scripts_dir(old_directory.as_ref());
+        let new_scripts = scripts_dir(new_directory.as_ref());
+
+        let mut table = RemapTable::default();
+        for name in list_scripts(&old_scripts, options)? {
+            if !options.resources().is_file(&new_scripts.join(&name)) {
+                continue;
+            }
+            let old = load_script(old_scripts.join(&name), options)?;
+            let new = load_script(new_scripts.join(&name), options)?;
+            if old.hash == new.hash {
+                continue;
+            }
+
+            let pcs: BTreeMap<usize, usize> = (0..old.code.len())
+                .map(|pc| (pc, anchor::resolve(&new, &anchor::find(&old, pc))))
+                .filter(|(old_pc, new_pc)| old_pc != new_pc)
+                .collect();
+            if !pcs.is_empty() {
+                table.scripts.insert(name, ScriptRemap { from: format!("{:016x}", old.hash), pcs });
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn load(file: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        Ok(serde_json::from_reader(std::fs::File::open(file)?)?)
+    }
+
+    pub fn save(&self, file: impl AsRef<Path>) -> Result<(), std::io::Error> {
+        serde_json::to_writer_pretty(std::fs::File::create(file)?, self)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    pub(crate) fn contains(&self, script: &str) -> bool {
+        self.scripts.contains_key(script)
+    }
+
+    // Saves made before script hashes were stored are assumed to predate the patch
+    // whenever the installed script is no longer the one the table remaps from.
+    pub(crate) fn remap(&self, script: &str, saved_hash: Option<u64>, current_hash: u64, pc: usize) -> usize {
+        let remap = match self.scripts.get(script) {
+            Some(remap) => remap,
+            None => return pc,
+        };
+        let from = |hash: u64| format!("{:016x}", hash) == remap.from;
+        let is_outdated = match saved_hash {
+            Some(hash) => from(hash),
+            None => !from(current_hash),
+        };
+        if is_outdated {
+            remap.pcs.get(&pc).copied().unwrap_or(pc)
+        } else {
+            pc
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemapTable, ScriptRemap};
+
+    #[test]
+    fn remaps_only_outdated_saves() {
+        let mut table = RemapTable::default();
+        table.scripts.insert("a.scr".to_string(), ScriptRemap {
+            from: format!("{:016x}", 1),
+            pcs: vec![(3, 5)].into_iter().collect(),
+        });
+
+        assert_eq!(table.remap("a.scr", Some(1), 2, 3), 5);
+        assert_eq!(table.remap("a.scr", Some(2), 2, 3), 3);
+        assert_eq!(table.remap("a.scr", None, 2, 3), 5);
+        assert_eq!(table.remap("a.scr", None, 1, 3), 3);
+        assert_eq!(table.remap("b.scr", Some(1), 2, 3), 3);
+    }
+}
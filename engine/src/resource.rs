@@ -0,0 +1,313 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::GameError;
+
+// Where scripts and game data are read from. Paths are the ones the files would have
+// on disk, so providers can be swapped without the engine noticing.
+pub trait ResourceProvider: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn is_file(&self, path: &Path) -> bool;
+    // Names of the files directly in `directory`, in no particular order.
+    fn list(&self, directory: &Path) -> io::Result<Vec<String>>;
+
+    // Path the frontend should load an asset from, for providers which keep files
+    // somewhere else than their path says. Assets engine steps refer to go through it.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+}
+
+pub struct FileSystem;
+
+impl ResourceProvider for FileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn list(&self, directory: &Path) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()).filter(|_| path.is_file()) {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+}
+
+// Files packed into `.legArchive`s or zip files, named relative to `root`.
+pub struct ArchiveResources {
+    archive: leg_archive::ArchiveSet,
+    root: PathBuf,
+    // What the archives were opened with, so listing matches names like reading does.
+    normalization: leg_archive::Normalization,
+}
+
+impl ArchiveResources {
+    pub fn new(archive: impl Into<leg_archive::ArchiveSet>, root: impl Into<PathBuf>) -> Self {
+        Self { archive: archive.into(), root: root.into(), normalization: leg_archive::Normalization::NONE }
+    }
+
+    pub fn open(file: impl AsRef<Path>, root: impl Into<PathBuf>) -> Result<Self, GameError> {
+        Self::open_archive(file.as_ref(), root.into(), None)
+    }
+
+    // For archives with encrypted entries, which can't be read without the key.
+    pub fn open_encrypted(file: impl AsRef<Path>, root: impl Into<PathBuf>, key: leg_archive::ArchiveKey) -> Result<Self, GameError> {
+        Self::open_archive(file.as_ref(), root.into(), Some(key))
+    }
+
+    fn open_archive(file: &Path, root: PathBuf, key: Option<leg_archive::ArchiveKey>) -> Result<Self, GameError> {
+        let normalization = leg_archive::Normalization::ALL;
+        let archive = match key {
+            Some(key) => leg_archive::load_any_encrypted(file, normalization, key),
+            None => leg_archive::load_any_with(file, normalization),
+        };
+        let archive = archive.map_err(|e| GameError::Archive(file.to_path_buf(), Box::new(e)))?;
+        Ok(Self::new(archive, root).with_normalization(normalization))
+    }
+
+    pub fn with_normalization(mut self, normalization: leg_archive::Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    // Reads without blocking, for frontends which load assets from async code.
+    #[cfg(feature = "async")]
+    pub fn read_async(&self, path: &Path) -> leg_archive::ReadFuture {
+        self.archive.read_async(&self.entry_name(path))
+    }
+
+    // Archives always separate directories with `/`, whatever the platform.
+    fn entry_name(&self, path: &Path) -> String {
+        let components: Vec<_> = path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        components.join("/")
+    }
+}
+
+impl ResourceProvider for ArchiveResources {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let name = self.entry_name(path);
+        self.archive
+            .read(&name)
+            .map(Vec::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", name)))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.archive.contains(&self.entry_name(path))
+    }
+
+    // Names are compared normalized, like reading does, but listed as they're stored.
+    fn list(&self, directory: &Path) -> io::Result<Vec<String>> {
+        let normalization = self.normalization;
+        let mut prefix = normalization.apply(&self.entry_name(directory)).into_owned();
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        Ok(self.archive
+            .names()
+            .iter()
+            .filter(|name| {
+                let name = normalization.apply(name);
+                name.strip_prefix(&prefix).is_some_and(|rest| !rest.is_empty() && !rest.contains('/'))
+            })
+            .filter_map(|name| name.rsplit(|c| c == '/' || (normalization.separators && c == '\\')).next())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+// Reads every file from the first layer which has it, so patches can be layered over
+// the original files.
+pub struct LayeredResources {
+    layers: Vec<Box<dyn ResourceProvider>>,
+}
+
+impl LayeredResources {
+    pub fn new() -> Self {
+        Self { layers: vec![] }
+    }
+
+    pub fn with(mut self, layer: impl ResourceProvider + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+}
+
+impl Default for LayeredResources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResourceProvider for LayeredResources {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.layers.iter().find(|layer| layer.is_file(path)) {
+            Some(layer) => layer.read(path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display()))),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.layers.iter().any(|layer| layer.is_file(path))
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match self.layers.iter().find(|layer| layer.is_file(path)) {
+            Some(layer) => layer.resolve(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    // Fails only when no layer has the directory.
+    fn list(&self, directory: &Path) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        let mut error = None;
+        for layer in &self.layers {
+            match layer.list(directory) {
+                Ok(listed) => names.extend(listed),
+                Err(e) => error = Some(e),
+            }
+        }
+        if names.is_empty() {
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use super::{ArchiveResources, FileSystem, LayeredResources, ResourceProvider};
+    use crate::testing::TestGame;
+    use crate::{EngineState, ScriptOptions, StepResult};
+
+    // Sees the files of `patch` as if they were in `base`, like a patch unpacked over the game.
+    struct Overlay {
+        base: PathBuf,
+        patch: PathBuf,
+    }
+
+    impl Overlay {
+        fn path(&self, path: &Path) -> PathBuf {
+            self.patch.join(path.strip_prefix(&self.base).unwrap_or(path))
+        }
+    }
+
+    impl ResourceProvider for Overlay {
+        fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            FileSystem.read(&self.path(path))
+        }
+        fn is_file(&self, path: &Path) -> bool {
+            FileSystem.is_file(&self.path(path))
+        }
+        fn list(&self, directory: &Path) -> std::io::Result<Vec<String>> {
+            FileSystem.list(&self.path(directory))
+        }
+        fn resolve(&self, path: &Path) -> PathBuf {
+            self.path(path)
+        }
+    }
+
+    #[test]
+    fn layers_override_files() {
+        let base = TestGame::new("resources-base");
+        let patch = TestGame::new("resources-patch");
+        base.write("a.scr", "base a");
+        base.write("b.scr", "base b");
+        patch.write("a.scr", "patched a");
+
+        let resources = LayeredResources::new()
+            .with(Overlay { base: base.to_path_buf(), patch: patch.to_path_buf() })
+            .with(FileSystem);
+        let a = resources.read(&base.join("a.scr"));
+        let b = resources.read(&base.join("b.scr"));
+        let c = resources.read(&base.join("c.scr"));
+        let listed = resources.list(&base);
+        let resolved = [resources.resolve(&base.join("a.scr")), resources.resolve(&base.join("b.scr"))];
+
+        assert_eq!(a.unwrap(), b"patched a");
+        assert_eq!(b.unwrap(), b"base b");
+        assert_eq!(c.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(listed.unwrap(), ["a.scr", "b.scr"]);
+        assert_eq!(resolved, [patch.join("a.scr"), base.join("b.scr")]);
+    }
+
+    #[test]
+    fn lists_archive_entries_by_normalized_names() {
+        let game_dir = TestGame::new("resources-archive");
+        let file = game_dir.join("game.legArchive");
+        let mut writer = leg_archive::Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("Sound\\SE01.aac", b"se").unwrap();
+        writer.add("sound/bgm.ogg", b"bgm").unwrap();
+        writer.add("sound/voice/a.aac", b"a").unwrap();
+        writer.finish().unwrap();
+
+        let resources = ArchiveResources::open(&file, game_dir.to_path_buf()).unwrap();
+        let mut listed = resources.list(&game_dir.join("SOUND")).unwrap();
+        listed.sort();
+
+        assert_eq!(listed, ["SE01.aac", "bgm.ogg"]);
+    }
+
+    #[test]
+    fn reads_encrypted_archives() {
+        let game_dir = TestGame::new("resources-encrypted");
+        let file = game_dir.join("game.legArchive");
+        let key = leg_archive::ArchiveKey::parse("aes:000102030405060708090a0b0c0d0e0f").unwrap();
+        let mut writer = leg_archive::Writer::new(std::fs::File::create(&file).unwrap());
+        writer.set_key(Some(key.clone()));
+        writer.add("Scripts/main.scr", b"text secret").unwrap();
+        writer.finish().unwrap();
+
+        let resources = ArchiveResources::open_encrypted(&file, game_dir.to_path_buf(), key).unwrap();
+        let without_key = ArchiveResources::open(&file, game_dir.to_path_buf()).unwrap();
+        let script = game_dir.join("Scripts").join("main.scr");
+
+        assert_eq!(resources.read(&script).unwrap(), b"text secret");
+        assert!(without_key.read(&script).is_err());
+    }
+
+    #[test]
+    fn resolves_assets_through_resources() {
+        let base = TestGame::new("assets-base");
+        let patch = TestGame::new("assets-patch");
+        base.script("main.scr", "bgload bg.png\nsetimg sprite.png 1 2 portrait\ntext a\n");
+        patch.write("CG/bg.png", "patched");
+        patch.write("image_slots.json", r#"[{"name": "portrait", "layer": 3}]"#);
+        let overlay = Overlay { base: base.to_path_buf(), patch: patch.to_path_buf() };
+        let options = ScriptOptions {
+            resources: Some(Arc::new(LayeredResources::new().with(overlay).with(FileSystem))),
+            ..Default::default()
+        };
+
+        let mut state = EngineState::with_options(&base, options);
+        let steps = state.run_until_interactive();
+
+        assert!(matches!(&steps[0], StepResult::Background(path) if *path == patch.join("CG").join("bg.png")));
+        assert!(matches!(&steps[1], StepResult::Image(path, slot, 1, 2)
+            if *path == base.join("CGAlt").join("sprite.png") && slot.name == "portrait" && slot.layer == 3));
+    }
+}
@@ -0,0 +1,623 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::bundle;
+use crate::persistent;
+use crate::{EngineState, GameError, SaveError, SerializedState, StepResult};
+
+// How saves are written. Loading works with either, whatever the file is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveFormat {
+    // Pretty-printed, for saves people want to read or edit.
+    Json,
+    // MessagePack, several times smaller and faster to write for games with large
+    // variable maps.
+    Binary,
+}
+
+impl SaveFormat {
+    // `.msgpack` files are binary, everything else is JSON.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("msgpack") => SaveFormat::Binary,
+            _ => SaveFormat::Json,
+        }
+    }
+
+    pub(crate) fn encode(self, value: &impl Serialize) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(match self {
+            SaveFormat::Json => serde_json::to_vec_pretty(value)?,
+            // With field names, so fields added later can still default when missing.
+            SaveFormat::Binary => rmp_serde::to_vec_named(value)?,
+        })
+    }
+}
+
+// JSON saves always start with an object, MessagePack ones never with `{`.
+pub(crate) fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error + Send + Sync>> {
+    match data.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b'{') => Ok(serde_json::from_slice(data)?),
+        _ => Ok(rmp_serde::from_slice(data)?),
+    }
+}
+
+// The save is written next to the file first and then renamed over it, so crashing
+// halfway leaves the previous save intact. That one is kept as `<file>.bak`.
+pub(crate) fn write_save(file: &Path, serialized: &SerializedState, format: SaveFormat) -> Result<(), GameError> {
+    let write = || -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = format.encode(serialized)?;
+        if file.is_file() {
+            std::fs::copy(file, with_suffix(file, ".bak"))?;
+        }
+        write_atomically(file, &data)?;
+        Ok(())
+    };
+    write().map_err(|e| GameError::Save(file.to_path_buf(), e))
+}
+
+// Replaces `file` only once `data` is fully on disk, for anything that must not be
+// left half written.
+pub(crate) fn write_atomically(file: &Path, data: &[u8]) -> io::Result<()> {
+    let temporary = with_suffix(file, ".tmp");
+    let write = || -> io::Result<()> {
+        let mut output = std::fs::File::create(&temporary)?;
+        output.write_all(data)?;
+        output.sync_all()?;
+        std::fs::rename(&temporary, file)
+    };
+    write().inspect_err(|_| {
+        let _ = std::fs::remove_file(&temporary);
+    })
+}
+
+fn with_suffix(file: &Path, suffix: &str) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    file.with_file_name(name)
+}
+
+pub(crate) fn read_save(file: &Path) -> Result<SerializedState, GameError> {
+    let data = std::fs::read(file).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => GameError::Save(file.to_path_buf(), Box::new(SaveError::Missing)),
+        _ => GameError::Save(file.to_path_buf(), e.into()),
+    })?;
+    parse_save(&data).map_err(|e| GameError::Save(file.to_path_buf(), Box::new(e)))
+}
+
+// Version of the save format, raised when older engines can't read the saves anymore.
+pub(crate) const SAVE_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct SaveVersion {
+    // Saves from before versioning have none.
+    #[serde(default)]
+    version: u32,
+}
+
+// Checks the version first, as newer saves may not even decode.
+pub(crate) fn parse_save(data: &[u8]) -> Result<SerializedState, SaveError> {
+    if data.iter().all(u8::is_ascii_whitespace) {
+        return Err(SaveError::Truncated);
+    }
+    if let Ok(SaveVersion { version }) = decode(data) {
+        if version > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+    }
+    decode(data).map_err(|e| {
+        let truncated = match (e.downcast_ref::<serde_json::Error>(), e.downcast_ref::<rmp_serde::decode::Error>()) {
+            (Some(e), _) => e.is_eof(),
+            (_, Some(rmp_serde::decode::Error::InvalidMarkerRead(e) | rmp_serde::decode::Error::InvalidDataRead(e))) => {
+                e.kind() == io::ErrorKind::UnexpectedEof
+            }
+            _ => false,
+        };
+        if truncated { SaveError::Truncated } else { SaveError::Invalid(e.to_string()) }
+    })
+}
+
+const BUNDLE_SLOT_PREFIX: &str = "slot-";
+
+// Slot the engine autosaves to by default, menus may want to show it apart.
+pub const AUTOSAVE_SLOT: u32 = 0;
+
+// Numbered save slots in one directory, `slot-1.sav` and so on.
+pub struct SaveManager {
+    directory: PathBuf,
+    // Persistent store moved between machines together with the slots.
+    global_data: Option<PathBuf>,
+}
+
+// What save and load menus show about a slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveInfo {
+    pub slot: u32,
+    // Missing in saves written before slots existed.
+    pub saved_at: Option<SystemTime>,
+    pub chapter: Option<String>,
+    pub speaker: Option<String>,
+    // The last line the player saw.
+    pub line: Option<String>,
+    pub play_time: Duration,
+}
+
+// Where and when `EngineState` saves on its own.
+pub struct Autosave {
+    pub saves: SaveManager,
+    pub slot: u32,
+    // Also save when a new chapter starts, not only at choices.
+    pub at_chapters: bool,
+}
+
+impl Autosave {
+    pub fn new(saves: SaveManager) -> Self {
+        Self { saves, slot: AUTOSAVE_SLOT, at_chapters: false }
+    }
+}
+
+impl SaveManager {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self { directory: directory.into(), global_data: None }
+    }
+
+    pub fn with_global_data(mut self, file: impl Into<PathBuf>) -> Self {
+        self.global_data = Some(file.into());
+        self
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn slot_path(&self, slot: u32) -> PathBuf {
+        self.directory.join(format!("slot-{}.sav", slot))
+    }
+
+    pub fn save(&self, state: &EngineState, slot: u32) -> Result<SaveInfo, GameError> {
+        std::fs::create_dir_all(&self.directory).map_err(|e| GameError::Save(self.directory.clone(), e.into()))?;
+        state.save(self.slot_path(slot))?;
+        self.info(slot)
+    }
+
+    pub fn load(&self, state: &mut EngineState, slot: u32) -> Result<Vec<StepResult>, GameError> {
+        state.load(self.slot_path(slot))
+    }
+
+    // Together with its backup.
+    pub fn delete(&self, slot: u32) -> Result<(), GameError> {
+        let file = self.slot_path(slot);
+        for file in [with_suffix(&file, ".bak"), file] {
+            match std::fs::remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(GameError::Save(file, e.into())),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Overwrites whatever is in `to`, keeping it as the backup like saving does.
+    pub fn copy(&self, from: u32, to: u32) -> Result<(), GameError> {
+        let (source, target) = (self.slot_path(from), self.slot_path(to));
+        if !source.is_file() {
+            return Err(GameError::Save(source, Box::new(SaveError::EmptySlot(from))));
+        }
+        if from == to {
+            return Ok(());
+        }
+        let temporary = with_suffix(&target, ".tmp");
+        let copy = || -> io::Result<()> {
+            std::fs::copy(&source, &temporary)?;
+            if target.is_file() {
+                std::fs::copy(&target, with_suffix(&target, ".bak"))?;
+            }
+            std::fs::rename(&temporary, &target)
+        };
+        copy().map_err(|e| {
+            let _ = std::fs::remove_file(&temporary);
+            GameError::Save(target.clone(), e.into())
+        })
+    }
+
+    // Moves a save to another slot, together with its backup. Whatever was in `to` is
+    // kept as the backup instead, like copying does.
+    pub fn rename(&self, from: u32, to: u32) -> Result<(), GameError> {
+        let (source, target) = (self.slot_path(from), self.slot_path(to));
+        if !source.is_file() {
+            return Err(GameError::Save(source, Box::new(SaveError::EmptySlot(from))));
+        }
+        if from == to {
+            return Ok(());
+        }
+        let (source_backup, target_backup) = (with_suffix(&source, ".bak"), with_suffix(&target, ".bak"));
+        let overwrites = target.is_file();
+        if overwrites {
+            std::fs::copy(&target, &target_backup).map_err(|e| GameError::Save(target.clone(), e.into()))?;
+        }
+        // Replaces the target atomically, so it's never lost if this fails.
+        std::fs::rename(&source, &target).map_err(|e| GameError::Save(source, e.into()))?;
+        let moved = match (overwrites, source_backup.is_file()) {
+            (true, _) => std::fs::remove_file(&source_backup),
+            (false, true) => std::fs::rename(&source_backup, &target_backup),
+            // A backup left from a deleted save doesn't belong to this one.
+            (false, false) => std::fs::remove_file(&target_backup),
+        };
+        match moved {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(GameError::Save(source_backup, e.into())),
+            _ => Ok(()),
+        }
+    }
+
+    // Writes every slot, and the global data if there is any, into one bundle file.
+    pub fn export_all(&self, bundle: impl AsRef<Path>) -> Result<(), GameError> {
+        let bundle = bundle.as_ref();
+        let mut entries = BTreeMap::new();
+        for info in self.list_saves()? {
+            let file = self.slot_path(info.slot);
+            let slot = bundle::read_entry(&file).map_err(|e| GameError::Save(file, e.into()))?;
+            entries.insert(format!("{}{}", BUNDLE_SLOT_PREFIX, info.slot), slot);
+        }
+        if let Some(file) = self.global_data.as_ref().filter(|file| file.is_file()) {
+            let global = bundle::read_entry(file).map_err(|e| GameError::Save(file.clone(), e.into()))?;
+            entries.insert(bundle::GLOBAL_ENTRY.to_string(), global);
+        }
+        bundle::write_bundle(bundle, entries).map_err(|e| GameError::Save(bundle.to_path_buf(), e.into()))
+    }
+
+    // Writes the slots of a bundle from `export_all` over the ones here and returns
+    // their numbers. Global data can't be imported while a game has it open, see
+    // `EngineState::import_saves`.
+    pub fn import(&self, bundle: impl AsRef<Path>) -> Result<Vec<u32>, GameError> {
+        let bundle = bundle.as_ref();
+        let entries = bundle::read_bundle(bundle).map_err(|e| GameError::Save(bundle.to_path_buf(), e.into()))?;
+        if let (Some(file), true) = (&self.global_data, entries.contains_key(bundle::GLOBAL_ENTRY)) {
+            if persistent::is_open(file) {
+                return Err(GameError::Save(file.clone(), Box::new(SaveError::GlobalDataInUse)));
+            }
+        }
+        // Checked upfront, so a bad entry doesn't leave half of the bundle imported.
+        let mut slots = vec![];
+        for (name, entry) in &entries {
+            if let Some(slot) = name.strip_prefix(BUNDLE_SLOT_PREFIX).and_then(|slot| slot.parse().ok()) {
+                let serialized: SerializedState = serde_json::from_value(entry.clone())
+                    .map_err(|e| GameError::Save(bundle.to_path_buf(), format!("slot {}: {}", slot, e).into()))?;
+                slots.push((slot, serialized));
+            }
+        }
+
+        std::fs::create_dir_all(&self.directory).map_err(|e| GameError::Save(self.directory.clone(), e.into()))?;
+        for (slot, serialized) in &slots {
+            write_save(&self.slot_path(*slot), serialized, SaveFormat::Json)?;
+        }
+        if let (Some(file), Some(global)) = (&self.global_data, entries.get(bundle::GLOBAL_ENTRY)) {
+            let write = || -> Result<(), Box<dyn Error + Send + Sync>> {
+                write_atomically(file, &SaveFormat::Json.encode(global)?)?;
+                Ok(())
+            };
+            write().map_err(|e| GameError::Save(file.clone(), e))?;
+        }
+        Ok(slots.into_iter().map(|(slot, _)| slot).collect())
+    }
+
+    pub fn info(&self, slot: u32) -> Result<SaveInfo, GameError> {
+        let serialized = read_save(&self.slot_path(slot))?;
+        let (speaker, line) = match serialized.last_line {
+            Some((speaker, line)) => (speaker, Some(line)),
+            None => (None, None),
+        };
+        Ok(SaveInfo {
+            slot,
+            saved_at: serialized.saved_at.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            chapter: serialized.chapter,
+            speaker,
+            line,
+            play_time: Duration::from_secs(serialized.play_time),
+        })
+    }
+
+    // Every used slot, in order. Unreadable slots are skipped, so one broken file
+    // doesn't hide the others.
+    pub fn list_saves(&self) -> Result<Vec<SaveInfo>, GameError> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(GameError::Save(self.directory.clone(), e.into())),
+        };
+        let mut saves = vec![];
+        for entry in entries {
+            let entry = entry.map_err(|e| GameError::Save(self.directory.clone(), e.into()))?;
+            let slot = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("slot-"))
+                .and_then(|name| name.strip_suffix(".sav"))
+                .and_then(|slot| slot.parse().ok());
+            if let Some(slot) = slot {
+                match self.info(slot) {
+                    Ok(info) => saves.push(info),
+                    Err(e) => warn!(%e, "skipping unreadable save"),
+                }
+            }
+        }
+        saves.sort_by_key(|info| info.slot);
+        Ok(saves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{AUTOSAVE_SLOT, Autosave, SaveFormat, SaveManager};
+    use crate::{EngineState, SaveError, StepResult};
+    use crate::testing::TestGame;
+
+    #[test]
+    fn lists_save_slots() {
+        let game_dir = TestGame::new("saves");
+        game_dir.script("main.scr", "\
+            chapter Prologue\n\
+            text \"Ayu\" Uguu...\n\
+            text The end.\n");
+        let saves = SaveManager::new(game_dir.join("saves"));
+        assert!(saves.list_saves().unwrap().is_empty());
+
+        let mut state = EngineState::new(&game_dir);
+        let steps = state.run_until_interactive();
+        assert!(matches!(steps.last(), Some(StepResult::Text(_, _, true, _))));
+        // Only time the frontend ticks counts as played.
+        state.tick(Duration::from_millis(75_400));
+        saves.save(&state, 3).unwrap();
+        state.run_until_interactive();
+        state.tick(Duration::from_secs(5));
+        saves.save(&state, 1).unwrap();
+        std::fs::write(saves.slot_path(2), "not a save").unwrap();
+        let listed = saves.list_saves().unwrap();
+        saves.delete(1).unwrap();
+        let remaining = saves.list_saves().unwrap();
+        let loaded = saves.load(&mut state, 3);
+
+        assert_eq!(listed.iter().map(|info| info.slot).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(listed[1].chapter.as_deref(), Some("Prologue"));
+        assert_eq!(listed[1].speaker.as_deref(), Some("Ayu"));
+        assert_eq!(listed[1].line.as_deref(), Some("Uguu..."));
+        assert_eq!(listed[0].line.as_deref(), Some("The end."));
+        assert!(listed[0].saved_at.is_some());
+        assert_eq!(listed[1].play_time, Duration::from_secs(75));
+        assert_eq!(listed[0].play_time, Duration::from_secs(80));
+        assert_eq!(remaining.len(), 1);
+        assert!(loaded.is_ok());
+    }
+
+    #[test]
+    fn autosaves_at_choices_and_chapters() {
+        let game_dir = TestGame::new("autosave");
+        game_dir.script("main.scr", "\
+            text Before.\n\
+            choice Yes|No\n\
+            chapter Two\n\
+            text After.\n");
+        let saves = SaveManager::new(game_dir.join("saves"));
+
+        let mut state = EngineState::new(&game_dir);
+        let mut autosave = Autosave::new(SaveManager::new(saves.directory()));
+        autosave.at_chapters = true;
+        state.set_autosave(Some(autosave));
+        state.run_until_interactive();
+        let before_choice = saves.list_saves().unwrap();
+        state.run_until_interactive();
+        let at_choice = saves.info(AUTOSAVE_SLOT).unwrap();
+        state.run_until_interactive();
+        let at_chapter = saves.info(AUTOSAVE_SLOT).unwrap();
+        let mut loaded = EngineState::new(&game_dir);
+        let steps = saves.load(&mut loaded, AUTOSAVE_SLOT).unwrap();
+        let resumed = loaded.run_until_interactive();
+
+        assert!(before_choice.is_empty());
+        assert_eq!(at_choice.chapter, None);
+        assert_eq!(at_choice.line.as_deref(), Some("Before."));
+        assert_eq!(at_chapter.chapter.as_deref(), Some("Two"));
+        assert!(steps.iter().any(|step| matches!(step, StepResult::Chapter(chapter) if chapter == "Two")));
+        assert!(matches!(&resumed[..], [StepResult::Text(_, text, true, _)] if text == "After."));
+    }
+
+    #[test]
+    fn writes_binary_saves() {
+        let game_dir = TestGame::new("binary-save");
+        game_dir.script("main.scr", "setvar x = 42\ntext one\ntext two\n");
+        let json = game_dir.join("save.sav");
+        let binary = game_dir.join("save.msgpack");
+        let forced = game_dir.join("forced.sav");
+
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        state.save(&json).unwrap();
+        state.save(&binary).unwrap();
+        state.set_save_format(Some(SaveFormat::Binary));
+        state.save(&forced).unwrap();
+        let sizes = [&json, &binary, &forced].map(|file| std::fs::metadata(file).unwrap().len());
+        let mut loaded = EngineState::new(&game_dir);
+        let loads = [&json, &binary, &forced].map(|file| loaded.load(file).is_ok());
+        let variables = loaded.named_variables(false);
+
+        assert_eq!(sizes[1], sizes[2]);
+        assert!(sizes[1] < sizes[0]);
+        assert_eq!(loads, [true; 3]);
+        assert_eq!(variables, state.named_variables(false));
+    }
+
+    #[test]
+    fn keeps_previous_save_as_backup() {
+        let game_dir = TestGame::new("backup-save");
+        game_dir.script("main.scr", "text one\ntext two\n");
+        let saves = SaveManager::new(game_dir.join("saves"));
+
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        saves.save(&state, 1).unwrap();
+        state.run_until_interactive();
+        saves.save(&state, 1).unwrap();
+        let current = saves.info(1).unwrap();
+        let backup = std::fs::read(saves.slot_path(1).with_extension("sav.bak"));
+        let files = std::fs::read_dir(saves.directory()).unwrap().count();
+
+        assert_eq!(current.line.as_deref(), Some("two"));
+        assert!(String::from_utf8(backup.unwrap()).unwrap().contains("\"one\""));
+        assert_eq!(files, 2);
+    }
+
+    #[test]
+    fn saves_to_streams() {
+        let game_dir = TestGame::new("stream-save");
+        game_dir.script("main.scr", "setvar x = 1\ntext one\ntext two\n");
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        let mut json = vec![];
+        state.save_to(&mut json, SaveFormat::Json).unwrap();
+        let mut binary = vec![];
+        state.save_to(&mut binary, SaveFormat::Binary).unwrap();
+        let mut loaded = EngineState::new(&game_dir);
+        loaded.load_from(&binary[..]).unwrap();
+        let from_binary = loaded.run_until_interactive();
+        loaded.load_from(&json[..]).unwrap();
+        let from_json = loaded.run_until_interactive();
+        let garbage = loaded.load_from(&b"{"[..]).unwrap_err();
+
+        for resumed in [from_binary, from_json] {
+            assert!(matches!(&resumed[..], [StepResult::Text(_, text, true, _)] if text == "one"));
+        }
+        assert!(garbage.to_string().starts_with("save error: "));
+        assert!(!garbage.to_string().starts_with("save error: :"));
+    }
+
+    #[test]
+    fn copies_and_renames_slots() {
+        let game_dir = TestGame::new("slot-ops");
+        game_dir.script("main.scr", "text one\ntext two\n");
+        let saves = SaveManager::new(game_dir.join("saves"));
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        saves.save(&state, 1).unwrap();
+        state.run_until_interactive();
+        saves.save(&state, 2).unwrap();
+
+        saves.copy(1, 2).unwrap();
+        saves.rename(1, 5).unwrap();
+        let slots: Vec<_> = saves.list_saves().unwrap().into_iter().map(|info| (info.slot, info.line)).collect();
+        let overwritten_backup = saves.slot_path(2).with_extension("sav.bak").is_file();
+        let empty = saves.copy(1, 3).unwrap_err();
+
+        let line = |text: &str| Some(text.to_string());
+        assert_eq!(slots, [(2, line("one")), (5, line("one"))]);
+        assert!(overwritten_backup);
+        assert_eq!(empty.save_error(), Some(&SaveError::EmptySlot(1)));
+    }
+
+    #[test]
+    fn keeps_the_save_renamed_over_as_backup() {
+        let game_dir = TestGame::new("slot-rename");
+        game_dir.script("main.scr", "text one\ntext two\n");
+        let saves = SaveManager::new(game_dir.join("saves"));
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        saves.save(&state, 1).unwrap();
+        saves.save(&state, 2).unwrap();
+        state.run_until_interactive();
+        saves.save(&state, 2).unwrap();
+        let overwritten = std::fs::read(saves.slot_path(1)).unwrap();
+
+        saves.rename(2, 1).unwrap();
+        let slots: Vec<_> = saves.list_saves().unwrap().into_iter().map(|info| (info.slot, info.line)).collect();
+        let backup = std::fs::read(saves.slot_path(1).with_extension("sav.bak")).unwrap();
+        let source_backup = saves.slot_path(2).with_extension("sav.bak").exists();
+
+        assert_eq!(slots, [(1, Some("two".to_string()))]);
+        assert_eq!(backup, overwritten);
+        assert!(!source_backup);
+    }
+
+    #[test]
+    fn moves_all_saves_in_a_bundle() {
+        let game_dir = TestGame::new("save-bundle-all");
+        game_dir.script("main.scr", "text one\ntext two\n");
+        game_dir.write("global.sav", "{\"endings\": [\"true\"]}");
+        let saves = SaveManager::new(game_dir.join("saves")).with_global_data(game_dir.join("global.sav"));
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        saves.save(&state, 1).unwrap();
+        state.set_save_format(Some(SaveFormat::Binary));
+        state.run_until_interactive();
+        saves.save(&state, 4).unwrap();
+        let bundle = game_dir.join("progress.bundle");
+        saves.export_all(&bundle).unwrap();
+
+        let other = SaveManager::new(game_dir.join("other")).with_global_data(game_dir.join("other.sav"));
+        let imported = other.import(&bundle).unwrap();
+        let listed: Vec<_> = other.list_saves().unwrap().into_iter().map(|info| info.line).collect();
+        let global: serde_json::Value = serde_json::from_slice(&std::fs::read(game_dir.join("other.sav")).unwrap()).unwrap();
+        std::fs::write(&bundle, "not a bundle").unwrap();
+        let invalid = other.import(&bundle);
+
+        assert_eq!(imported, [1, 4]);
+        assert_eq!(listed, [Some("one".to_string()), Some("two".to_string())]);
+        assert_eq!(global["endings"][0], "true");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn imports_global_data_only_through_the_game_holding_it() {
+        let game_dir = TestGame::new("save-bundle-open");
+        game_dir.script("main.scr", "text one\n");
+        game_dir.write("exported.sav", "{\"completed_endings\": [\"true\"]}");
+        let exported = SaveManager::new(game_dir.join("saves")).with_global_data(game_dir.join("exported.sav"));
+        let bundle = game_dir.join("progress.bundle");
+        exported.export_all(&bundle).unwrap();
+
+        let saves = SaveManager::new(game_dir.join("saves")).with_global_data(game_dir.join("global.sav"));
+        let mut state = EngineState::new(&game_dir);
+        state.open_persistent_store(game_dir.join("global.sav")).unwrap();
+        let refused = saves.import(&bundle).unwrap_err();
+        state.import_saves(&saves, &bundle).unwrap();
+        let ended = state.completed_endings().contains(&"true");
+        drop(state);
+        let global: serde_json::Value = serde_json::from_slice(&std::fs::read(game_dir.join("global.sav")).unwrap()).unwrap();
+
+        assert_eq!(refused.save_error(), Some(&SaveError::GlobalDataInUse));
+        assert!(ended);
+        assert_eq!(global["completed_endings"][0], "true");
+    }
+
+    #[test]
+    fn tells_broken_saves_apart() {
+        let game_dir = TestGame::new("broken-saves");
+        game_dir.script("main.scr", "text one\n");
+        let mut state = EngineState::new(&game_dir);
+        state.run_until_interactive();
+        let mut json = vec![];
+        state.save_to(&mut json, SaveFormat::Json).unwrap();
+        let mut binary = vec![];
+        state.save_to(&mut binary, SaveFormat::Binary).unwrap();
+        let newer = String::from_utf8(json.clone()).unwrap().replacen("\"version\": 1,", "\"version\": 99,", 1);
+
+        let mut error = |data: &[u8]| state.load_from(data).unwrap_err().save_error().cloned();
+        let truncated_json = error(&json[..json.len() / 2]);
+        let truncated_binary = error(&binary[..binary.len() / 2]);
+        let empty = error(b"");
+        let invalid = error(b"{\"pc\": \"x\"}");
+        let unsupported = error(newer.as_bytes());
+        let missing = state.load(game_dir.join("missing.sav")).unwrap_err();
+
+        assert_eq!(truncated_json, Some(SaveError::Truncated));
+        assert_eq!(truncated_binary, Some(SaveError::Truncated));
+        assert_eq!(empty, Some(SaveError::Truncated));
+        assert!(matches!(invalid, Some(SaveError::Invalid(_))));
+        assert_eq!(unsupported, Some(SaveError::UnsupportedVersion(99)));
+        assert_eq!(missing.save_error(), Some(&SaveError::Missing));
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::ResourceProvider;
+
+// Lets games define their own sprite layers instead of the built-in main and date ones.
+pub const IMAGE_SLOTS_FILE: &str = "image_slots.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageSlot {
+    pub name: String,
+    // Higher layers are drawn on top of lower ones, the background is below all of them.
+    pub layer: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SlotDefinition {
+    #[serde(flatten)]
+    slot: ImageSlot,
+    // Images set through this variable, like `setimg DATEIMAGE 0 0`, go to this slot.
+    #[serde(default)]
+    variable: Option<String>,
+}
+
+// The first slot is used by `setimg` unless the script picks another one.
+pub(crate) struct ImageSlots {
+    definitions: Vec<SlotDefinition>,
+}
+
+impl Default for ImageSlots {
+    fn default() -> Self {
+        let slot = |name: &str, layer, variable: Option<&str>| SlotDefinition {
+            slot: ImageSlot { name: name.to_string(), layer },
+            variable: variable.map(str::to_string),
+        };
+        Self {
+            definitions: vec![
+                slot("main", 1, None),
+                slot("date", 2, Some("DATEIMAGE")),
+            ],
+        }
+    }
+}
+
+impl ImageSlots {
+    pub(crate) fn load(directory: &Path, resources: &dyn ResourceProvider) -> Self {
+        let file = directory.join(IMAGE_SLOTS_FILE);
+        if !resources.is_file(&file) {
+            return Self::default();
+        }
+        let definitions: Result<Vec<SlotDefinition>, std::io::Error> = resources.read(&file)
+            .and_then(|data| Ok(serde_json::from_slice(&data)?));
+        match definitions {
+            Ok(definitions) if !definitions.is_empty() => Self { definitions },
+            Ok(_) => {
+                warn!(file = %file.display(), "no image slots defined, using the default ones");
+                Self::default()
+            }
+            Err(e) => {
+                warn!(file = %file.display(), %e, "invalid image slots, using the default ones");
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&ImageSlot> {
+        self.definitions
+            .iter()
+            .map(|definition| &definition.slot)
+            .find(|slot| slot.name == name)
+    }
+
+    pub(crate) fn by_layer(&self) -> Vec<&ImageSlot> {
+        let mut slots: Vec<_> = self.definitions.iter().map(|definition| &definition.slot).collect();
+        slots.sort_by_key(|slot| slot.layer);
+        slots
+    }
+
+    pub(crate) fn for_variable(&self, variable: &str) -> &ImageSlot {
+        self.definitions
+            .iter()
+            .find(|definition| definition.variable.as_deref() == Some(variable))
+            .map_or(&self.definitions[0].slot, |definition| &definition.slot)
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::manifest::scripts_dir;
+use crate::{Instr, ScriptOptions, list_scripts, load_script};
+
+#[derive(Debug, Default, Serialize)]
+pub struct ScriptStats {
+    pub script: String,
+    // Text lines the player has to advance past.
+    pub lines: usize,
+    pub words: usize,
+    // Scripts without spaces between words, like Japanese ones, are better measured in these.
+    pub characters: usize,
+    pub narration_lines: usize,
+    pub lines_by_speaker: BTreeMap<String, usize>,
+    pub choices: usize,
+    pub branches: usize,
+}
+
+// Statistics of every script in the game, sorted by script name.
+pub fn analyze(
+    directory: impl AsRef<Path>,
+    options: &ScriptOptions,
+) -> Result<Vec<ScriptStats>, Box<dyn std::error::Error + Send + Sync>> {
+    let scripts_dir = scripts_dir(directory.as_ref());
+
+    let mut all = vec![];
+    for name in list_scripts(&scripts_dir, options)? {
+        let script = load_script(scripts_dir.join(&name), options)?;
+        let mut stats = ScriptStats { script: name, ..Default::default() };
+        for instr in &script.code {
+            match instr {
+                Instr::text(who, what, true) if !what.is_empty() => {
+                    stats.lines += 1;
+                    stats.words += what.split_whitespace().count();
+                    stats.characters += what.chars().filter(|c| !c.is_whitespace()).count();
+                    match who {
+                        Some(who) => *stats.lines_by_speaker.entry(who.clone()).or_default() += 1,
+                        None => stats.narration_lines += 1,
+                    }
+                }
+                Instr::choice(_) => stats.choices += 1,
+                Instr::branch(..) => stats.branches += 1,
+                _ => (),
+            }
+        }
+        all.push(stats);
+    }
+    Ok(all)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyze;
+    use crate::testing::TestGame;
+
+    #[test]
+    fn counts_lines_and_words() {
+        let game_dir = TestGame::new("stats");
+        game_dir.script("main.scr", "\
+            text It was cold.\n\
+            text \"Yuichi\" Hello there.\n\
+            text \"Yuichi\" Again.\n\
+            choice a|b\n\
+            if selected == 1\n\
+            text ~\n\
+            fi\n");
+
+        let stats = analyze(&game_dir, &Default::default()).unwrap();
+
+        let stats = &stats[0];
+        assert_eq!((stats.lines, stats.words, stats.characters), (3, 6, 27));
+        assert_eq!((stats.narration_lines, stats.lines_by_speaker["Yuichi"]), (1, 2));
+        assert_eq!((stats.choices, stats.branches), (1, 1));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::expr::Expr;
+use crate::{Instr, Script, VarOrConst};
+
+// Id of an interned name, only meaningful with the table which interned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Symbol(u32);
+
+// Names of the variables and scripts of a running game. Loaded scripts refer to their
+// variables by symbol, so looking them up neither hashes nor clones strings.
+#[derive(Default)]
+pub(crate) struct SymbolTable {
+    names: Vec<String>,
+    ids: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub(crate) fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.ids.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+
+    pub(crate) fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    // Interns the variables a script reads or writes. Constants are left alone, there
+    // are far more of them and they're never looked up.
+    pub(crate) fn intern_script(&mut self, script: &mut Script) {
+        for instr in script.code.iter_mut() {
+            match instr {
+                Instr::setvar(var, value) | Instr::gsetvar(var, value) => {
+                    self.intern_target(var);
+                    self.intern_expr(value);
+                }
+                Instr::unsetvar(Some(var)) | Instr::gunsetvar(Some(var)) => self.intern_target(var),
+                Instr::addvar(var, _) | Instr::random(var, _, _) => self.intern_target(var),
+                Instr::bgload(var, _) | Instr::setimg(var, _, _, _) => self.intern_var(var),
+                Instr::branch(lhs, _, rhs, _) => {
+                    self.intern_expr(lhs);
+                    self.intern_expr(rhs);
+                }
+                Instr::choice(options) => {
+                    for (text, condition) in options {
+                        self.intern_var(text);
+                        if let Some((lhs, _, rhs)) = condition {
+                            self.intern_expr(lhs);
+                            self.intern_expr(rhs);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn intern_target(&mut self, var: &mut VarOrConst) {
+        var.symbol = Some(self.intern(&var.name));
+        if let Some(index) = &mut var.index {
+            self.intern_var(index);
+        }
+    }
+
+    fn intern_var(&mut self, var: &mut VarOrConst) {
+        if var.is_ref {
+            self.intern_target(var);
+        }
+    }
+
+    fn intern_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Const(_) => (),
+            Expr::Var(var) => self.intern_var(var),
+            Expr::Binary(lhs, _, rhs) => {
+                self.intern_expr(lhs);
+                self.intern_expr(rhs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SymbolTable;
+    use crate::{Instr, ScriptOptions, parse};
+
+    #[test]
+    fn interns_script_variables() {
+        let mut script = parse("setvar x[$i] = $y + 1\nbgload bg.png\n", None, &ScriptOptions::default())
+            .unwrap()
+            .into_script()
+            .unwrap();
+        let mut symbols = SymbolTable::default();
+        symbols.intern_script(&mut script);
+
+        let x = symbols.get("x").unwrap();
+        assert_eq!(symbols.name(x), "x");
+        assert!(symbols.get("i").is_some());
+        assert!(symbols.get("y").is_some());
+        assert!(symbols.get("bg.png").is_none());
+        assert!(matches!(&script.code[0], Instr::setvar(var, _) if var.symbol == Some(x)));
+        assert_eq!(symbols.intern("x"), x);
+    }
+}
@@ -0,0 +1,55 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A scratch game directory for tests, removed again when dropped so a failing
+/// assertion doesn't leave it behind.
+pub(crate) struct TestGame {
+    directory: PathBuf,
+}
+
+impl TestGame {
+    pub(crate) fn new(name: &str) -> Self {
+        let directory = std::env::temp_dir().join(format!("madenon-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(directory.join("Scripts")).unwrap();
+        TestGame { directory }
+    }
+
+    /// Writes a file relative to the game directory, creating its parents.
+    pub(crate) fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let file = self.directory.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, contents).unwrap();
+        file
+    }
+
+    pub(crate) fn script(&self, name: &str, source: &str) -> PathBuf {
+        self.write(Path::new("Scripts").join(name), source)
+    }
+}
+
+impl Deref for TestGame {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.directory
+    }
+}
+
+impl AsRef<Path> for TestGame {
+    fn as_ref(&self) -> &Path {
+        &self.directory
+    }
+}
+
+impl From<&TestGame> for PathBuf {
+    fn from(game: &TestGame) -> Self {
+        game.directory.clone()
+    }
+}
+
+impl Drop for TestGame {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
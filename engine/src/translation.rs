@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::LineIds;
+
+// Translated text of lines by their stable id, see `LineIds`. Lines missing from the
+// table keep their original text.
+pub(crate) struct Translation {
+    line_ids: LineIds,
+    lines: HashMap<String, String>,
+}
+
+impl Translation {
+    pub(crate) fn load(line_ids_file: &Path, table_file: &Path) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            line_ids: LineIds::load(line_ids_file)?,
+            lines: serde_json::from_reader(std::fs::File::open(table_file)?)?,
+        })
+    }
+
+    pub(crate) fn text(&self, script: &str, pc: usize) -> Option<&str> {
+        let id = self.line_ids.id_at(script, pc)?;
+        self.lines.get(id).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EngineState, LINE_IDS_FILE, LineIds, StepResult, step};
+    use crate::testing::TestGame;
+
+    #[test]
+    fn replaces_translated_lines() {
+        let game_dir = TestGame::new("translation");
+        game_dir.script("main.scr", "text one\ntext two\n");
+        let mut ids = LineIds::default();
+        ids.update(&game_dir, &Default::default()).unwrap();
+        ids.save(game_dir.join(LINE_IDS_FILE)).unwrap();
+        let table = game_dir.write("table.json", format!(r#"{{"{}": "deux"}}"#, ids.id_at("main.scr", 1).unwrap()));
+
+        let mut state = EngineState::new(&game_dir);
+        state.load_translation(&table).unwrap();
+        let texts: Vec<String> = std::iter::from_fn(|| match step(&mut state) {
+            StepResult::Text(_, text, _, _) => Some(text),
+            _ => None,
+        }).collect();
+        assert_eq!(texts, vec!["one", "deux"]);
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::expr::Expr;
+use crate::manifest::scripts_dir;
+use crate::{Emitter, Instr, Label, ScriptOptions, VarOrConst, decode_script, list_scripts, parse, parse_script, split_args};
+
+#[derive(Debug)]
+pub enum Diagnostic {
+    ParseError { script: String, message: String },
+    UndefinedLabel { script: String, pc: usize, label: String },
+    UnusedLabel { script: String, label: String },
+    MissingScript { script: String, pc: usize, target: String },
+    Unreachable { script: String, range: Range<usize> },
+    // Lines are counted from 1, in the source passed to `lint`.
+    DuplicateLabel { script: String, line: usize, label: String },
+    UnmatchedFi { script: String, line: usize },
+    UnclosedIf { script: String, line: usize },
+    UnreadVariable { script: String, pc: usize, name: String },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::ParseError { script, message } =>
+                write!(f, "{}: failed to parse: {}", script, message),
+            Diagnostic::UndefinedLabel { script, pc, label } =>
+                write!(f, "{} @ {}: goto to undefined label {}", script, pc, label),
+            Diagnostic::UnusedLabel { script, label } =>
+                write!(f, "{}: label {} is never used", script, label),
+            Diagnostic::MissingScript { script, pc, target } =>
+                write!(f, "{} @ {}: jump to missing script {}", script, pc, target),
+            Diagnostic::Unreachable { script, range } =>
+                write!(f, "{} @ {}..{}: unreachable code", script, range.start, range.end),
+            Diagnostic::DuplicateLabel { script, line, label } =>
+                write!(f, "{}:{}: label {} is already defined", script, line, label),
+            Diagnostic::UnmatchedFi { script, line } =>
+                write!(f, "{}:{}: fi without a matching if", script, line),
+            Diagnostic::UnclosedIf { script, line } =>
+                write!(f, "{}:{}: if is never closed by a fi", script, line),
+            Diagnostic::UnreadVariable { script, pc, name } =>
+                write!(f, "{} @ {}: variable {} is set but never read", script, pc, name),
+        }
+    }
+}
+
+fn label_name(label: &Label) -> String {
+    match label {
+        Label::Offset(x) => format!("#{}", x),
+        Label::Indexed(x) => format!("@{}", x),
+        Label::Named(x) => x.clone(),
+    }
+}
+
+// Checks the scripts of the game as played in `language`, where translated scripts
+// stand in for the originals like they do in the engine.
+pub fn validate(
+    directory: impl AsRef<Path>,
+    language: Option<&str>,
+    options: &ScriptOptions,
+) -> Result<Vec<Diagnostic>, std::io::Error> {
+    let scripts_dir = scripts_dir(directory.as_ref());
+    let script_path = |name: &str| {
+        language
+            .map(|language| scripts_dir.join(language).join(name))
+            .filter(|path| options.resources().is_file(path))
+            .unwrap_or_else(|| scripts_dir.join(name))
+    };
+    let has_script = |name: &str| options.resources().is_file(&script_path(name));
+
+    let mut names = list_scripts(&scripts_dir, options)?;
+    if let Some(language) = language {
+        names.extend(list_scripts(&scripts_dir.join(language), options)?);
+        names.sort();
+        names.dedup();
+    }
+    let mut diagnostics = vec![];
+    for name in &names {
+        match parse_script(script_path(name), options) {
+            Ok(emitter) => validate_script(name, &emitter, Some(&has_script), &mut diagnostics),
+            Err(e) => diagnostics.push(Diagnostic::ParseError {
+                script: name.clone(),
+                message: e.to_string(),
+            }),
+        }
+    }
+    Ok(diagnostics)
+}
+
+// Checks a single script without its game, for editors. Jump targets aren't checked.
+// `source` is decoded (and decrypted) the way the engine would read the file.
+pub fn lint(name: &str, source: &[u8], options: &ScriptOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let source = match decode_script(source.to_vec(), options) {
+        Ok(source) => source,
+        Err(e) => {
+            diagnostics.push(Diagnostic::ParseError { script: name.to_string(), message: e.to_string() });
+            return diagnostics;
+        }
+    };
+    check_structure(name, &source, &mut diagnostics);
+    // Unbalanced branches can't be compiled.
+    let is_unbalanced = diagnostics.iter().any(|d| matches!(d,
+        Diagnostic::UnmatchedFi { .. } | Diagnostic::UnclosedIf { .. }));
+    if is_unbalanced {
+        return diagnostics;
+    }
+
+    match parse(&source, None, options) {
+        Ok(emitter) => {
+            validate_script(name, &emitter, None, &mut diagnostics);
+            check_variables(name, &emitter, &mut diagnostics);
+        }
+        Err(e) => diagnostics.push(Diagnostic::ParseError {
+            script: name.to_string(),
+            message: e.to_string(),
+        }),
+    }
+    diagnostics
+}
+
+fn check_structure(name: &str, source: &str, out: &mut Vec<Diagnostic>) {
+    let mut labels = HashSet::new();
+    // Lines of the `if`s not closed yet, innermost last.
+    let mut open_ifs = vec![];
+    for (idx, line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+        match split_args(line.trim(), 2)[..] {
+            ["label", label] if !labels.insert(label) => {
+                out.push(Diagnostic::DuplicateLabel {
+                    script: name.to_string(),
+                    line: line_number,
+                    label: label.to_string(),
+                });
+            }
+            ["if", ..] => open_ifs.push(line_number),
+            ["fi"] if open_ifs.pop().is_none() => {
+                out.push(Diagnostic::UnmatchedFi { script: name.to_string(), line: line_number });
+            }
+            _ => (),
+        }
+    }
+    for line in open_ifs {
+        out.push(Diagnostic::UnclosedIf { script: name.to_string(), line });
+    }
+}
+
+// Local variables which are set but never read are likely typos. Globals are left out,
+// since they are usually read by other scripts.
+fn check_variables(name: &str, emitter: &Emitter, out: &mut Vec<Diagnostic>) {
+    let mut written: HashMap<&str, usize> = HashMap::new();
+    let mut read = HashSet::new();
+    for (pc, instr) in emitter.code.iter().enumerate() {
+        match instr {
+            Instr::setvar(var, value) => {
+                written.entry(&var.name).or_insert(pc);
+                read_index(var, &mut read);
+                read_expr(value, &mut read);
+            }
+            Instr::addvar(var, _) | Instr::random(var, _, _) => {
+                written.entry(&var.name).or_insert(pc);
+                read_index(var, &mut read);
+            }
+            Instr::gsetvar(var, value) => {
+                read_index(var, &mut read);
+                read_expr(value, &mut read);
+            }
+            Instr::bgload(var, _) | Instr::setimg(var, _, _, _) => read_var(var, &mut read),
+            Instr::branch(lhs, _, rhs, _) => {
+                read_expr(lhs, &mut read);
+                read_expr(rhs, &mut read);
+            }
+            Instr::choice(options) => {
+                for (text, condition) in options {
+                    read_var(text, &mut read);
+                    if let Some((lhs, _, rhs)) = condition {
+                        read_expr(lhs, &mut read);
+                        read_expr(rhs, &mut read);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut unread: Vec<_> = written
+        .into_iter()
+        .filter(|(var, _)| !read.contains(var))
+        .collect();
+    unread.sort_by_key(|&(_, pc)| pc);
+    out.extend(unread.into_iter().map(|(var, pc)| Diagnostic::UnreadVariable {
+        script: name.to_string(),
+        pc,
+        name: var.to_string(),
+    }));
+}
+
+fn read_var<'a>(var: &'a VarOrConst, read: &mut HashSet<&'a str>) {
+    if var.is_ref {
+        read.insert(&var.name);
+    }
+    read_index(var, read);
+}
+
+fn read_index<'a>(var: &'a VarOrConst, read: &mut HashSet<&'a str>) {
+    if let Some(index) = &var.index {
+        read_var(index, read);
+    }
+}
+
+fn read_expr<'a>(expr: &'a Expr, read: &mut HashSet<&'a str>) {
+    match expr {
+        Expr::Const(_) => (),
+        Expr::Var(var) => read_var(var, read),
+        Expr::Binary(lhs, _, rhs) => {
+            read_expr(lhs, read);
+            read_expr(rhs, read);
+        }
+    }
+}
+
+// Jump targets are only checked given `has_script`, which tells whether a script exists.
+fn validate_script(name: &str, emitter: &Emitter, has_script: Option<&dyn Fn(&str) -> bool>, out: &mut Vec<Diagnostic>) {
+    let code = &emitter.code;
+
+    let mut used_labels = HashSet::new();
+    for (pc, instr) in code.iter().enumerate() {
+        match instr {
+            Instr::goto(label) if emitter.labels.contains_key(label) => {
+                used_labels.insert(label);
+            }
+            Instr::goto(label) => {
+                out.push(Diagnostic::UndefinedLabel {
+                    script: name.to_string(),
+                    pc,
+                    label: label_name(label),
+                });
+            }
+            Instr::jump(target, _) if has_script.is_some_and(|has_script| !has_script(target)) => {
+                out.push(Diagnostic::MissingScript {
+                    script: name.to_string(),
+                    pc,
+                    target: target.clone(),
+                });
+            }
+            _ => (),
+        }
+    }
+
+    let mut unused: Vec<_> = emitter.labels
+        .keys()
+        .filter(|label| !used_labels.contains(label))
+        .map(label_name)
+        .collect();
+    unused.sort();
+    out.extend(unused.into_iter().map(|label| Diagnostic::UnusedLabel {
+        script: name.to_string(),
+        label,
+    }));
+
+    let mut visited = vec![false; code.len()];
+    let mut pending = vec![0];
+    while let Some(pc) = pending.pop() {
+        if pc >= code.len() || visited[pc] {
+            continue;
+        }
+        visited[pc] = true;
+        match &code[pc] {
+            Instr::branch(_, _, _, else_target) => {
+                pending.push(pc + 1);
+                pending.push(*else_target);
+            }
+            Instr::goto(label) => {
+                pending.extend(emitter.labels.get(label).copied());
+            }
+            Instr::jump(..) | Instr::endscript => (),
+            _ => pending.push(pc + 1),
+        }
+    }
+
+    let mut pc = 0;
+    while pc < code.len() {
+        if visited[pc] {
+            pc += 1;
+            continue;
+        }
+        let start = pc;
+        while pc < code.len() && !visited[pc] {
+            pc += 1;
+        }
+        out.push(Diagnostic::Unreachable {
+            script: name.to_string(),
+            range: start..pc,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Diagnostic, lint, validate};
+    use crate::testing::TestGame;
+    use crate::{ArchiveResources, ScriptEncoding, ScriptOptions};
+
+    #[test]
+    fn reports_problems() {
+        let directory = TestGame::new("validate");
+        directory.script("main.scr", "\
+            goto end\n\
+            text unreachable\n\
+            label unused\n\
+            label end\n\
+            goto nowhere\n\
+            jump missing.scr\n");
+
+        let diagnostics = validate(&directory, None, &ScriptOptions::default()).unwrap();
+
+        assert!(matches!(&diagnostics[..], [
+            Diagnostic::UndefinedLabel { pc: 2, .. },
+            Diagnostic::MissingScript { pc: 3, .. },
+            Diagnostic::UnusedLabel { .. },
+            Diagnostic::Unreachable { range: first, .. },
+            Diagnostic::Unreachable { range: second, .. },
+        ] if *first == (1..2) && *second == (3..4)));
+    }
+
+    #[test]
+    fn validates_games_in_archives() {
+        let game_dir = TestGame::new("validate-archive");
+        let file = game_dir.join("game.legArchive");
+        let mut writer = leg_archive::Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("Scripts/main.scr", b"if $x == 1\njump gone.scr\nfi\njump b.scr\n").unwrap();
+        writer.add("Scripts/b.scr", b"text b\n").unwrap();
+        writer.finish().unwrap();
+        let resources = ArchiveResources::open(&file, game_dir.to_path_buf()).unwrap();
+        let options = ScriptOptions { resources: Some(Arc::new(resources)), ..Default::default() };
+
+        let diagnostics = validate(&game_dir, None, &options).unwrap();
+
+        assert!(matches!(&diagnostics[..], [
+            Diagnostic::MissingScript { script, pc: 1, target },
+        ] if script == "main.scr" && target == "gone.scr"));
+    }
+
+    #[test]
+    fn validates_translated_scripts() {
+        let directory = TestGame::new("validate-language");
+        directory.script("main.scr", "jump b.scr\n");
+        directory.script("en/main.scr", "text hello\njump b.scr\n");
+        directory.script("en/b.scr", "text b\n");
+
+        let original = validate(&directory, None, &ScriptOptions::default()).unwrap();
+        let translated = validate(&directory, Some("en"), &ScriptOptions::default()).unwrap();
+
+        assert!(matches!(&original[..], [Diagnostic::MissingScript { target, .. }] if target == "b.scr"));
+        assert!(translated.is_empty());
+    }
+
+    #[test]
+    fn lints_source() {
+        let diagnostics = lint("main.scr", b"\
+            label a\n\
+            label a\n\
+            if 1 == 1\n\
+            fi\n\
+            fi\n", &ScriptOptions::default());
+        assert!(matches!(&diagnostics[..], [
+            Diagnostic::DuplicateLabel { line: 2, .. },
+            Diagnostic::UnmatchedFi { line: 5, .. },
+        ]));
+
+        let diagnostics = lint("main.scr", b"\
+            if 1 == 1\n\
+            if 2 == 2\n\
+            fi\n\
+            if 3 == 3\n\
+            fi\n", &ScriptOptions::default());
+        assert!(matches!(&diagnostics[..], [Diagnostic::UnclosedIf { line: 1, .. }]));
+
+        let diagnostics = lint("main.scr", b"\
+            setvar count 1\n\
+            setvar conut 2\n\
+            if count == 1\n\
+            text yes\n\
+            fi\n", &ScriptOptions::default());
+        assert!(matches!(&diagnostics[..], [
+            Diagnostic::UnreadVariable { pc: 1, name, .. },
+        ] if name == "conut"));
+    }
+
+    #[test]
+    fn lints_with_script_options() {
+        let source = b"# comment\ntext \x82\xa0\n";
+        let utf8 = ScriptOptions { encoding: ScriptEncoding::Utf8, vnds_compat: true, ..Default::default() };
+        let vnds = ScriptOptions { vnds_compat: true, ..Default::default() };
+
+        assert!(matches!(&lint("main.scr", source, &ScriptOptions::default())[..], [Diagnostic::ParseError { .. }]));
+        assert!(matches!(&lint("main.scr", source, &utf8)[..], [Diagnostic::ParseError { .. }]));
+        assert!(lint("main.scr", source, &vnds).is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// Untagged so that saves from before typed variables, which stored every value
+// as a string, still load.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    // Script literals are integers whenever they're written like one. Anything which
+    // wouldn't be written back the same, like `007` or `+1`, stays a string.
+    pub fn parse(s: &str) -> Self {
+        match s.parse::<i64>() {
+            Ok(n) if n.to_string() == s => Value::Int(n),
+            _ => Value::Str(s.to_string()),
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            Value::Str(s) => s.trim().parse().ok(),
+        }
+    }
+
+    // Arithmetic treats non-numeric strings as 0.
+    pub fn to_int(&self) -> i64 {
+        self.as_int().unwrap_or(0)
+    }
+
+    // Numeric when both sides are numbers, otherwise a plain string comparison.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self.as_int(), other.as_int()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => self.to_string().cmp(&other.to_string()),
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Str(String::new())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::Value;
+
+    #[test]
+    fn coercion() {
+        assert_eq!(Value::parse("9").compare(&Value::parse("10")), Ordering::Less);
+        assert_eq!(Value::Str("10".into()).compare(&Value::Int(10)), Ordering::Equal);
+        assert_eq!(Value::parse("b").compare(&Value::parse("a")), Ordering::Greater);
+        assert_eq!(Value::parse("abc").to_int(), 0);
+        assert_eq!(Value::default().compare(&Value::parse("")), Ordering::Equal);
+        assert_eq!(Value::parse("-5"), Value::Int(-5));
+    }
+
+    #[test]
+    fn keeps_leading_zeros() {
+        assert_eq!(Value::parse("007"), Value::Str("007".to_string()));
+        assert_eq!(Value::parse("+1"), Value::Str("+1".to_string()));
+        assert_eq!(Value::parse("007").to_string(), "007");
+        // Still compares as a number.
+        assert_eq!(Value::parse("007").compare(&Value::Int(7)), Ordering::Equal);
+    }
+}
@@ -0,0 +1,102 @@
+// Runs every script in tests/conformance as the `main.scr` of an otherwise empty game
+// and compares the results it produces with the transcript next to it. Scripts in
+// the `vnds` subdirectory are parsed in VNDS compatibility mode.
+//
+// Run with CONFORMANCE_BLESS=1 to write the transcripts instead of checking them.
+
+use std::path::{Path, PathBuf};
+
+use engine::{EngineState, ScriptOptions, StepResult, step};
+
+const MAX_STEPS: usize = 10_000;
+
+fn run_case(script: &Path, options: ScriptOptions) -> Vec<StepResult> {
+    let game_dir = std::env::temp_dir().join(format!(
+        "madenon-conformance-{}-{}",
+        std::process::id(),
+        script.file_stem().unwrap().to_string_lossy(),
+    ));
+    std::fs::create_dir_all(game_dir.join("Scripts")).unwrap();
+    std::fs::copy(script, game_dir.join("Scripts").join("main.scr")).unwrap();
+
+    // Paths are written relative to the game directory so the transcripts are portable.
+    let relative = |path: PathBuf| -> PathBuf {
+        let path = path.strip_prefix(&game_dir).unwrap();
+        let parts: Vec<_> = path.iter().map(|part| part.to_string_lossy()).collect();
+        PathBuf::from(parts.join("/"))
+    };
+
+    let mut state = EngineState::with_options(&game_dir, options);
+    let mut transcript = vec![];
+    for _ in 0..MAX_STEPS {
+        match step(&mut state) {
+            StepResult::Continue => {}
+            StepResult::Exit => {
+                transcript.push(StepResult::Exit);
+                break;
+            }
+            StepResult::Jump(file) => {
+                state.load_script(&file).unwrap();
+                transcript.push(StepResult::Jump(file));
+            }
+            StepResult::Background(path) => {
+                transcript.push(StepResult::Background(relative(path)));
+            }
+            StepResult::Image(path, slot, x, y) => {
+                transcript.push(StepResult::Image(relative(path), slot, x, y));
+            }
+            result => transcript.push(result),
+        }
+    }
+
+    std::fs::remove_dir_all(&game_dir).unwrap();
+    transcript
+}
+
+fn cases(dir: &Path) -> Vec<PathBuf> {
+    let mut scripts: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "scr"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance");
+    let bless = std::env::var_os("CONFORMANCE_BLESS").is_some();
+
+    let plain = cases(&root).into_iter().map(|script| (script, false));
+    let vnds = cases(&root.join("vnds")).into_iter().map(|script| (script, true));
+
+    let mut failures = vec![];
+    for (script, vnds_compat) in plain.chain(vnds) {
+        let options = ScriptOptions { vnds_compat, ..Default::default() };
+        let actual = serde_json::to_value(run_case(&script, options)).unwrap();
+
+        let expected_path = script.with_extension("json");
+        if bless {
+            let mut json = serde_json::to_string_pretty(&actual).unwrap();
+            json.push('\n');
+            std::fs::write(&expected_path, json).unwrap();
+            continue;
+        }
+
+        let expected: serde_json::Value = std::fs::read_to_string(&expected_path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| panic!("can't read {}: {}", expected_path.display(), e));
+        if actual != expected {
+            failures.push(format!(
+                "{}:\nexpected: {}\n  actual: {}",
+                script.strip_prefix(&root).unwrap().display(),
+                expected,
+                actual,
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "conformance cases failed:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Read, Write};
+
+// How an entry's data is stored. Entries are compressed one by one, so any of them
+// can still be read without the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zlib,
+    Zstd,
+}
+
+impl Compression {
+    // The flag written to the table.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zlib => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Zlib),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zlib => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(data, 19),
+        }
+    }
+
+    // Fails unless the data decompresses to exactly `size` bytes.
+    pub(crate) fn decompress(self, data: impl Read, size: u64) -> io::Result<Vec<u8>> {
+        // One byte more than expected is enough to tell the size is wrong.
+        let mut limited: Box<dyn Read> = match self {
+            Compression::None => Box::new(data.take(size + 1)),
+            Compression::Zlib => Box::new(flate2::read::ZlibDecoder::new(data).take(size + 1)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(data)?.take(size + 1)),
+        };
+        let mut buf = Vec::with_capacity(size.min(1 << 24) as usize);
+        limited.read_to_end(&mut buf)?;
+        if buf.len() as u64 != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "entry doesn't decompress to its size"));
+        }
+        Ok(buf)
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+// How an entry's data is obfuscated, after it was compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    None,
+    Xor,
+    Aes128Ctr,
+}
+
+impl Encryption {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Encryption::None => 0,
+            Encryption::Xor => 1,
+            Encryption::Aes128Ctr => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Encryption::None),
+            1 => Some(Encryption::Xor),
+            2 => Some(Encryption::Aes128Ctr),
+            _ => None,
+        }
+    }
+}
+
+// Keys of archives whose entries are encrypted. Both ciphers are symmetric, so the
+// same key encrypts and decrypts.
+#[derive(Clone)]
+pub enum ArchiveKey {
+    // Repeated from the start of every entry.
+    Xor(Vec<u8>),
+    // One key stream for the whole archive, by position in it, so no two entries share
+    // any of it. It starts from the archive's nonce, so archives encrypted with the
+    // same key, or packed again, don't share it either.
+    Aes128Ctr([u8; 16]),
+}
+
+impl ArchiveKey {
+    // `xor:` or `aes:` followed by the key in hex, 16 bytes for AES.
+    pub fn parse(key: &str) -> Option<Self> {
+        let (cipher, hex) = key.split_once(':')?;
+        let pairs = hex.as_bytes().chunks_exact(2);
+        if !pairs.remainder().is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let bytes = pairs
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        match cipher {
+            "xor" if !bytes.is_empty() => Some(ArchiveKey::Xor(bytes)),
+            "aes" => <[u8; 16]>::try_from(bytes).ok().map(ArchiveKey::Aes128Ctr),
+            _ => None,
+        }
+    }
+
+    pub fn encryption(&self) -> Encryption {
+        match self {
+            ArchiveKey::Xor(_) => Encryption::Xor,
+            ArchiveKey::Aes128Ctr(_) => Encryption::Aes128Ctr,
+        }
+    }
+
+    // `start` is where the entry's data starts in the archive, and `offset` where `data`
+    // is in the entry, so any part of an entry can be decrypted on its own.
+    pub(crate) fn apply(&self, data: &mut [u8], nonce: u64, start: u64, offset: u64) {
+        match self {
+            ArchiveKey::Xor(key) => {
+                let skip = (offset % key.len() as u64) as usize;
+                for (b, k) in data.iter_mut().zip(key.iter().cycle().skip(skip)) {
+                    *b ^= k;
+                }
+            }
+            ArchiveKey::Aes128Ctr(key) => {
+                let mut cipher = Aes128Ctr::new(key.into(), &iv(nonce, 0).into());
+                cipher.seek(start + offset);
+                cipher.apply_keystream(data);
+            }
+        }
+    }
+
+    // Stored in the table, so a wrong AES key is told apart from damaged entries. It's
+    // the key stream of a block no archive is large enough to reach.
+    pub(crate) fn check(&self, nonce: u64) -> Option<[u8; 8]> {
+        match self {
+            ArchiveKey::Xor(_) => None,
+            ArchiveKey::Aes128Ctr(key) => {
+                let mut check = [0u8; 8];
+                Aes128Ctr::new(key.into(), &iv(nonce, u64::MAX).into()).apply_keystream(&mut check);
+                Some(check)
+            }
+        }
+    }
+
+    pub(crate) fn check_encryption(&self, encryption: Encryption) -> io::Result<()> {
+        if encryption != self.encryption() {
+            let message = format!("entry is encrypted with {:?}, not {:?}", encryption, self.encryption());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        Ok(())
+    }
+}
+
+// The nonce in the high half, the block counter in the low one.
+fn iv(nonce: u64, block: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[..8].copy_from_slice(&nonce.to_be_bytes());
+    iv[8..].copy_from_slice(&block.to_be_bytes());
+    iv
+}
+
+// Only has to differ between archives, it isn't secret.
+pub(crate) fn new_nonce() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+    RandomState::new().hash_one(now)
+}
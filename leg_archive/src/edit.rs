@@ -0,0 +1,103 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::crypt::new_nonce;
+use crate::writer::TableEntry;
+use crate::{ArchiveError, ArchiveKey, Compression, Encryption, Normalization, Writer, load_with};
+
+// Changes an archive in place. New files and the new table are written after the end
+// of the archive, so the data already in it isn't copied and its table stays valid
+// until `finish`. Replaced and removed files, and the old table, still take up space
+// until the archive is packed again. Dropping the editor without finishing it, or any
+// error, cuts the archive back to how it was, and the editor can't be used anymore.
+pub struct Editor {
+    // Taken when the edit is finished or undone.
+    writer: Option<Writer<BufWriter<File>>>,
+    // Another handle to the archive, to cut it back with.
+    file: File,
+    original_len: u64,
+}
+
+pub fn edit(path: impl AsRef<Path>) -> Result<Editor, ArchiveError> {
+    let archive = load_with(path.as_ref(), Normalization::NONE)?;
+    let files = archive.entries().map(TableEntry::from).collect();
+    // Nothing was encrypted with the nonce yet, so a new one is as good.
+    let encrypted = archive.entries().any(|entry| entry.encryption() != Encryption::None);
+    let nonce = if encrypted { archive.nonce } else { new_nonce() };
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let original_len = file.seek(SeekFrom::End(0))?;
+    Ok(Editor {
+        file: file.try_clone()?,
+        writer: Some(Writer::resume(BufWriter::new(file), original_len, files, nonce, archive.key_check)),
+        original_len,
+    })
+}
+
+impl Editor {
+    pub fn set_compression(&mut self, compression: Compression) {
+        if let Some(writer) = &mut self.writer {
+            writer.set_compression(compression);
+        }
+    }
+
+    pub fn set_key(&mut self, key: Option<ArchiveKey>) {
+        if let Some(writer) = &mut self.writer {
+            writer.set_key(key);
+        }
+    }
+
+    // Adds the file, or replaces it if the archive has a file with exactly this name.
+    pub fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let result = self.writer()?.add(name, data);
+        self.undo_on_error(result)
+    }
+
+    pub fn add_file(&mut self, name: &str, path: impl AsRef<Path>) -> io::Result<()> {
+        let result = self.writer()?.add_file(name, path);
+        self.undo_on_error(result)
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.writer.as_mut().is_some_and(|writer| writer.remove(name))
+    }
+
+    // Writes the new table after the new files.
+    pub fn finish(mut self) -> io::Result<()> {
+        let writer = self.writer.take().ok_or_else(undone)?;
+        let finished = writer.finish().and_then(|writer| writer.into_inner().map_err(|e| e.into_error()));
+        let file = self.undo_on_error(finished)?;
+        file.sync_all()
+    }
+
+    fn writer(&mut self) -> io::Result<&mut Writer<BufWriter<File>>> {
+        self.writer.as_mut().ok_or_else(undone)
+    }
+
+    fn undo_on_error<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if result.is_err() {
+            self.undo();
+        }
+        result
+    }
+
+    fn undo(&mut self) {
+        // Data still buffered is dropped instead of written.
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.into_inner().into_parts();
+        }
+        let _ = self.file.set_len(self.original_len);
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            self.undo();
+        }
+    }
+}
+
+fn undone() -> io::Error {
+    io::Error::other("the edit was undone after an error")
+}
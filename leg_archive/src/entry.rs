@@ -0,0 +1,160 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+#[cfg(not(any(unix, windows)))]
+use std::sync::{Mutex, PoisonError};
+
+use crate::ArchiveKey;
+
+// Where an archive's data is read from.
+pub(crate) enum Storage {
+    File(File),
+    // The whole archive file, read once.
+    Memory(Arc<[u8]>),
+}
+
+impl Storage {
+    #[cfg(feature = "async")]
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Storage::File(file) => file.try_clone().map(Storage::File),
+            Storage::Memory(data) => Ok(Storage::Memory(data.clone())),
+        }
+    }
+}
+
+// Reads a single entry on demand. Positions are relative to the start of the entry,
+// and reads stop at its end. Every read says where it reads from, so any number of
+// readers can share the archive file.
+pub struct EntryReader<'a> {
+    source: Source<'a>,
+    position: u64,
+    decryption: Option<Decryption<'a>>,
+}
+
+// Encrypted entries are decrypted as they're read, by where the data is in the archive.
+struct Decryption<'a> {
+    key: &'a ArchiveKey,
+    nonce: u64,
+    // Where the entry starts in the archive.
+    start: u64,
+}
+
+enum Source<'a> {
+    File(&'a File, Range<u64>),
+    // Entries of preloaded archives, and compressed entries, which are decompressed
+    // when they're opened.
+    Memory(Cow<'a, [u8]>),
+}
+
+impl<'a> EntryReader<'a> {
+    pub(crate) fn new(storage: &'a Storage, range: Range<u64>) -> io::Result<Self> {
+        let source = match storage {
+            Storage::File(file) => Source::File(file, range),
+            Storage::Memory(data) => {
+                let data = data.get(range.start as usize..range.end as usize)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "entry is past the end of the archive"))?;
+                Source::Memory(Cow::Borrowed(data))
+            }
+        };
+        Ok(Self {
+            source,
+            position: 0,
+            decryption: None,
+        })
+    }
+
+    pub(crate) fn from_memory(data: Vec<u8>) -> Self {
+        Self {
+            source: Source::Memory(Cow::Owned(data)),
+            position: 0,
+            decryption: None,
+        }
+    }
+
+    // For entries encrypted with `key`, starting at `start` in the archive.
+    pub(crate) fn decrypted(self, key: &'a ArchiveKey, nonce: u64, start: u64) -> Self {
+        Self { decryption: Some(Decryption { key, nonce, start }), ..self }
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.source {
+            Source::File(_, range) => range.end - range.start,
+            Source::Memory(data) => data.len() as u64,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len().saturating_sub(self.position);
+        let len = (buf.len() as u64).min(remaining) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let read = match &self.source {
+            Source::File(file, range) => read_at(file, &mut buf[..len], range.start + self.position)?,
+            Source::Memory(data) => {
+                let start = self.position as usize;
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                len
+            }
+        };
+        if let Some(Decryption { key, nonce, start }) = &self.decryption {
+            key.apply(&mut buf[..read], *nonce, *start, self.position);
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for EntryReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add(self.len(), offset),
+            SeekFrom::Current(offset) => checked_add(self.position, offset),
+        };
+        let position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position")
+        })?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+pub(crate) fn checked_add(base: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        base.checked_sub(offset.unsigned_abs())
+    } else {
+        base.checked_add(offset as u64)
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+// Moves the file's cursor too, but nothing depends on it.
+#[cfg(windows)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+// Elsewhere, like on wasm, reads seek first, so they're done one at a time to not move
+// each other's cursor.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
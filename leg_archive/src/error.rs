@@ -0,0 +1,67 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    // The file doesn't end with a table, so it's likely not an archive at all.
+    BadMagic,
+    // The file ends in the middle of the table.
+    TruncatedTable,
+    NonUtf8Name(String),
+    // An entry's data reaches past the end of the file.
+    OffsetOutOfRange { name: String, end: u64, file_len: u64 },
+    UnknownCompression { name: String, method: u8 },
+    // The AES key doesn't match the one the archive was encrypted with.
+    WrongKey,
+    Zip(zip::result::ZipError),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "{}", e),
+            ArchiveError::BadMagic => write!(f, "not an archive, the table is missing"),
+            ArchiveError::TruncatedTable => write!(f, "the table is truncated"),
+            ArchiveError::NonUtf8Name(name) => write!(f, "entry name {:?} is not valid UTF-8", name),
+            ArchiveError::OffsetOutOfRange { name, end, file_len } => {
+                write!(f, "entry {} ends at {}, past the end of the file at {}", name, end, file_len)
+            }
+            ArchiveError::UnknownCompression { name, method } => {
+                write!(f, "entry {} is stored with unknown method {}", name, method)
+            }
+            ArchiveError::WrongKey => write!(f, "the archive is encrypted with another key"),
+            ArchiveError::Zip(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ArchiveError::Io(e) => Some(e),
+            ArchiveError::Zip(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Running out of data can only happen while reading the table.
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => ArchiveError::TruncatedTable,
+            _ => ArchiveError::Io(e),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => ArchiveError::Io(e),
+            e => ArchiveError::Zip(e),
+        }
+    }
+}
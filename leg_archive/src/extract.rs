@@ -0,0 +1,66 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{Archive, ArchiveEntry};
+
+impl Archive {
+    // Writes every entry into `directory`, keeping the directories in their names.
+    // `progress` is called after each entry with it, how many entries were written and
+    // how many there are in total.
+    pub fn extract_all(
+        &self,
+        directory: impl AsRef<Path>,
+        progress: impl FnMut(&ArchiveEntry, usize, usize),
+    ) -> io::Result<usize> {
+        self.extract(directory.as_ref(), self.entries().collect(), progress)
+    }
+
+    // Like `extract_all`, for the entries whose names match a glob pattern such as
+    // `sound/*.aac`. `*` doesn't match across directories, `**` does.
+    pub fn extract_matching(
+        &self,
+        directory: impl AsRef<Path>,
+        pattern: &str,
+        progress: impl FnMut(&ArchiveEntry, usize, usize),
+    ) -> io::Result<usize> {
+        let pattern = glob::Pattern::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let options = glob::MatchOptions {
+            case_sensitive: !self.normalization.case,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let entries = self.entries().filter(|entry| pattern.matches_with(entry.name(), options)).collect();
+        self.extract(directory.as_ref(), entries, progress)
+    }
+
+    fn extract(
+        &self,
+        directory: &Path,
+        entries: Vec<&ArchiveEntry>,
+        mut progress: impl FnMut(&ArchiveEntry, usize, usize),
+    ) -> io::Result<usize> {
+        for (i, entry) in entries.iter().enumerate() {
+            let path = directory.join(entry_path(entry.name())?);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut reader = self.open_entry(entry)?;
+            io::copy(&mut reader, &mut std::fs::File::create(&path)?)?;
+            progress(entry, i + 1, entries.len());
+        }
+        Ok(entries.len())
+    }
+}
+
+// Names are split at both kinds of separators. Names which would end up outside of
+// the directory, like `../x` or `/x`, aren't extracted.
+fn entry_path(name: &str) -> io::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        if part.is_empty() || part == "." || part == ".." || part.contains(':') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} can't be extracted safely", name)));
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
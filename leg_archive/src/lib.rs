@@ -1,72 +1,366 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
 use std::ops::Range;
 use std::path::Path;
 
-#[derive(Debug)]
-struct ArchiveEntry {
+use entry::Storage;
+
+mod compression;
+mod crypt;
+mod edit;
+mod entry;
+mod error;
+mod extract;
+mod normalize;
+mod set;
+mod writer;
+mod zipped;
+
+pub use compression::Compression;
+pub use crypt::{ArchiveKey, Encryption};
+pub use edit::{Editor, edit};
+pub use entry::EntryReader;
+pub use error::ArchiveError;
+pub use normalize::{Normalization, UnicodeForm};
+pub use set::ArchiveSet;
+pub use writer::{Writer, pack, pack_encrypted, pack_with};
+pub use zipped::ZipArchive;
+
+// What the engine needs from an archive, whatever its format.
+pub trait ArchiveRead: Send + Sync {
+    fn contains(&self, name: &str) -> bool;
+    fn read(&self, name: &str) -> Option<Box<[u8]>>;
+    // Names of the files in no particular order.
+    fn names(&self) -> Vec<String>;
+
+    // The bytes of the file in `range`, which is cut off at the end of the file.
+    // Archives which can't read parts of files read them whole.
+    fn read_range(&self, name: &str, range: Range<u64>) -> Option<Box<[u8]>> {
+        let data = self.read(name)?;
+        let end = (range.end.min(data.len() as u64)) as usize;
+        let start = (range.start as usize).min(end);
+        Some(data[start..end].into())
+    }
+
+    // Like `read`, for callers which mustn't block. Archives which can't do better
+    // read before returning.
+    #[cfg(feature = "async")]
+    fn read_async(&self, name: &str) -> ReadFuture {
+        Box::pin(std::future::ready(self.read(name)))
+    }
+}
+
+#[cfg(feature = "async")]
+pub type ReadFuture = std::pin::Pin<Box<dyn std::future::Future<Output = Option<Box<[u8]>>> + Send>>;
+
+impl<A: ArchiveRead + ?Sized> ArchiveRead for Box<A> {
+    fn contains(&self, name: &str) -> bool {
+        (**self).contains(name)
+    }
+
+    fn read(&self, name: &str) -> Option<Box<[u8]>> {
+        (**self).read(name)
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> Option<Box<[u8]>> {
+        (**self).read_range(name, range)
+    }
+
+    #[cfg(feature = "async")]
+    fn read_async(&self, name: &str) -> ReadFuture {
+        (**self).read_async(name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        (**self).names()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
     file_name: String,
     range: Range<u64>,
+    compression: Compression,
+    encryption: Encryption,
+    // Of the data once decompressed.
+    size: u64,
+}
+
+impl ArchiveEntry {
+    pub fn name(&self) -> &str {
+        &self.file_name
+    }
+
+    // Where the data starts in the archive file.
+    pub fn offset(&self) -> u64 {
+        self.range.start
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // Of the data as it's in the archive file, which is smaller for compressed entries.
+    pub fn stored_size(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn encryption(&self) -> Encryption {
+        self.encryption
+    }
 }
 
 pub struct Archive {
-    reader: BufReader<File>,
+    storage: Storage,
     files: Vec<ArchiveEntry>,
-    case_sensitive: bool,
+    // Positions in `files` by their normalized name.
+    index: HashMap<String, usize>,
+    normalization: Normalization,
+    key: Option<ArchiveKey>,
+    // Of tables with encrypted entries, 0 for the ones from before they had one.
+    nonce: u64,
+    key_check: Option<[u8; 8]>,
 }
 
 impl Archive {
-    pub fn read(&mut self, name: &str) -> Option<Box<[u8]>> {
-        let eq = if self.case_sensitive {
-            str::eq
-        } else {
-            str::eq_ignore_ascii_case
-        };
+    fn find(&self, name: &str) -> Option<&ArchiveEntry> {
+        let index = self.index.get(self.normalization.apply(name).as_ref());
+        index.map(|&i| &self.files[i])
+    }
 
-        let entry = self.files
-            .iter()
-            .find(|f| eq(&f.file_name, name))?;
-        let Range { start, end } = entry.range.clone();
-        let len = (end - start) as usize;
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.files.iter().map(|f| f.file_name.as_str())
+    }
+
+    // In the order of the table.
+    pub fn entries(&self) -> impl Iterator<Item=&ArchiveEntry> {
+        self.files.iter()
+    }
 
-        self.reader.seek(SeekFrom::Start(start)).ok()?;
-        let mut buf = vec![0u8; len];
-        self.reader.read_exact(&mut buf).ok()?;
+    pub fn read(&self, name: &str) -> Option<Box<[u8]>> {
+        read_entry(&self.storage, self.find(name)?, self.key.as_ref(), self.nonce)
+    }
+
+    // Reads only `range` of the file, cut off at its end. Encrypted files are only
+    // decrypted in that range, but compressed ones are still decompressed whole.
+    pub fn read_range(&self, name: &str, range: Range<u64>) -> Option<Box<[u8]>> {
+        let mut reader = self.open(name)?.ok()?;
+        let end = range.end.min(reader.len());
+        let start = range.start.min(end);
+        reader.seek(SeekFrom::Start(start)).ok()?;
+        let mut buf = vec![0u8; (end - start) as usize];
+        reader.read_exact(&mut buf).ok()?;
         Some(buf.into_boxed_slice())
     }
+
+    // Reads on a thread of its own. Reads only say where they read from, so they can
+    // run while other entries are read.
+    #[cfg(feature = "async")]
+    pub fn read_async(&self, name: &str) -> impl std::future::Future<Output = Option<Box<[u8]>>> + Send + 'static {
+        let entry = self.find(name).cloned();
+        let storage = self.storage.try_clone();
+        let (key, nonce) = (self.key.clone(), self.nonce);
+        async move {
+            let (entry, storage) = (entry?, storage.ok()?);
+            blocking::unblock(move || read_entry(&storage, &entry, key.as_ref(), nonce)).await
+        }
+    }
+
+    // Reads the entry as it's needed, instead of all at once like `read`. Compressed
+    // entries can only be decompressed as a whole, so they're read when opened.
+    pub fn open(&self, name: &str) -> Option<std::io::Result<EntryReader<'_>>> {
+        Some(self.open_entry(self.find(name)?))
+    }
+
+    fn open_entry(&self, entry: &ArchiveEntry) -> std::io::Result<EntryReader<'_>> {
+        open_entry(&self.storage, entry, self.key.as_ref(), self.nonce)
+    }
+
+    // Reads the whole archive into memory, so reading files from it no longer waits
+    // for the disk. Meant for small archives, like the one with sound effects.
+    pub fn preload(&mut self) -> std::io::Result<()> {
+        if let Storage::File(file) = &self.storage {
+            let mut reader: &File = file;
+            let mut data = Vec::new();
+            reader.seek(SeekFrom::Start(0))?;
+            reader.read_to_end(&mut data)?;
+            self.storage = Storage::Memory(data.into());
+        }
+        Ok(())
+    }
+
+    pub fn is_preloaded(&self) -> bool {
+        matches!(self.storage, Storage::Memory(_))
+    }
+}
+
+fn open_entry<'a>(storage: &'a Storage, entry: &ArchiveEntry, key: Option<&'a ArchiveKey>, nonce: u64) -> std::io::Result<EntryReader<'a>> {
+    let mut reader = EntryReader::new(storage, entry.range.clone())?;
+    if entry.encryption != Encryption::None {
+        let key = key.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "entry is encrypted, but there's no key"))?;
+        key.check_encryption(entry.encryption)?;
+        reader = reader.decrypted(key, nonce, entry.range.start);
+    }
+    match entry.compression {
+        Compression::None => Ok(reader),
+        compression => compression.decompress(reader, entry.size).map(EntryReader::from_memory),
+    }
 }
 
+fn read_entry(storage: &Storage, entry: &ArchiveEntry, key: Option<&ArchiveKey>, nonce: u64) -> Option<Box<[u8]>> {
+    let mut reader = open_entry(storage, entry, key, nonce).ok()?;
+    let mut buf = vec![0u8; reader.len() as usize];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf.into_boxed_slice())
+}
+
+impl ArchiveRead for Archive {
+    fn contains(&self, name: &str) -> bool {
+        Archive::contains(self, name)
+    }
+
+    fn read(&self, name: &str) -> Option<Box<[u8]>> {
+        Archive::read(self, name)
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> Option<Box<[u8]>> {
+        Archive::read_range(self, name, range)
+    }
+
+    fn names(&self) -> Vec<String> {
+        Archive::names(self).map(str::to_string).collect()
+    }
+
+    #[cfg(feature = "async")]
+    fn read_async(&self, name: &str) -> ReadFuture {
+        Box::pin(Archive::read_async(self, name))
+    }
+}
+
+const ZIPIDENTIFICATION: &[u8; 2] = b"PK";
+
 const ENDTABLEIDENTIFICATION: &[u8; 10] = b"LEGARCHTBL";
+// Tables of archives with compressed or encrypted entries, which have how they're
+// stored and their size after the length.
+const COMPRESSEDTABLEIDENTIFICATION: &[u8; 10] = b"LEGARCHTBC";
+// Like the compressed ones, with the archive's nonce and the check of its AES key, if
+// it has one, after the count.
+const ENCRYPTEDTABLEIDENTIFICATION: &[u8; 10] = b"LEGARCHTBE";
+
+// Loads a `.legArchive` or a zip file, whichever the file is.
+pub fn load_any(path: impl AsRef<Path>, case_sensitive: bool) -> Result<Box<dyn ArchiveRead>, ArchiveError> {
+    load_any_with(path, Normalization::case_sensitive(case_sensitive))
+}
+
+pub fn load_any_with(path: impl AsRef<Path>, normalization: Normalization) -> Result<Box<dyn ArchiveRead>, ArchiveError> {
+    load_any_archive(path.as_ref(), normalization, None)
+}
+
+// Zip files can't have encrypted entries, so they're loaded without the key.
+pub fn load_any_encrypted(path: impl AsRef<Path>, normalization: Normalization, key: ArchiveKey) -> Result<Box<dyn ArchiveRead>, ArchiveError> {
+    load_any_archive(path.as_ref(), normalization, Some(key))
+}
+
+fn load_any_archive(path: &Path, normalization: Normalization, key: Option<ArchiveKey>) -> Result<Box<dyn ArchiveRead>, ArchiveError> {
+    let mut magic = [0u8; 2];
+    let is_zip = File::open(path)?.read_exact(&mut magic).is_ok() && &magic == ZIPIDENTIFICATION;
+    if is_zip {
+        Ok(Box::new(ZipArchive::load_with(path, normalization)?))
+    } else {
+        Ok(Box::new(load_archive(path, normalization, key)?))
+    }
+}
+
+pub fn load(path: impl AsRef<Path>, case_sensitive: bool) -> Result<Archive, ArchiveError> {
+    load_with(path, Normalization::case_sensitive(case_sensitive))
+}
 
-pub fn load(path: impl AsRef<Path>, case_sensitive: bool) -> Result<Archive, Box<dyn std::error::Error>> {
-    let file = File::open(path.as_ref())?;
-    let mut reader = BufReader::new(file);
+pub fn load_with(path: impl AsRef<Path>, normalization: Normalization) -> Result<Archive, ArchiveError> {
+    load_archive(path.as_ref(), normalization, None)
+}
+
+// For archives with encrypted entries. Entries which aren't encrypted are read as usual.
+// Fails with `WrongKey` if the archive was encrypted with another AES key.
+pub fn load_encrypted(path: impl AsRef<Path>, normalization: Normalization, key: ArchiveKey) -> Result<Archive, ArchiveError> {
+    load_archive(path.as_ref(), normalization, Some(key))
+}
+
+fn load_archive(path: &Path, normalization: Normalization, key: Option<ArchiveKey>) -> Result<Archive, ArchiveError> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    // The position of the table, which starts with its identification and count.
+    if file_len < 8 + ENDTABLEIDENTIFICATION.len() as u64 + 4 {
+        return Err(ArchiveError::BadMagic);
+    }
+    let mut reader = BufReader::new(&file);
     reader.seek(SeekFrom::End(-8))?;
 
     let start_pos = {
         let mut x = [0u8; 8];
         reader.read_exact(&mut x)?;
-        i64::from_ne_bytes(x)
+        i64::from_le_bytes(x)
     };
+    if start_pos < 0 || start_pos as u64 > file_len - 8 {
+        return Err(ArchiveError::BadMagic);
+    }
 
     reader.seek(SeekFrom::Start(start_pos as u64))?;
 
     let mut header = [0u8; 10];
     reader.read_exact(&mut header)?;
-    if &header != ENDTABLEIDENTIFICATION {
-        panic!();
-    }
+    let (compressed, encrypted) = match &header {
+        ENDTABLEIDENTIFICATION => (false, false),
+        COMPRESSEDTABLEIDENTIFICATION => (true, false),
+        ENCRYPTEDTABLEIDENTIFICATION => (true, true),
+        _ => return Err(ArchiveError::BadMagic),
+    };
     let total_files = {
         let mut x = [0u8; 4];
         reader.read_exact(&mut x)?;
         i32::from_le_bytes(x)
     };
+    if total_files < 0 {
+        return Err(ArchiveError::TruncatedTable);
+    }
+    let (nonce, key_check) = if encrypted {
+        let nonce = {
+            let mut x = [0u8; 8];
+            reader.read_exact(&mut x)?;
+            u64::from_le_bytes(x)
+        };
+        // Whether there is a check, and the check.
+        let mut x = [0u8; 9];
+        reader.read_exact(&mut x)?;
+        let mut check = [0u8; 8];
+        check.copy_from_slice(&x[1..]);
+        (nonce, (x[0] != 0).then_some(check))
+    } else {
+        (0, None)
+    };
+    let expected_check = key.as_ref().and_then(|key| key.check(nonce));
+    if key_check.is_some() && expected_check.is_some() && key_check != expected_check {
+        return Err(ArchiveError::WrongKey);
+    }
 
-    let mut files = Vec::with_capacity(total_files as usize);
+    // The count isn't trusted for allocating, entries take at least 13 bytes each.
+    let mut files = Vec::with_capacity((total_files as usize).min((file_len / 13) as usize));
     let mut file_name = Vec::new();
     for _ in 0..total_files {
         let read = reader.read_until(b'\0', &mut file_name)?;
-        let name = std::str::from_utf8(&file_name[..read - 1])?;
+        if read == 0 || file_name[read - 1] != b'\0' {
+            return Err(ArchiveError::TruncatedTable);
+        }
+        let name = std::str::from_utf8(&file_name[..read - 1])
+            .map_err(|_| ArchiveError::NonUtf8Name(String::from_utf8_lossy(&file_name[..read - 1]).into_owned()))?;
 
         let position = {
             let mut x = [0u8; 8];
@@ -80,17 +374,489 @@ pub fn load(path: impl AsRef<Path>, case_sensitive: bool) -> Result<Archive, Box
             i32::from_le_bytes(x)
         } as u64;
 
+        let (compression, encryption, size) = if compressed {
+            let mut x = [0u8; 5];
+            reader.read_exact(&mut x)?;
+            // Compression in the low four bits, encryption in the high ones.
+            let compression = Compression::from_id(x[0] & 0x0f);
+            let encryption = Encryption::from_id(x[0] >> 4);
+            let (compression, encryption) = compression.zip(encryption).ok_or_else(|| ArchiveError::UnknownCompression {
+                name: name.to_string(),
+                method: x[0],
+            })?;
+            (compression, encryption, u32::from_le_bytes([x[1], x[2], x[3], x[4]]) as u64)
+        } else {
+            (Compression::None, Encryption::None, length)
+        };
+
+        let end = position.checked_add(length).filter(|&end| end <= file_len);
+        let end = end.ok_or_else(|| ArchiveError::OffsetOutOfRange {
+            name: name.to_string(),
+            end: position.saturating_add(length),
+            file_len,
+        })?;
+
         files.push(ArchiveEntry {
             file_name: name.to_string(),
-            range: position..position + length,
+            range: position..end,
+            compression,
+            encryption,
+            size,
         });
 
         file_name.clear();
     }
 
+    // The first entry wins if a name is in the table twice.
+    let mut index = HashMap::with_capacity(files.len());
+    for (i, file) in files.iter().enumerate() {
+        index.entry(normalization.apply(&file.file_name).into_owned()).or_insert(i);
+    }
+
     Ok(Archive {
-        reader,
+        storage: Storage::File(file),
         files,
-        case_sensitive,
+        index,
+        normalization,
+        key,
+        nonce,
+        key_check,
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+
+    use super::{
+        ArchiveError, ArchiveKey, ArchiveRead, ArchiveSet, Compression, Encryption, Normalization, Writer, edit, load,
+        load_any, load_any_encrypted, load_encrypted, load_with, pack, pack_encrypted, pack_with,
+    };
+
+    // A scratch directory which is removed again when dropped, even if the test fails.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let directory = std::env::temp_dir().join(format!("madenon-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&directory);
+            std::fs::create_dir_all(&directory).unwrap();
+            Scratch(directory)
+        }
+
+        fn path(&self, path: &str) -> PathBuf {
+            self.0.join(path)
+        }
+
+        fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+            let file = self.path(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, contents).unwrap();
+            file
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn packs_directories() {
+        let scratch = Scratch::new("pack");
+        let file = scratch.path("pack.legArchive");
+        scratch.write("game/main.scr", "text hi");
+        scratch.write("game/sound/Se.aac", [1, 2, 3]);
+        scratch.write("game/empty", []);
+        pack(scratch.path("game"), &file).unwrap();
+        let archive = load(&file, false).unwrap();
+
+        assert_eq!(archive.names().collect::<Vec<_>>(), ["empty", "main.scr", "sound/Se.aac"]);
+        assert_eq!(&*archive.read("main.scr").unwrap(), b"text hi");
+        assert_eq!(&*archive.read("SOUND/se.aac").unwrap(), [1, 2, 3]);
+        assert_eq!(&*archive.read("empty").unwrap(), []);
+        assert!(archive.read("missing").is_none());
+        let entries: Vec<_> = archive.entries().map(|e| (e.name(), e.offset(), e.size())).collect();
+        assert_eq!(entries, [("empty", 0, 0), ("main.scr", 0, 7), ("sound/Se.aac", 7, 3)]);
+        assert!(archive.contains("Main.scr"));
+    }
+
+    #[test]
+    fn streams_entries() {
+        let scratch = Scratch::new("stream");
+        let file = scratch.path("stream.legArchive");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("a", b"first").unwrap();
+        writer.add("b", b"0123456789").unwrap();
+        writer.add("c", b"last").unwrap();
+        writer.finish().unwrap();
+        let archive = load(&file, true).unwrap();
+
+        let mut entry = archive.open("b").unwrap().unwrap();
+        let mut buf = [0u8; 4];
+        entry.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"0123");
+        assert_eq!(entry.seek(SeekFrom::End(-3)).unwrap(), 7);
+        let mut rest = vec![];
+        entry.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"789");
+        entry.seek(SeekFrom::Current(-5)).unwrap();
+        entry.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"5678");
+        assert!(entry.seek(SeekFrom::Current(-10)).is_err());
+        assert!(archive.open("B").is_none());
+        assert_eq!(&*archive.read_range("b", 2..5).unwrap(), b"234");
+        assert_eq!(&*archive.read_range("b", 8..100).unwrap(), b"89");
+        assert_eq!(&*archive.read_range("b", 20..30).unwrap(), b"");
+        let boxed: Box<dyn ArchiveRead> = Box::new(archive);
+        assert_eq!(&*boxed.read_range("c", 1..3).unwrap(), b"as");
+        assert!(boxed.read_range("d", 0..1).is_none());
+    }
+
+    #[test]
+    fn rejects_broken_archives() {
+        let scratch = Scratch::new("broken");
+        let file = scratch.path("broken.legArchive");
+        let mut writer = Writer::new(vec![]);
+        writer.add("name", b"data").unwrap();
+        let archive = writer.finish().unwrap();
+        let check = |data: &[u8]| {
+            std::fs::write(&file, data).unwrap();
+            load(&file, false).err()
+        };
+        let table = archive.len() - 8 - 31;
+        let with_trailer = |data: &[u8]| [data, &(table as i64).to_le_bytes()].concat();
+        let mut out_of_range = archive[..archive.len() - 8].to_vec();
+        out_of_range[table + 27] = 200;
+        let mut non_utf8 = archive[..archive.len() - 8].to_vec();
+        non_utf8[table + 14] = 0xff;
+        let results = [
+            check(&archive),
+            check(b"no archive"),
+            check(&[b"x".repeat(30).as_slice(), &[0xff; 8]].concat()),
+            check(&with_trailer(&archive[..table + 20])),
+            check(&with_trailer(&out_of_range)),
+            check(&with_trailer(&non_utf8)),
+        ];
+
+        assert!(results[0].is_none());
+        assert!(matches!(results[1], Some(ArchiveError::BadMagic)));
+        assert!(matches!(results[2], Some(ArchiveError::BadMagic)));
+        assert!(matches!(results[3], Some(ArchiveError::TruncatedTable)));
+        assert!(matches!(results[4], Some(ArchiveError::OffsetOutOfRange { end: 200, .. })));
+        assert!(matches!(results[5], Some(ArchiveError::NonUtf8Name(_))));
+    }
+
+    #[test]
+    fn reads_from_the_first_source_with_a_file() {
+        let scratch = Scratch::new("set");
+        let file = scratch.path("set.legArchive");
+        scratch.write("game/a.png", "game a");
+        scratch.write("game/b.png", "game b");
+        scratch.write("mods/sound/a.png", "mod a");
+        scratch.write("mods/a.png", "mod a");
+        pack(scratch.path("game"), &file).unwrap();
+        let set = ArchiveSet::new().with_directory(scratch.path("mods")).with_archive(load(&file, false).unwrap());
+        let read = |name| set.read(name).map(|data| String::from_utf8(data.into_vec()).unwrap());
+        let (a, b, c, names) = (read("a.png"), read("B.png"), read("c.png"), set.names());
+        let ranges = (set.read_range("a.png", 4..10), set.read_range("b.png", 0..4));
+
+        assert_eq!(a.as_deref(), Some("mod a"));
+        assert_eq!(b.as_deref(), Some("game b"));
+        assert_eq!(c, None);
+        assert_eq!(names, ["a.png", "b.png", "sound/a.png"]);
+        assert_eq!(ranges.0.as_deref(), Some(&b"a"[..]));
+        assert_eq!(ranges.1.as_deref(), Some(&b"game"[..]));
+    }
+
+    #[test]
+    fn reads_zip_files() {
+        let scratch = Scratch::new("zip");
+        let file = scratch.path("assets.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&file).unwrap());
+        let stored = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.add_directory("sound/", stored).unwrap();
+        zip.start_file("sound/Se.aac", stored).unwrap();
+        zip.write_all(&[1, 2, 3]).unwrap();
+        zip.start_file("main.scr", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&b"text hi\n".repeat(100)).unwrap();
+        zip.finish().unwrap();
+        let archive = load_any(&file, false).unwrap();
+        let set = ArchiveSet::from(load_any(&file, false).unwrap());
+        let concurrent: Vec<_> = std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4).map(|_| scope.spawn(|| archive.read("main.scr").map(|data| data.len()))).collect();
+            readers.into_iter().map(|reader| reader.join().unwrap()).collect()
+        });
+
+        assert_eq!(&*archive.read("SOUND/se.aac").unwrap(), [1, 2, 3]);
+        assert_eq!(archive.read("main.scr").unwrap().len(), 800);
+        assert_eq!(concurrent, [Some(800); 4]);
+        assert!(archive.read("sound/").is_none());
+        assert!(!archive.contains("missing"));
+        assert_eq!(set.names(), ["main.scr", "sound/Se.aac"]);
+    }
+
+    #[test]
+    fn compresses_entries() {
+        let scratch = Scratch::new("compressed");
+        let file = scratch.path("compressed.legArchive");
+        let script = b"text hello\n".repeat(100);
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("plain", b"plain").unwrap();
+        writer.set_compression(Compression::Zlib);
+        writer.add("zlib.scr", &script).unwrap();
+        writer.add("tiny", b"x").unwrap();
+        writer.set_compression(Compression::Zstd);
+        writer.add("zstd.scr", &script).unwrap();
+        writer.finish().unwrap();
+        let archive = load(&file, false).unwrap();
+
+        let entries: Vec<_> = archive.entries().map(|e| (e.name(), e.compression(), e.size())).collect();
+        assert_eq!(entries, [
+            ("plain", Compression::None, 5),
+            ("zlib.scr", Compression::Zlib, 1100),
+            ("tiny", Compression::None, 1),
+            ("zstd.scr", Compression::Zstd, 1100),
+        ]);
+        assert!(archive.entries().all(|e| e.stored_size() < 100));
+        assert_eq!(&*archive.read("plain").unwrap(), b"plain");
+        assert_eq!(&*archive.read("zlib.scr").unwrap(), &script[..]);
+        assert_eq!(&*archive.read("zstd.scr").unwrap(), &script[..]);
+        let mut entry = archive.open("zstd.scr").unwrap().unwrap();
+        entry.seek(SeekFrom::Start(1095)).unwrap();
+        let mut rest = String::new();
+        entry.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "ello\n");
+    }
+
+    #[test]
+    fn extracts_entries() {
+        let scratch = Scratch::new("extract");
+        let directory = scratch.path("out");
+        let file = scratch.path("extract.legArchive");
+        scratch.write("packed/main.scr", "text hi");
+        scratch.write("packed/sound/a.AAC", [1, 2]);
+        scratch.write("packed/sound/b.ogg", [3]);
+        pack_with(scratch.path("packed"), &file, Compression::Zlib).unwrap();
+        let archive = load(&file, false).unwrap();
+        let mut progress = vec![];
+        let all = archive.extract_all(directory.join("all"), |e, done, total| progress.push((e.name().to_string(), done, total)));
+        let sounds = archive.extract_matching(directory.join("sounds"), "sound/*.aac", |_, _, _| ());
+        let top = archive.extract_matching(directory.join("top"), "*", |_, _, _| ());
+        let read = |path: &[&str]| std::fs::read(path.iter().fold(directory.clone(), |dir, part| dir.join(part))).ok();
+        let (main, sound, only_aac, only_ogg) = (
+            read(&["all", "main.scr"]),
+            read(&["all", "sound", "b.ogg"]),
+            read(&["sounds", "sound", "a.AAC"]),
+            read(&["sounds", "sound", "b.ogg"]),
+        );
+
+        assert_eq!(all.unwrap(), 3);
+        assert_eq!(progress[2], ("sound/b.ogg".to_string(), 3, 3));
+        assert_eq!(main.as_deref(), Some(&b"text hi"[..]));
+        assert_eq!(sound, Some(vec![3]));
+        assert_eq!(sounds.unwrap(), 1);
+        assert_eq!(only_aac, Some(vec![1, 2]));
+        assert_eq!(only_ogg, None);
+        assert_eq!(top.unwrap(), 1);
+    }
+
+    #[test]
+    fn normalizes_names() {
+        let scratch = Scratch::new("normalize");
+        let file = scratch.path("normalize.legArchive");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("CG\\Café.png", b"cafe").unwrap();
+        writer.add("bgm/ＢＧＭ01.ogg", b"bgm").unwrap();
+        writer.finish().unwrap();
+        let normalized = load_with(&file, Normalization::ALL).unwrap();
+        let exact = load_with(&file, Normalization::NONE).unwrap();
+
+        // The accent written as a combining character.
+        assert!(normalized.contains("cg/cafe\u{301}.png"));
+        assert!(normalized.contains("CG/CAFÉ.PNG"));
+        assert!(normalized.contains("bgm/bgm01.ogg"));
+        assert!(!exact.contains("cg/café.png"));
+        assert!(exact.contains("CG\\Café.png"));
+        assert_eq!(Normalization::NONE.apply("A\\b"), "A\\b");
+    }
+
+    #[test]
+    fn normalizes_names_in_directories() {
+        let scratch = Scratch::new("normalize-directory");
+        scratch.write("mods/CG/Café.png", "cafe");
+        let normalized = ArchiveSet::new().with_normalized_directory(scratch.path("mods"), Normalization::ALL);
+        let exact = ArchiveSet::new().with_directory(scratch.path("mods"));
+
+        assert_eq!(normalized.read("cg\\cafe\u{301}.PNG").as_deref(), Some(&b"cafe"[..]));
+        assert!(normalized.contains("CG/Café.png"));
+        assert!(!normalized.contains("cg/tea.png"));
+        assert!(!exact.contains("cg/café.png"));
+        assert!(exact.contains("CG/Café.png"));
+    }
+
+    #[test]
+    fn edits_archives_in_place() {
+        let scratch = Scratch::new("edit");
+        let file = scratch.path("edit.legArchive");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("a.scr", b"text original").unwrap();
+        writer.add("b.scr", b"text b").unwrap();
+        writer.add("c.scr", b"text c").unwrap();
+        writer.finish().unwrap();
+
+        let mut editor = edit(&file).unwrap();
+        editor.add("a.scr", b"text translated").unwrap();
+        editor.add("d.scr", b"text d").unwrap();
+        assert!(editor.remove("c.scr"));
+        assert!(!editor.remove("missing.scr"));
+        editor.finish().unwrap();
+        let archive = load(&file, false).unwrap();
+
+        assert_eq!(archive.names().collect::<Vec<_>>(), ["b.scr", "a.scr", "d.scr"]);
+        assert_eq!(&*archive.read("a.scr").unwrap(), b"text translated");
+        assert_eq!(&*archive.read("b.scr").unwrap(), b"text b");
+        assert_eq!(&*archive.read("d.scr").unwrap(), b"text d");
+        assert!(archive.read("c.scr").is_none());
+    }
+
+    #[test]
+    fn undoes_unfinished_edits() {
+        let scratch = Scratch::new("edit-undo");
+        let file = scratch.path("edit.legArchive");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("a.scr", b"text a").unwrap();
+        writer.finish().unwrap();
+        let original = std::fs::read(&file).unwrap();
+
+        let mut editor = edit(&file).unwrap();
+        editor.add("a.scr", b"text translated").unwrap();
+        editor.add("b.scr", b"text b").unwrap();
+        drop(editor);
+        let dropped = std::fs::read(&file).unwrap();
+        let mut editor = edit(&file).unwrap();
+        editor.add("b.scr", b"text b").unwrap();
+        let failed = editor.add_file("c.scr", scratch.path("missing.scr")).is_err();
+        let undone = editor.add("d.scr", b"text d").is_err();
+        let finished = editor.finish().is_err();
+        let archive = load(&file, false).unwrap();
+
+        assert_eq!(dropped, original);
+        assert!(failed && undone && finished);
+        assert_eq!(std::fs::read(&file).unwrap(), original);
+        assert_eq!(&*archive.read("a.scr").unwrap(), b"text a");
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn reads_asynchronously() {
+        let scratch = Scratch::new("async");
+        let file = scratch.path("async.legArchive");
+        scratch.write("loose/a.png", "loose a");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("a.png", b"packed a").unwrap();
+        writer.set_compression(Compression::Zlib);
+        writer.add("b.png", &b"packed b".repeat(10)).unwrap();
+        writer.finish().unwrap();
+        let set = ArchiveSet::new().with_directory(scratch.path("loose")).with_archive(load(&file, false).unwrap());
+        let reads = vec![set.read_async("a.png"), set.read_async("B.png"), set.read_async("c.png")];
+        let read: Vec<_> = reads.into_iter().map(futures_lite::future::block_on).collect();
+
+        assert_eq!(read[0].as_deref(), Some(&b"loose a"[..]));
+        assert_eq!(read[1].as_deref(), Some(&b"packed b".repeat(10)[..]));
+        assert_eq!(read[2], None);
+    }
+
+    #[test]
+    fn preloads_archives() {
+        let scratch = Scratch::new("preload");
+        let file = scratch.path("preload.legArchive");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("se.aac", b"sound").unwrap();
+        writer.set_compression(Compression::Zstd);
+        writer.add("se.txt", &b"text".repeat(10)).unwrap();
+        writer.finish().unwrap();
+        let mut archive = load(&file, false).unwrap();
+        archive.preload().unwrap();
+
+        assert!(archive.is_preloaded());
+        assert_eq!(&*archive.read("se.aac").unwrap(), b"sound");
+        assert_eq!(&*archive.read_range("se.aac", 1..3).unwrap(), b"ou");
+        assert_eq!(&*archive.read("se.txt").unwrap(), &b"text".repeat(10)[..]);
+    }
+
+    #[test]
+    fn encrypts_entries() {
+        let scratch = Scratch::new("encrypted");
+        let file = scratch.path("encrypted.legArchive");
+        let script = b"text secret\n".repeat(10);
+        let aes = ArchiveKey::Aes128Ctr(*b"0123456789abcdef");
+        let mut writer = Writer::new(std::fs::File::create(&file).unwrap());
+        writer.add("plain.scr", b"text plain").unwrap();
+        writer.set_key(Some(aes.clone()));
+        writer.add("a.scr", &script).unwrap();
+        writer.set_compression(Compression::Zlib);
+        writer.add("b.scr", &script).unwrap();
+        writer.finish().unwrap();
+        let raw = std::fs::read(&file).unwrap();
+        let repacked = scratch.path("repacked.legArchive");
+        let mut writer = Writer::new(std::fs::File::create(&repacked).unwrap());
+        writer.add("plain.scr", b"text plain").unwrap();
+        writer.set_key(Some(aes.clone()));
+        writer.add("a.scr", &script).unwrap();
+        writer.finish().unwrap();
+        let repacked = std::fs::read(&repacked).unwrap();
+        let encrypted = load_encrypted(&file, Normalization::NONE, aes.clone()).unwrap();
+        let any = load_any_encrypted(&file, Normalization::NONE, aes.clone()).unwrap();
+        let wrong = load_encrypted(&file, Normalization::NONE, ArchiveKey::Xor(vec![1])).unwrap();
+        let wrong_aes = load_encrypted(&file, Normalization::NONE, ArchiveKey::Aes128Ctr(*b"fedcba9876543210"));
+        let without_key = load(&file, false).unwrap();
+
+        let mut editor = edit(&file).unwrap();
+        editor.set_key(Some(ArchiveKey::Xor(vec![0x55, 0xaa])));
+        editor.add("c.scr", &script).unwrap();
+        editor.finish().unwrap();
+        let xor = load_encrypted(&file, Normalization::NONE, ArchiveKey::Xor(vec![0x55, 0xaa])).unwrap();
+        let edited = load_encrypted(&file, Normalization::NONE, aes.clone()).unwrap();
+        let mut editor = edit(&file).unwrap();
+        editor.set_key(Some(ArchiveKey::Aes128Ctr(*b"fedcba9876543210")));
+        let other_aes = editor.add("d.scr", &script);
+        scratch.write("packed/e.scr", &script);
+        let packed = scratch.path("packed.legArchive");
+        pack_encrypted(scratch.path("packed"), &packed, Compression::Zstd, aes.clone()).unwrap();
+        let packed = load_encrypted(&packed, Normalization::NONE, aes.clone()).unwrap();
+
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+        // The same key and data, at the same position, but another archive.
+        assert_ne!(raw[10..130], repacked[10..130]);
+        let entries: Vec<_> = encrypted.entries().map(|e| (e.name(), e.encryption())).collect();
+        assert_eq!(entries, [("plain.scr", Encryption::None), ("a.scr", Encryption::Aes128Ctr), ("b.scr", Encryption::Aes128Ctr)]);
+        assert_eq!(&*encrypted.read("a.scr").unwrap(), &script[..]);
+        assert_eq!(&*encrypted.read("b.scr").unwrap(), &script[..]);
+        assert_eq!(&*encrypted.read_range("a.scr", 5..11).unwrap(), b"secret");
+        assert_eq!(&*xor.read_range("c.scr", 17..23).unwrap(), b"secret");
+        assert_eq!(&*without_key.read("plain.scr").unwrap(), b"text plain");
+        assert!(without_key.read("a.scr").is_none());
+        assert_eq!(&*any.read("a.scr").unwrap(), &script[..]);
+        assert!(wrong.read("a.scr").is_none());
+        assert!(matches!(wrong_aes, Err(ArchiveError::WrongKey)));
+        assert_eq!(&*xor.read("c.scr").unwrap(), &script[..]);
+        assert_eq!(&*xor.read("plain.scr").unwrap(), b"text plain");
+        assert_eq!(&*edited.read("a.scr").unwrap(), &script[..]);
+        assert!(other_aes.is_err());
+        assert_eq!(packed.entries().next().map(|e| e.encryption()), Some(Encryption::Aes128Ctr));
+        assert_eq!(&*packed.read("e.scr").unwrap(), &script[..]);
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert!(matches!(ArchiveKey::parse("aes:000102030405060708090a0b0c0d0e0F"), Some(ArchiveKey::Aes128Ctr(key)) if key[15] == 15));
+        assert!(matches!(ArchiveKey::parse("xor:55aa"), Some(ArchiveKey::Xor(key)) if key == [0x55, 0xaa]));
+        assert!(ArchiveKey::parse("aes:55aa").is_none());
+        assert!(ArchiveKey::parse("xor:").is_none());
+        assert!(ArchiveKey::parse("55aa").is_none());
+        assert!(ArchiveKey::parse("des:55aa").is_none());
+    }
+}
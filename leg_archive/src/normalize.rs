@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    None,
+    // Composes characters, so `é` matches whether it was written as one or two.
+    Nfc,
+    // Also folds compatibility characters, like full-width letters into ASCII ones.
+    Nfkc,
+}
+
+// How names are made comparable before looking them up. Applied to both the names
+// in the archive and the ones asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Normalization {
+    // Treats `\` like `/`.
+    pub separators: bool,
+    pub case: bool,
+    pub unicode: UnicodeForm,
+}
+
+impl Normalization {
+    // Names have to match exactly.
+    pub const NONE: Normalization = Normalization { separators: false, case: false, unicode: UnicodeForm::None };
+    pub const ALL: Normalization = Normalization { separators: true, case: true, unicode: UnicodeForm::Nfkc };
+
+    pub(crate) fn case_sensitive(case_sensitive: bool) -> Self {
+        Normalization { case: !case_sensitive, ..Normalization::NONE }
+    }
+
+    pub fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let mut name = Cow::Borrowed(name);
+        if self.separators && name.contains('\\') {
+            name = Cow::Owned(name.replace('\\', "/"));
+        }
+        // Only non-ASCII text can change.
+        if !name.is_ascii() {
+            match self.unicode {
+                UnicodeForm::None => (),
+                UnicodeForm::Nfc => name = Cow::Owned(name.nfc().collect()),
+                UnicodeForm::Nfkc => name = Cow::Owned(name.nfkc().collect()),
+            }
+        }
+        if self.case && name.chars().any(char::is_uppercase) {
+            name = Cow::Owned(name.to_lowercase());
+        }
+        name
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::writer::collect_files;
+use crate::{Archive, ArchiveRead, Normalization, ZipArchive};
+
+enum Source {
+    Archive(Box<dyn ArchiveRead>),
+    // Loose files, named like archive entries by their path relative to the directory.
+    Directory(LooseFiles),
+}
+
+struct LooseFiles {
+    directory: PathBuf,
+    normalization: Normalization,
+    // Names of the files by their normalized name, listed when the directory is added
+    // like archives index their entries. Empty when names aren't normalized.
+    index: HashMap<String, String>,
+}
+
+impl LooseFiles {
+    fn new(directory: PathBuf, normalization: Normalization) -> Self {
+        let mut index = HashMap::new();
+        if normalization != Normalization::NONE {
+            let mut names = vec![];
+            let _ = collect_files(&directory, "", &mut names);
+            for name in names {
+                index.entry(normalization.apply(&name).into_owned()).or_insert(name);
+            }
+        }
+        Self { directory, normalization, index }
+    }
+
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        let exact = self.directory.join(name);
+        if exact.is_file() {
+            return Some(exact);
+        }
+        let name = self.index.get(self.normalization.apply(name).as_ref())?;
+        Some(self.directory.join(name)).filter(|path| path.is_file())
+    }
+}
+
+// Several archives and directories seen as one. Every file is read from the first
+// source which has it, so mods mounted first override the files of the game.
+#[derive(Default)]
+pub struct ArchiveSet {
+    sources: Vec<Source>,
+}
+
+impl ArchiveSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_archive(mut self, archive: impl ArchiveRead + 'static) -> Self {
+        self.sources.push(Source::Archive(Box::new(archive)));
+        self
+    }
+
+    // Names have to match the files exactly, see `with_normalized_directory`.
+    pub fn with_directory(self, directory: impl Into<PathBuf>) -> Self {
+        self.with_normalized_directory(directory, Normalization::NONE)
+    }
+
+    // Looks files up like archives loaded with `normalization` do. The files are listed
+    // once, so ones added later only match by their exact name.
+    pub fn with_normalized_directory(mut self, directory: impl Into<PathBuf>, normalization: Normalization) -> Self {
+        self.sources.push(Source::Directory(LooseFiles::new(directory.into(), normalization)));
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sources.iter().any(|source| match source {
+            Source::Archive(archive) => archive.contains(name),
+            Source::Directory(files) => files.path(name).is_some(),
+        })
+    }
+
+    pub fn read(&self, name: &str) -> Option<Box<[u8]>> {
+        self.sources.iter().find_map(|source| match source {
+            Source::Archive(archive) => archive.read(name),
+            Source::Directory(files) => std::fs::read(files.path(name)?).ok().map(Vec::into_boxed_slice),
+        })
+    }
+
+    pub fn read_range(&self, name: &str, range: Range<u64>) -> Option<Box<[u8]>> {
+        self.sources.iter().find_map(|source| match source {
+            Source::Archive(archive) => archive.read_range(name, range.clone()),
+            Source::Directory(files) => read_file_range(&files.path(name)?, range.clone()).ok(),
+        })
+    }
+
+    #[cfg(feature = "async")]
+    pub fn read_async(&self, name: &str) -> crate::ReadFuture {
+        for source in &self.sources {
+            match source {
+                Source::Archive(archive) if archive.contains(name) => return archive.read_async(name),
+                Source::Directory(files) => {
+                    if let Some(path) = files.path(name) {
+                        return Box::pin(blocking::unblock(move || std::fs::read(path).ok().map(Vec::into_boxed_slice)));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Box::pin(std::future::ready(None))
+    }
+
+    // Names of the files in every source, sorted and without duplicates. Directories
+    // which can't be read have no files.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for source in &self.sources {
+            match source {
+                Source::Archive(archive) => names.extend(archive.names()),
+                Source::Directory(files) => {
+                    let _ = collect_files(&files.directory, "", &mut names);
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+fn read_file_range(path: &Path, range: Range<u64>) -> io::Result<Box<[u8]>> {
+    let mut file = File::open(path)?;
+    let start = range.start.min(range.end);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![];
+    file.take(range.end - start).read_to_end(&mut buf)?;
+    Ok(buf.into_boxed_slice())
+}
+
+impl From<Archive> for ArchiveSet {
+    fn from(archive: Archive) -> Self {
+        Self::new().with_archive(archive)
+    }
+}
+
+impl From<ZipArchive> for ArchiveSet {
+    fn from(archive: ZipArchive) -> Self {
+        Self::new().with_archive(archive)
+    }
+}
+
+impl From<Box<dyn ArchiveRead>> for ArchiveSet {
+    fn from(archive: Box<dyn ArchiveRead>) -> Self {
+        Self::new().with_archive(archive)
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::crypt::new_nonce;
+use crate::{ArchiveEntry, ArchiveKey, COMPRESSEDTABLEIDENTIFICATION, Compression, ENCRYPTEDTABLEIDENTIFICATION, ENDTABLEIDENTIFICATION, Encryption};
+
+pub(crate) struct TableEntry {
+    name: String,
+    position: u64,
+    length: u32,
+    compression: Compression,
+    encryption: Encryption,
+    size: u32,
+}
+
+impl From<&ArchiveEntry> for TableEntry {
+    fn from(entry: &ArchiveEntry) -> Self {
+        TableEntry {
+            name: entry.file_name.clone(),
+            position: entry.range.start,
+            length: entry.stored_size() as u32,
+            compression: entry.compression,
+            encryption: entry.encryption,
+            size: entry.size as u32,
+        }
+    }
+}
+
+// Writes the files one after another, followed by the table of their names and
+// positions, and finally the position of the table. Adding a name again replaces the
+// file in the table.
+pub struct Writer<W: Write> {
+    writer: W,
+    position: u64,
+    files: Vec<TableEntry>,
+    compression: Compression,
+    key: Option<ArchiveKey>,
+    nonce: u64,
+    // Of the AES key the files are encrypted with, there can only be one.
+    key_check: Option<[u8; 8]>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Self::resume(writer, 0, Vec::new(), new_nonce(), None)
+    }
+
+    // Continues writing an archive whose data ends at `position`, with the table for
+    // the files in it not written yet.
+    pub(crate) fn resume(writer: W, position: u64, files: Vec<TableEntry>, nonce: u64, key_check: Option<[u8; 8]>) -> Self {
+        Self {
+            writer,
+            position,
+            files,
+            compression: Compression::None,
+            key: None,
+            nonce,
+            key_check,
+        }
+    }
+
+    // Compresses the files added from now on. Files which don't get smaller are stored
+    // as they are. Archives with compressed files can't be read by older readers.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    // Encrypts the files added from now on, or stops encrypting them.
+    pub fn set_key(&mut self, key: Option<ArchiveKey>) {
+        self.key = key;
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        if name.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} contains a nul byte", name)));
+        }
+        // The table stores lengths as 32 bit signed integers.
+        if data.len() > i32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is too large", name)));
+        }
+        let key_check = self.key.as_ref().and_then(|key| key.check(self.nonce));
+        if key_check.is_some() && self.key_check.is_some() && key_check != self.key_check {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the archive is encrypted with another AES key"));
+        }
+
+        let compressed = match self.compression {
+            Compression::None => None,
+            compression => Some(compression.compress(data)?).filter(|compressed| compressed.len() < data.len()),
+        };
+        let (compression, stored) = match &compressed {
+            Some(compressed) => (self.compression, compressed.as_slice()),
+            None => (Compression::None, data),
+        };
+        let encrypted = self.key.as_ref().map(|key| {
+            let mut encrypted = stored.to_vec();
+            key.apply(&mut encrypted, self.nonce, self.position, 0);
+            encrypted
+        });
+        let stored = encrypted.as_deref().unwrap_or(stored);
+
+        self.writer.write_all(stored)?;
+        self.remove(name);
+        self.files.push(TableEntry {
+            name: name.to_string(),
+            position: self.position,
+            length: stored.len() as u32,
+            compression,
+            encryption: self.key.as_ref().map_or(Encryption::None, ArchiveKey::encryption),
+            size: data.len() as u32,
+        });
+        self.position += stored.len() as u64;
+        self.key_check = self.key_check.or(key_check);
+        Ok(())
+    }
+
+    pub fn add_file(&mut self, name: &str, path: impl AsRef<Path>) -> io::Result<()> {
+        let data = std::fs::read(path)?;
+        self.add(name, &data)
+    }
+
+    // Leaves the file out of the table. Its data stays where it was written.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.files.len();
+        self.files.retain(|file| file.name != name);
+        self.files.len() != len
+    }
+
+    // The underlying writer, without the table. What was added isn't in any table.
+    pub(crate) fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // Writes the table and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let table = self.position;
+        // Archives without compressed files stay readable by every reader.
+        let encrypted = self.files.iter().any(|file| file.encryption != Encryption::None);
+        let compressed = encrypted || self.files.iter().any(|file| file.compression != Compression::None);
+        if encrypted {
+            self.writer.write_all(ENCRYPTEDTABLEIDENTIFICATION)?;
+        } else if compressed {
+            self.writer.write_all(COMPRESSEDTABLEIDENTIFICATION)?;
+        } else {
+            self.writer.write_all(ENDTABLEIDENTIFICATION)?;
+        }
+        self.writer.write_all(&(self.files.len() as i32).to_le_bytes())?;
+        if encrypted {
+            self.writer.write_all(&self.nonce.to_le_bytes())?;
+            self.writer.write_all(&[self.key_check.is_some() as u8])?;
+            self.writer.write_all(&self.key_check.unwrap_or_default())?;
+        }
+        for file in &self.files {
+            self.writer.write_all(file.name.as_bytes())?;
+            self.writer.write_all(b"\0")?;
+            self.writer.write_all(&(file.position as i64).to_le_bytes())?;
+            self.writer.write_all(&(file.length as i32).to_le_bytes())?;
+            if compressed {
+                self.writer.write_all(&[file.compression.id() | file.encryption.id() << 4])?;
+                self.writer.write_all(&(file.size as i32).to_le_bytes())?;
+            }
+        }
+        self.writer.write_all(&(table as i64).to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// Packs every file under `directory` into the archive `file`, named by their path
+// relative to `directory` with `/` between directories.
+pub fn pack(directory: impl AsRef<Path>, file: impl AsRef<Path>) -> io::Result<()> {
+    pack_with(directory, file, Compression::None)
+}
+
+// Like `pack`, compressing the files with `compression`.
+pub fn pack_with(directory: impl AsRef<Path>, file: impl AsRef<Path>, compression: Compression) -> io::Result<()> {
+    pack_files(directory.as_ref(), file.as_ref(), compression, None)
+}
+
+// Like `pack_with`, encrypting the files with `key`.
+pub fn pack_encrypted(directory: impl AsRef<Path>, file: impl AsRef<Path>, compression: Compression, key: ArchiveKey) -> io::Result<()> {
+    pack_files(directory.as_ref(), file.as_ref(), compression, Some(key))
+}
+
+fn pack_files(directory: &Path, file: &Path, compression: Compression, key: Option<ArchiveKey>) -> io::Result<()> {
+    let mut names = Vec::new();
+    collect_files(directory, "", &mut names)?;
+    names.sort();
+
+    let mut writer = Writer::new(BufWriter::new(File::create(file)?));
+    writer.set_compression(compression);
+    writer.set_key(key);
+    for name in &names {
+        writer.add_file(name, directory.join(name))?;
+    }
+    writer.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+pub(crate) fn collect_files(directory: &Path, prefix: &str, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string()
+            .map_err(|name| io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is not valid UTF-8", name)))?;
+        let name = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", name), names)?;
+        } else {
+            names.push(name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::entry::{checked_add, read_at};
+use crate::{ArchiveError, ArchiveRead, Normalization};
+
+// A zip file, as ports of VNDS games often ship their assets in them. Directories in
+// it aren't entries.
+pub struct ZipArchive {
+    // Cloned for every read, which shares the file and the parsed central directory.
+    zip: zip::ZipArchive<SharedFile>,
+    // Names of the files by their normalized name.
+    index: HashMap<String, String>,
+    normalization: Normalization,
+}
+
+impl ZipArchive {
+    pub fn load(path: impl AsRef<Path>, case_sensitive: bool) -> Result<Self, ArchiveError> {
+        Self::load_with(path, Normalization::case_sensitive(case_sensitive))
+    }
+
+    pub fn load_with(path: impl AsRef<Path>, normalization: Normalization) -> Result<Self, ArchiveError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let zip = zip::ZipArchive::new(SharedFile { file: Arc::new(file), len, position: 0 })?;
+        let mut index = HashMap::new();
+        for name in zip.file_names().filter(|name| !name.ends_with('/')) {
+            index.entry(normalization.apply(name).into_owned()).or_insert_with(|| name.to_string());
+        }
+        Ok(Self {
+            zip,
+            index,
+            normalization,
+        })
+    }
+
+    fn find(&self, name: &str) -> Option<&str> {
+        self.index.get(self.normalization.apply(name).as_ref()).map(String::as_str)
+    }
+}
+
+impl ArchiveRead for ZipArchive {
+    fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    fn read(&self, name: &str) -> Option<Box<[u8]>> {
+        let name = self.find(name)?;
+        let mut zip = self.zip.clone();
+        let mut file = zip.by_name(name).ok()?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf).ok()?;
+        Some(buf.into_boxed_slice())
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.index.values().cloned().collect()
+    }
+}
+
+// The zip file with a position of its own, so clones of the archive read it without
+// waiting for each other.
+#[derive(Clone)]
+struct SharedFile {
+    file: Arc<File>,
+    len: u64,
+    position: u64,
+}
+
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = read_at(&self.file, buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add(self.len, offset),
+            SeekFrom::Current(offset) => checked_add(self.position, offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+
+use leg_archive::{Archive, ArchiveEntry, ArchiveError, ArchiveKey, Compression, Normalization};
+
+const USAGE: &str = "usage: legarc list <archive> | legarc extract <archive> <directory> [pattern] | \
+    legarc pack <directory> <archive> [--compress=zlib|zstd] | legarc verify <archive>\n\
+    pack encrypts the files and list, extract and verify read them with --key=aes:<hex>|xor:<hex>";
+
+fn load(file: &str, key: Option<&ArchiveKey>) -> Result<Archive, ArchiveError> {
+    match key {
+        Some(key) => leg_archive::load_encrypted(file, Normalization { case: true, ..Normalization::NONE }, key.clone()),
+        None => leg_archive::load(file, false),
+    }
+}
+
+fn list(file: &str, key: Option<&ArchiveKey>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let archive = load(file, key)?;
+    for entry in archive.entries() {
+        let compression = match entry.compression() {
+            Compression::None => "",
+            Compression::Zlib => " zlib",
+            Compression::Zstd => " zstd",
+        };
+        println!("{:>10} {:>10}{:5} {}", entry.size(), entry.stored_size(), compression, entry.name());
+    }
+    Ok(())
+}
+
+fn extract(file: &str, directory: &str, pattern: Option<&str>, key: Option<&ArchiveKey>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let archive = load(file, key)?;
+    let progress = |entry: &ArchiveEntry, done, total| eprintln!("// {}/{} {}", done, total, entry.name());
+    let extracted = match pattern {
+        Some(pattern) => archive.extract_matching(directory, pattern, progress)?,
+        None => archive.extract_all(directory, progress)?,
+    };
+    println!("// Extracted {} files", extracted);
+    Ok(())
+}
+
+// Reads every entry, so broken compressed data is found too.
+fn verify(file: &str, key: Option<&ArchiveKey>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let archive = load(file, key)?;
+    let mut broken = 0;
+    for entry in archive.entries() {
+        let read = archive.open(entry.name())
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::NotFound, "missing")))
+            .and_then(|mut reader| io::copy(&mut reader, &mut io::sink()));
+        if let Err(e) = read {
+            println!("{}: {}", entry.name(), e);
+            broken += 1;
+        }
+    }
+    match broken {
+        0 => {
+            println!("// {} entries are fine", archive.entries().count());
+            Ok(())
+        }
+        _ => Err(format!("{} broken entries", broken).into()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run_command() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_command() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let compression = match args.iter().find_map(|arg| arg.strip_prefix("--compress=")) {
+        None => Compression::None,
+        Some("zlib") => Compression::Zlib,
+        Some("zstd") => Compression::Zstd,
+        Some(method) => return Err(format!("unknown compression: {}", method).into()),
+    };
+    let key = match args.iter().find_map(|arg| arg.strip_prefix("--key=")) {
+        None => None,
+        Some(key) => Some(ArchiveKey::parse(key).ok_or_else(|| format!("invalid key: {}", key))?),
+    };
+
+    let mut positional = args.iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .map(String::as_str);
+
+    match (positional.next(), positional.next(), positional.next()) {
+        (Some("list"), Some(file), None) => list(file, key.as_ref()),
+        (Some("extract"), Some(file), Some(directory)) => extract(file, directory, positional.next(), key.as_ref()),
+        (Some("pack"), Some(directory), Some(file)) => match key {
+            Some(key) => Ok(leg_archive::pack_encrypted(directory, file, compression, key)?),
+            None => Ok(leg_archive::pack_with(directory, file, compression)?),
+        },
+        (Some("verify"), Some(file), None) => verify(file, key.as_ref()),
+        _ => Err(USAGE.into()),
+    }
+}